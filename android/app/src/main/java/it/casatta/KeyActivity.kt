@@ -57,7 +57,9 @@ class KeyActivity : AppCompatActivity() {
 
         hidden_items.layoutManager = LinearLayoutManager(this)
         hidden_items.adapter = hiddenItemsAdapter
-        hiddenItemsAdapter.list.add(DescItem("Xpriv", keyJson.key.xprv))
+        if (keyJson.key.xprv != null) {
+            hiddenItemsAdapter.list.add(DescItem("Xpriv", keyJson.key.xprv))
+        }
 
         if (keyJson.key.mnemonic != null) {
             hiddenItemsAdapter.list.add(DescItem("Mnemonic", keyJson.key.mnemonic))
@@ -27,6 +27,7 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
     private var listOutput = Data.ListOutput(emptyList(), emptyList(), emptyList())
     private val mapper: ObjectMapper = ObjectMapper().registerModule(KotlinModule())
     private var rawHexes: ArrayList<String> = ArrayList()
+    private var urParts: ArrayList<String> = ArrayList()
     private var diceLaunches: ArrayList<Int> = ArrayList()
     private var faces: Int = 0
     private var keyName: String? = null
@@ -352,10 +353,10 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
         dialog.show()
     }
 
-    private fun saveWallet(content: String) {
-        Log.d("LIST", "saveWallet $content")
+    private fun saveWallet(hexContent: String) {
+        Log.d("LIST", "saveWallet $hexContent")
         try {
-            val json = mapper.readValue(content, Data.WalletJson::class.java)
+            val json = Rust().decodeWallet(filesDir.toString(), hexContent)
             Rust().importWallet(filesDir.toString(), json)
             Rust().signWallet(filesDir.toString(), json.name, EncryptionKey.get(applicationContext))
         } catch (e: Exception) {
@@ -384,6 +385,19 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
         }
     }
 
+    private fun onQrComplete(hexResult: String) {
+        when (intent.getIntExtra(C.WHAT, 0)) {
+            IMPORT_WALLET -> {
+                saveWallet(hexResult)
+                finish()
+            }
+            IMPORT_PSBT -> {
+                savePsbt(hexResult, Data.Encoding.HEX)
+                finish()
+            }
+        }
+    }
+
     override fun onActivityResult(
         requestCode: Int,
         resultCode: Int,
@@ -393,33 +407,34 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
         if (result != null) {
             if (result.contents == null) {
                 rawHexes.clear()
+                urParts.clear()
                 C.showMessageDialog(this, R.string.cancelled)
             } else {
                 val hexString = result.rawBytes.toHexString()
-                this.rawHexes.add(hexString)
-                if (hexString.startsWith("3")) {
+                if (result.contents.toUpperCase().startsWith("UR:")) {
+                    this.urParts.add(result.contents)
+                    try {
+                        val merged = Rust().mergeUrs(filesDir.toString(), this.urParts)
+                        urParts.clear()
+                        Log.d("MAIN", "ur complete: ${merged.ur_type}")
+                        onQrComplete(merged.hex)
+                    } catch (e: RustException) {
+                        launchScan("Next")
+                    }
+                } else if (hexString.startsWith("3")) {
+                    this.rawHexes.add(hexString)
                     try {
                         val hexResult = Rust().mergeQrs(filesDir.toString(), this.rawHexes)
                         rawHexes.clear()
                         Log.d("MAIN", "qr complete: $hexResult")
-                        when (intent.getIntExtra(C.WHAT, 0)) {
-                            IMPORT_WALLET -> {
-                                val bytes = decodeHexString(hexResult)
-                                saveWallet(bytes!!.toString(Charsets.UTF_8))
-                                finish()
-                            }
-                            IMPORT_PSBT -> {
-                                savePsbt(hexResult, Data.Encoding.HEX)
-                                finish()
-                            }
-                        }
+                        onQrComplete(hexResult)
                     } catch (e: RustException) {
                         launchScan("Next")
                     }
                 } else {
                     when (intent.getIntExtra(C.WHAT, 0)) {
                         WALLETS -> {
-                            saveWallet(result.contents)
+                            saveWallet(result.contents.toByteArray(Charsets.ISO_8859_1).toHexString())
                             updateWallets()
                         }
                         PSBTS -> {
@@ -474,35 +489,6 @@ class ListActivity : AppCompatActivity() , ItemsAdapter.ItemGesture {
         newIntent.putExtra(C.LAUNCH_NUMBER, launchNumber)
         startActivityForResult(newIntent, DICE_LAUNCH)
     }
-
-    private fun hexToByte(hexString: String): Byte {
-        val firstDigit = toDigit(hexString[0])
-        val secondDigit = toDigit(hexString[1])
-        return ((firstDigit shl 4) + secondDigit).toByte()
-    }
-
-    private fun toDigit(hexChar: Char): Int {
-        val digit: Int = Character.digit(hexChar, 16)
-        if (digit == -1) {
-            throw IllegalArgumentException(
-                    "Invalid Hexadecimal Character: $hexChar")
-        }
-        return digit
-    }
-
-    private fun decodeHexString(hexString: String): ByteArray? {
-        if (hexString.length % 2 == 1) {
-            throw IllegalArgumentException(
-                    "Invalid hexadecimal String supplied.")
-        }
-        val bytes = ByteArray(hexString.length / 2)
-        var i = 0
-        while (i < hexString.length ) {
-            bytes[i / 2] = hexToByte(hexString.substring(i, i + 2))
-            i += 2
-        }
-        return bytes
-    }
 }
 
 data class Item(val name: String, val description: String?, val json: String?, val qrs: List<String>): Serializable
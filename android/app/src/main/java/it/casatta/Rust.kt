@@ -15,7 +15,7 @@ class Rust {
         val strResult = call(json)
         val jsonResult = mapper.readTree(strResult)
         if (jsonResult.has("error")) {
-            throw RustException(jsonResult["error"].asText())
+            throw RustException(jsonResult["error"].asText(), jsonResult.path("code").asText())
         }
         return jsonResult
     }
@@ -47,6 +47,16 @@ class Rust {
         return callMethod("merge_qrs", datadir, Network.TYPE, qrs_bytes).asText()
     }
 
+    fun mergeUrs(datadir: String, parts: List<String>): MergeUrOutput {
+        val json = callMethod("merge_urs", datadir, Network.TYPE, parts)
+        return mapper.convertValue(json, MergeUrOutput::class.java)
+    }
+
+    fun decodeWallet(datadir: String, hex: String): WalletJson {
+        val json = callMethod("decode_wallet", datadir, Network.TYPE, hex)
+        return mapper.convertValue(json, WalletJson::class.java)
+    }
+
     fun importWallet(datadir: String, wallet: WalletJson) {
         callMethod("import_wallet", datadir, Network.TYPE, wallet)
     }
@@ -85,5 +95,5 @@ class Rust {
     }
 }
 
-class RustException(message: String) : Exception(message)
+class RustException(message: String, val code: String = "") : Exception(message)
 
@@ -20,28 +20,46 @@ class Data {
         val key: PrivateMasterKey,
         val private_file: String,
         val public_file: String?,
-        val public_qr_files: List<String>
+        val public_qr_files: List<String>,
+        val public_ur_files: List<String>,
+        val entropy_checks: EntropyChecks? = null
+    )
+
+    data class EntropyChecks(
+        val chi_square: Double,
+        val longest_run: Int,
+        val longest_sequence: Int,
+        val failures: List<String>
     )
 
     data class PrivateMasterKey(
         val name: String,
         val xpub: String,
-        val xprv: String,
+        val xprv: String?,
         val mnemonic: String?,
         val dice: Dice?,
-        val fingerprint: String
+        val fingerprint: String,
+        val passphrase: Boolean = false,
+        val bip85: Bip85Origin? = null
+    )
+
+    data class Bip85Origin(
+        val parent_fingerprint: String,
+        val path: String
     )
 
     data class Dice(
         val launches: String,
         val faces: Int,
-        val value: String
+        val value: String,
+        val xor_random: Boolean = false
     )
 
     data class CreateWalletOutput(
         val wallet_file: String,
         val wallet: WalletJson,
         val qr_files: List<String>,
+        val ur_files: List<String>,
         val signature: WalletSignature?
     )
 
@@ -71,6 +89,7 @@ class Data {
         val psbt: PsbtJson,
         val file: String,
         val qr_files: List<String>,
+        val ur_files: List<String>,
         val unsigned_txid: String
     )
 
@@ -107,7 +126,21 @@ class Data {
         val fee: Fee,
         val info: List<String>,
         val psbt_file: String,
-        val balances: String
+        val balances: String,
+        val metadata: PsbtMetadata = PsbtMetadata()
+    )
+
+    data class PsbtMetadata(
+        val name: String? = null,
+        val creator: String? = null,
+        val created_at: Long? = null,
+        val wallet_name: String? = null,
+        val memo: String? = null
+    )
+
+    data class MergeUrOutput(
+        val ur_type: String,
+        val hex: String
     )
 
     data class GetAddressOutput(
@@ -149,7 +182,8 @@ class Data {
     enum class Encoding(@JsonValue val code: String) {
         BASE64("base64"),
         HEX("hex"),
-        BECH32("bech32")
+        BECH32("bech32"),
+        PASSWORD("password")
     }
 
 }
\ No newline at end of file
@@ -1,7 +1,8 @@
 use firma::bitcoin::Network;
+use firma::common::config::NetworkConfig;
+use firma::common::dispatcher;
 use firma::serde_json::{self, Value};
 use firma::{common, init_logger, offline, Error, Result, StringEncoding, ToJson};
-use std::convert::TryInto;
 use std::io;
 use std::io::Read;
 use structopt::StructOpt;
@@ -24,6 +25,39 @@ struct FirmaOfflineCommands {
     #[structopt(long)]
     pub read_stdin: bool,
 
+    /// Flag to indicate that a BIP39 passphrase is expected in standard input.
+    /// If also `--read-stdin` is used, the passphrase is the first line and the encryption key follows
+    #[structopt(long)]
+    pub read_passphrase: bool,
+
+    /// Flag to indicate that the encryption key read with `--read-stdin` is a password,
+    /// the key used for encryption is derived with Argon2id
+    #[structopt(long)]
+    pub password: bool,
+
+    /// Flag to indicate that the new encryption key of `reencrypt` is expected in standard input,
+    /// as a 32 bytes hex line, after the passphrase line and before the current key if any
+    #[structopt(long)]
+    pub read_new_key: bool,
+
+    /// Flag to indicate that the new encryption key line is a password
+    #[structopt(long)]
+    pub new_password: bool,
+
+    /// Flag to indicate that the datadir encryption key, used for wallets, PSBTs and the journal,
+    /// is expected in standard input as a 32 bytes hex line, after the new key line if any and
+    /// before the key file encryption key
+    #[structopt(long)]
+    pub read_datadir_key: bool,
+
+    /// Flag to indicate that the datadir encryption key line is a password
+    #[structopt(long)]
+    pub datadir_password: bool,
+
+    /// The datadir encryption key read from standard input, used also for the journal
+    #[structopt(skip)]
+    datadir_key: Option<StringEncoding>,
+
     //TODO ContextOffline with network, json, firma_datadir
     #[structopt(subcommand)] // Note that we mark a field as a subcommand
     subcommand: FirmaOfflineSubcommands,
@@ -60,17 +94,144 @@ enum FirmaOfflineSubcommands {
 
     /// Verify a wallet json containing the descriptor to avoid tampering
     VerifyWallet(offline::sign_wallet::VerifyWalletOptions),
+
+    /// Sign a message with a key, or create the BIP322 PSBT proving control of a wallet address
+    SignMessage(offline::message::SignMessageOptions),
+
+    /// Verify a message signature, legacy or BIP322, also from a BIP322 PSBT signed by cosigners
+    VerifyMessage(offline::message::VerifyMessageOptions),
+
+    /// Decode QR codes from image files and import the PSBT or wallet they contain
+    Scan(offline::scan::ScanOptions),
+
+    /// Write a printable page (svg or pdf) with the wallet descriptor or the key backup
+    BackupSheet(offline::backup_sheet::BackupSheetOptions),
+
+    /// Split the seed of a key in SLIP-39 shares, recoverable by any SLIP-39 wallet
+    Split(offline::split::SplitOptions),
+
+    /// Derive a child mnemonic, xprv or entropy from a master key with BIP85
+    Bip85(offline::bip85::Bip85Options),
+
+    /// Change, add or remove the encryption of private key files, the datadir key isn't rotated
+    Reencrypt(offline::reencrypt::ReencryptOptions),
+
+    /// Verify a written backup recomputing the key from the mnemonic or the dice launches
+    VerifyBackup(offline::verify_backup::VerifyBackupOptions),
+
+    /// Import PSBTs, wallets and xpubs exported by the online machine in a directory
+    Import(common::exchange::ImportOptions),
+
+    /// Export PSBTs, wallets and xpubs in a directory to be imported by the online machine
+    Export(common::exchange::ExportOptions),
+
+    /// Show the effective configuration, `config.toml` in the datadir merged with the defaults
+    Config,
+
+    /// Show or verify the hash chained journal of signatures, key creations, decryptions and exports
+    Journal(common::journal::JournalCommand),
 }
 
 fn main() -> Result<()> {
     init_logger();
-    let mut cmd = FirmaOfflineCommands::from_args();
+    let matches = FirmaOfflineCommands::clap().get_matches();
+    let mut cmd = FirmaOfflineCommands::from_clap(&matches);
+
+    let config = match common::config::Config::load(&cmd.firma_datadir) {
+        Ok(config) => config,
+        Err(e) => return exit_with(Err(e)),
+    };
+    if matches.occurrences_of("network") == 0 {
+        if let Some(network) = config.network {
+            cmd.network = network;
+        }
+    }
+
+    let mut buffer = vec![];
+    if cmd.read_stdin || cmd.read_passphrase || cmd.read_new_key || cmd.read_datadir_key {
+        io::stdin().read_to_end(&mut buffer)?;
+    }
+
+    if cmd.read_passphrase {
+        // read passphrase from the first line of stdin and initialize passphrase field
+        let passphrase = Some(take_line(&mut buffer)?);
+        match &mut cmd.subcommand {
+            Random(opt) => opt.passphrase = passphrase,
+            Dice(opt) => opt.passphrase = passphrase,
+            Restore(opt) => opt.passphrase = passphrase,
+            Sign(opt) => opt.passphrase = passphrase,
+            SignMessage(opt) => opt.passphrase = passphrase,
+            SignWallet(opt) => opt.passphrase = passphrase,
+            Split(opt) => opt.passphrase = passphrase,
+            DeriveKey(opt) => opt.passphrase = passphrase,
+            Bip85(opt) => opt.passphrase = passphrase,
+            VerifyBackup(opt) => opt.passphrase = passphrase,
+            _ => {
+                return exit_with(Err(Error::Generic(
+                    "Subcommand doesn't need passphrase".to_string(),
+                )));
+            }
+        }
+    }
+
+    if cmd.read_new_key {
+        // read the new encryption key from the next line of stdin
+        let line = take_line(&mut buffer)?;
+        let new_key = if cmd.new_password {
+            StringEncoding::Password(line)
+        } else {
+            StringEncoding::Hex(line)
+        };
+        match &mut cmd.subcommand {
+            Reencrypt(opt) => opt.new_encryption_key = Some(new_key),
+            _ => {
+                return exit_with(Err(Error::Generic(
+                    "Subcommand doesn't need a new key".to_string(),
+                )));
+            }
+        }
+    }
+
+    if cmd.read_datadir_key {
+        // read the datadir encryption key from the next line of stdin
+        let line = take_line(&mut buffer)?;
+        let datadir_key = Some(if cmd.datadir_password {
+            StringEncoding::Password(line)
+        } else {
+            StringEncoding::Hex(line)
+        });
+        cmd.datadir_key = datadir_key.clone();
+        match &mut cmd.subcommand {
+            Sign(opt) => opt.datadir_encryption_key = datadir_key,
+            SignWallet(opt) => opt.datadir_encryption_key = datadir_key,
+            SignMessage(opt) => opt.datadir_encryption_key = datadir_key,
+            BackupSheet(opt) => opt.datadir_encryption_key = datadir_key,
+            Print(opt) => opt.encryption_key = datadir_key,
+            VerifyWallet(opt) => opt.encryption_key = datadir_key,
+            VerifyMessage(opt) => opt.encryption_key = datadir_key,
+            Scan(opt) => opt.encryption_key = datadir_key,
+            Import(opt) => opt.encryption_key = datadir_key,
+            Export(opt) => opt.encryption_key = datadir_key,
+            List(opt) => opt.encryption_keys.extend(datadir_key),
+            // the key is used only for the journal
+            Random(_) | Dice(_) | Restore(_) | DeriveKey(_) | Decrypt(_) | Bip85(_)
+            | Journal(_) => (),
+            _ => {
+                return exit_with(Err(Error::Generic(
+                    "Subcommand doesn't need a datadir key".to_string(),
+                )));
+            }
+        }
+    }
 
     if cmd.read_stdin {
         // read encryption key from stdin and initialize encryption_key field
-        let mut buffer = vec![];
-        io::stdin().read_to_end(&mut buffer)?;
-        let encoded = StringEncoding::new_base64(&buffer);
+        let encoded = if cmd.password {
+            let password = std::str::from_utf8(&buffer)?.trim_end_matches(&['\r', '\n'][..]);
+            StringEncoding::Password(password.to_string())
+        } else {
+            StringEncoding::new_base64(&buffer)
+        };
         match &mut cmd.subcommand {
             Random(opt) => opt.encryption_key = Some(encoded),
             Sign(opt) => opt.encryption_key = Some(encoded),
@@ -79,38 +240,115 @@ fn main() -> Result<()> {
             Restore(opt) => opt.encryption_key = Some(encoded),
             DeriveKey(opt) => opt.encryption_key = Some(encoded),
             SignWallet(opt) => opt.encryption_key = Some(encoded),
-            List(opt) => opt.encryption_keys = vec![encoded],
-            _ => {
-                let err = Error::Generic("Subcommand doesn't need encryption key".to_string());
-                println!("{}", serde_json::to_string_pretty(&err.to_json())?);
-                return Ok(());
-            }
+            BackupSheet(opt) => opt.encryption_key = Some(encoded),
+            Split(opt) => opt.encryption_key = Some(encoded),
+            Bip85(opt) => opt.encryption_key = Some(encoded),
+            Reencrypt(opt) => opt.encryption_key = Some(encoded),
+            Print(opt) => opt.encryption_key = Some(encoded),
+            VerifyWallet(opt) => opt.encryption_key = Some(encoded),
+            SignMessage(opt) => opt.encryption_key = Some(encoded),
+            VerifyMessage(opt) => opt.encryption_key = Some(encoded),
+            Scan(opt) => opt.encryption_key = Some(encoded),
+            VerifyBackup(opt) => opt.encryption_key = Some(encoded),
+            Import(opt) => opt.encryption_key = Some(encoded),
+            Export(opt) => opt.encryption_key = Some(encoded),
+            List(opt) => opt.encryption_keys.push(encoded),
+            Config | Journal(_) => (),
         }
     }
 
-    let value = match launch_subcommand(&cmd) {
-        Ok(value) => value,
-        Err(e) => e.to_json(),
+    // options not given in the command line take the defaults of the config
+    let given = |name: &str| match matches.subcommand().1 {
+        Some(sub_matches) => sub_matches.occurrences_of(name.replace('_', "-")) > 0,
+        None => false,
+    };
+    let config = config.for_network(cmd.network);
+    exit_with(launch_subcommand(&cmd, config, given))
+}
+
+/// Print the json result and exit with non-zero status if it's an error
+fn exit_with(result: Result<Value>) -> Result<()> {
+    let (value, status) = match result {
+        Ok(value) => (value, 0),
+        Err(e) => (e.to_json(), 1),
     };
 
     println!("{}", serde_json::to_string_pretty(&value)?);
+    std::process::exit(status)
+}
 
-    Ok(())
+fn launch_subcommand<F: Fn(&str) -> bool>(
+    cmd: &FirmaOfflineCommands,
+    config: &NetworkConfig,
+    given: F,
+) -> Result<Value> {
+    let (method, mut args) = method_and_args(&cmd.subcommand)?;
+    config.apply_to_args(&mut args, given);
+    dispatcher::dispatch(
+        method,
+        &cmd.firma_datadir,
+        cmd.network,
+        args,
+        cmd.datadir_key.as_ref(),
+    )
 }
 
-fn launch_subcommand(cmd: &FirmaOfflineCommands) -> Result<Value> {
-    let net = cmd.network;
-    let datadir = &cmd.firma_datadir;
-    match &cmd.subcommand {
-        Dice(opt) => offline::dice::roll(datadir, net, &opt)?.try_into(),
-        Sign(opt) => offline::sign::start(&opt, net)?.try_into(),
-        Random(opt) => offline::random::create_key(datadir, net, &opt)?.try_into(),
-        Print(opt) => offline::print::start(datadir, net, &opt)?.try_into(),
-        Restore(opt) => offline::restore::start(datadir, net, &opt)?.try_into(),
-        DeriveKey(opt) => offline::derive_key::start(datadir, net, &opt)?.try_into(),
-        List(opt) => common::list::list(datadir, net, &opt)?.try_into(),
-        SignWallet(opt) => offline::sign_wallet::sign_wallet(datadir, net, &opt)?.try_into(),
-        VerifyWallet(opt) => offline::sign_wallet::verify_wallet(datadir, net, &opt)?.try_into(),
-        Decrypt(opt) => offline::decrypt::decrypt::<Value>(&opt),
+/// Name of the dispatcher method and its arguments for the given subcommand
+fn method_and_args(subcommand: &FirmaOfflineSubcommands) -> Result<(&'static str, Value)> {
+    Ok(match subcommand {
+        Dice(opt) => ("dice", serde_json::to_value(opt)?),
+        Sign(opt) => ("sign", serde_json::to_value(opt)?),
+        Random(opt) => ("random", serde_json::to_value(opt)?),
+        Print(opt) => ("print", serde_json::to_value(opt)?),
+        Restore(opt) => ("restore", serde_json::to_value(opt)?),
+        DeriveKey(opt) => ("derive_key", serde_json::to_value(opt)?),
+        List(opt) => ("list", serde_json::to_value(opt)?),
+        SignWallet(opt) => ("sign_wallet", serde_json::to_value(opt)?),
+        VerifyWallet(opt) => ("verify_wallet", serde_json::to_value(opt)?),
+        SignMessage(opt) => ("sign_message", serde_json::to_value(opt)?),
+        VerifyMessage(opt) => ("verify_message", serde_json::to_value(opt)?),
+        Decrypt(opt) => ("decrypt", serde_json::to_value(opt)?),
+        Scan(opt) => ("scan", serde_json::to_value(opt)?),
+        BackupSheet(opt) => ("backup_sheet", serde_json::to_value(opt)?),
+        Split(opt) => ("split", serde_json::to_value(opt)?),
+        Bip85(opt) => ("bip85", serde_json::to_value(opt)?),
+        Reencrypt(opt) => ("reencrypt", serde_json::to_value(opt)?),
+        VerifyBackup(opt) => ("verify_backup", serde_json::to_value(opt)?),
+        Import(opt) => ("import", serde_json::to_value(opt)?),
+        Export(opt) => ("export", serde_json::to_value(opt)?),
+        Config => ("config", Value::Null),
+        Journal(cmd) => ("journal", serde_json::to_value(cmd)?),
+    })
+}
+
+/// Remove the first line from `buffer` and return it without line terminators
+fn take_line(buffer: &mut Vec<u8>) -> Result<String> {
+    let end = buffer
+        .iter()
+        .position(|b| *b == b'\n')
+        .unwrap_or(buffer.len());
+    let line: Vec<u8> = buffer.drain(..end).collect();
+    if !buffer.is_empty() {
+        buffer.remove(0); // the new line
+    }
+    Ok(std::str::from_utf8(&line)?
+        .trim_end_matches('\r')
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_subcommand_is_dispatched() {
+        let app = FirmaOfflineCommands::clap();
+        for subcommand in app.p.subcommands.iter() {
+            let name = subcommand.p.meta.name.replace('-', "_");
+            if name == "help" {
+                continue;
+            }
+            assert!(dispatcher::METHODS.contains(&name.as_str()), "{}", name);
+        }
     }
 }
@@ -1,9 +1,8 @@
-use firma::bitcoin::Network;
-use firma::bitcoincore_rpc::{Auth, RpcApi};
 use firma::log::debug;
 use firma::serde_json::Value;
 use firma::*;
 use std::convert::TryInto;
+use std::io::{self, Read};
 use structopt::StructOpt;
 use FirmaOnlineSubcommands::*;
 
@@ -17,6 +16,20 @@ struct FirmaOnlineCommands {
     #[structopt(flatten)]
     context: Context,
 
+    /// Flag to indicate that the datadir encryption key is expected in standard input
+    #[structopt(long)]
+    read_stdin: bool,
+
+    /// Flag to indicate that the encryption key read with `--read-stdin` is a password
+    #[structopt(long)]
+    password: bool,
+
+    /// Flag to indicate that the node rpc password is expected in standard input, if also
+    /// `--read-stdin` is used the rpc password is the first line and the encryption key follows.
+    /// Otherwise the FIRMA_RPC_PASSWORD environment variable is used, if set
+    #[structopt(long)]
+    read_rpc_password: bool,
+
     #[structopt(subcommand)]
     subcommand: FirmaOnlineSubcommands,
 }
@@ -43,64 +56,166 @@ enum FirmaOnlineSubcommands {
 
     /// View wallet coins
     ListCoins,
+
+    /// Create an unspendable PSBT proving control of the wallet coins, to be signed offline
+    ProofOfReserves(firma::online::proof_of_reserves::ProofOfReservesOptions),
+
+    /// Verify a signed proof of reserves against the node or a UTXO set snapshot
+    VerifyReserves(firma::offline::reserves::VerifyReservesOptions),
+
+    /// Export PSBTs, wallets and xpubs in a directory to be imported by the offline machine
+    Export(firma::common::exchange::ExportOptions),
+
+    /// Import PSBTs, wallets and xpubs exported by the offline machine in a directory
+    Import(firma::common::exchange::ImportOptions),
+
+    /// Serve the online commands as JSON-RPC over HTTP until killed
+    Serve(firma::online::serve::ServeOptions),
+
+    /// Show the effective configuration, `config.toml` in the datadir merged with the defaults
+    Config,
 }
 
 fn main() -> Result<()> {
-    let output = match start() {
-        Ok(output) => output,
-        Err(e) => e.to_json(),
+    let (output, status) = match start() {
+        Ok(output) => (output, 0),
+        Err(e) => (e.to_json(), 1),
     };
 
     println!("{}", serde_json::to_string_pretty(&output)?);
-    Ok(())
+    std::process::exit(status)
 }
 
 fn start() -> Result<Value> {
     init_logger();
     debug!("firma-online start");
-    let cmd = FirmaOnlineCommands::from_args();
+    let matches = FirmaOnlineCommands::clap().get_matches();
+    let mut cmd = FirmaOnlineCommands::from_clap(&matches);
+
+    // command line flags win over the config
+    let config = common::config::Config::load(&cmd.context.firma_datadir)?;
+    if matches.occurrences_of("network") == 0 {
+        if let Some(network) = config.network {
+            cmd.context.network = network;
+        }
+    }
+    let config = config.for_network(cmd.context.network).clone();
+    if cmd.context.wallet_name.is_empty() {
+        if let Some(wallet_name) = config.wallet_name.as_ref() {
+            cmd.context.wallet_name = wallet_name.clone();
+        }
+    }
+    let given = |name: &str| match matches.subcommand().1 {
+        Some(sub_matches) => sub_matches.occurrences_of(name.replace('_', "-")) > 0,
+        None => false,
+    };
+    match cmd.subcommand {
+        CreateWallet(ref mut opt) => *opt = config.apply(opt, given)?,
+        CreateTx(ref mut opt) => *opt = config.apply(opt, given)?,
+        ProofOfReserves(ref mut opt) => *opt = config.apply(opt, given)?,
+        _ => (),
+    }
 
-    let daemon_opts = match &cmd.subcommand {
-        CreateWallet(ref opt) => opt.daemon_opts.clone(),
-        _ => cmd.context.load_wallet_index_daemon()?.2,
+    let mut buffer = vec![];
+    if cmd.read_stdin || cmd.read_rpc_password {
+        io::stdin().read_to_end(&mut buffer)?;
+    }
+    let rpc_password = if cmd.read_rpc_password {
+        Some(take_line(&mut buffer)?)
+    } else {
+        std::env::var("FIRMA_RPC_PASSWORD").ok()
     };
 
-    let url_with_wallet = format!("{}/wallet/{}", daemon_opts.url, cmd.context.wallet_name);
-    let wallet = Wallet::new(
-        url_with_wallet,
-        Auth::CookieFile(daemon_opts.cookie_file.clone()),
-        cmd.context.clone(),
-    )?;
+    if cmd.read_stdin {
+        cmd.context.encryption_key = Some(if cmd.password {
+            let password = std::str::from_utf8(&buffer)?.trim_end_matches(&['\r', '\n'][..]);
+            StringEncoding::Password(password.to_string())
+        } else {
+            StringEncoding::new_base64(&buffer)
+        });
+    }
 
-    if let CreateWallet(_) = cmd.subcommand {
-        // do nothing, I need the else branch (!matches!() require too recent rust version)
-    } else {
-        wallet.load_if_unloaded(&cmd.context.wallet_name)?;
+    // exchange with the offline machine doesn't need the node
+    let datadir = &cmd.context.firma_datadir;
+    let network = cmd.context.network;
+    match cmd.subcommand {
+        Export(ref mut opt) => {
+            opt.encryption_key = cmd.context.encryption_key.clone();
+            return common::exchange::export_journaled(datadir, network, opt)?.try_into();
+        }
+        Import(ref mut opt) => {
+            opt.encryption_key = cmd.context.encryption_key.clone();
+            return common::exchange::import(datadir, network, opt)?.try_into();
+        }
+        VerifyReserves(ref mut opt) => {
+            opt.encryption_key = cmd.context.encryption_key.clone();
+            if let Some(utxos_file) = opt.utxos_file.clone() {
+                return offline::reserves::verify_reserves_snapshot(opt, &utxos_file)?.try_into();
+            }
+        }
+        Serve(ref mut opt) => {
+            opt.rpc_password = rpc_password;
+            online::serve::serve(cmd.context.clone(), opt)?;
+            return Ok(Value::Null);
+        }
+        Config => {
+            let mut output = common::config::show(datadir, network)?;
+            if !cmd.context.wallet_name.is_empty() {
+                output.config.wallet_name = Some(cmd.context.wallet_name.clone());
+            }
+            return output.try_into();
+        }
+        _ => (),
     }
 
-    let result = wallet.client.get_blockchain_info()?;
+    if cmd.context.wallet_name.is_empty() {
+        return Err("wallet name is required, use --wallet-name".into());
+    }
 
-    let node_network = match result.chain.as_ref() {
-        "main" => Network::Bitcoin,
-        "test" => Network::Testnet,
-        "regtest" => Network::Regtest,
-        _ => return Err("Unrecognized network".into()),
+    let mut daemon_opts = match &cmd.subcommand {
+        CreateWallet(ref opt) => opt.daemon_opts.clone(),
+        _ => match config.daemon_opts() {
+            Some(daemon_opts) => daemon_opts,
+            None => cmd.context.load_wallet_index_daemon()?.2,
+        },
     };
-    if node_network != cmd.context.network {
-        return Err(format!(
-            "network of the bitcoin node {} does not match used one {}",
-            node_network, cmd.context.network
-        )
-        .into());
+    if daemon_opts.rpc_password.is_none() {
+        daemon_opts.rpc_password = rpc_password;
     }
 
+    let wallet = Wallet::open(&daemon_opts, cmd.context.clone())?;
+
+    if !matches!(cmd.subcommand, CreateWallet(_)) {
+        wallet.load_if_unloaded(&cmd.context.wallet_name)?;
+    }
+
+    let blocks = wallet.check_network()?;
+
     match cmd.subcommand {
-        CreateWallet(ref opt) => wallet.create(&daemon_opts, opt, result.blocks)?.try_into(),
+        CreateWallet(ref opt) => wallet.create(&daemon_opts, opt, blocks)?.try_into(),
         GetAddress(ref opt) => wallet.get_address(opt)?.try_into(),
         CreateTx(ref opt) => wallet.create_tx(opt)?.try_into(),
         SendTx(ref opt) => wallet.send_tx(opt)?.try_into(),
         Balance => wallet.balance()?.try_into(),
         Rescan(ref opt) => Ok(wallet.rescan(opt)?),
         ListCoins => wallet.list_coins()?.try_into(),
+        ProofOfReserves(ref opt) => wallet.proof_of_reserves(opt)?.try_into(),
+        VerifyReserves(ref opt) => wallet.verify_reserves(opt)?.try_into(),
+        Export(_) | Import(_) | Serve(_) | Config => unreachable!(),
+    }
+}
+
+/// Remove the first line from `buffer` and return it without line terminators
+fn take_line(buffer: &mut Vec<u8>) -> Result<String> {
+    let end = buffer
+        .iter()
+        .position(|b| *b == b'\n')
+        .unwrap_or(buffer.len());
+    let line: Vec<u8> = buffer.drain(..end).collect();
+    if !buffer.is_empty() {
+        buffer.remove(0); // the new line
     }
+    Ok(std::str::from_utf8(&line)?
+        .trim_end_matches('\r')
+        .to_string())
 }
@@ -39,7 +39,7 @@ fn integration_test() {
         Error::DiceValueErr(0, 20).to_string()
     );
     let r3 = firma_2of2
-        .offline_restore("r4", "xprv", &r1.key.xprv.to_string())
+        .offline_restore("r4", "xprv", &r1.key.xprv.unwrap().to_string())
         .unwrap();
     assert_eq!(r3.key.xpub, r1.key.xpub);
     let xpubs = vec![
@@ -330,11 +330,14 @@ impl FirmaCommand {
         if !output.status.success() {
             println!("{}", std::str::from_utf8(&output.stderr)?);
         }
-        assert!(
+        let value: Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(
             output.status.success(),
-            format!("online subcmd:{} args:{:?}", subcmd, args)
+            value.get("error").is_none(),
+            "online subcmd:{} args:{:?}",
+            subcmd,
+            args
         );
-        let value: Value = serde_json::from_slice(&output.stdout).unwrap();
         println!("{}", to_string_pretty(&value).unwrap());
         Ok(value)
     }
@@ -443,15 +446,15 @@ impl FirmaCommand {
             println!("{}", std::str::from_utf8(&output.stderr)?);
         }
 
-        assert!(
+        let value: Value = serde_json::from_slice(&output.stdout)?;
+        assert_eq!(
             output.status.success(),
-            format!(
-                "offline subcmd:{} args:{:?} encryption_key:{:?}",
-                subcmd, args, encryption_key
-            )
+            value.get("error").is_none(),
+            "offline subcmd:{} args:{:?} encryption_key:{:?}",
+            subcmd,
+            args,
+            encryption_key
         );
-
-        let value: Value = serde_json::from_slice(&output.stdout)?;
         println!("{:?}", to_string_pretty(&value));
 
         Ok(value)
@@ -486,7 +489,8 @@ impl FirmaCommand {
         faces: u32,
     ) -> Result<MasterKeyOutput> {
         let faces = format!("{}", faces);
-        let mut args = vec!["--key-name", key_name, "--faces", &faces];
+        // launches in tests are not random
+        let mut args = vec!["--key-name", key_name, "--faces", &faces, "--force"];
         let launches: Vec<String> = launches.iter().map(|e| format!("{}", e)).collect();
         for launch in launches.iter() {
             args.push("-l");
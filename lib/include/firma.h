@@ -0,0 +1,31 @@
+#ifndef FIRMA_H
+#define FIRMA_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Version of the request schema, increased when a method changes its arguments incompatibly
+#define FIRMA_SCHEMA_VERSION 1
+
+// Call the firma method specified in the json `request`, for example
+// `{"datadir":"/tmp/firma","network":"testnet","method":"random","args":{"key_name":"a"}}`.
+// Returns a json string with the result or with an `error` field, also if the library panics.
+//
+// # Safety
+//
+// `request` must be a valid nul terminated string, the returned string must be released with
+// `firma_free`
+char *firma_call(const char *request);
+
+// Release a string returned by `firma_call`
+//
+// # Safety
+//
+// `s` must be returned by `firma_call` and not already released
+void firma_free(char *s);
+
+#endif /* FIRMA_H */
@@ -0,0 +1,193 @@
+use crate::*;
+use bitcoin::util::address::Payload;
+use bitcoin::util::amount::Denomination;
+use bitcoin::{Address, Amount};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+const SCHEME: &str = "bitcoin:";
+
+/// BIP21 payment URI, `bitcoin:<address>?amount=<btc>&label=<label>&message=<message>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentUri {
+    pub address: Address,
+    pub amount: Option<Amount>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+}
+
+impl PaymentUri {
+    /// true if the URI has no parameters and could be encoded as the bare address
+    pub fn is_bare(&self) -> bool {
+        self.amount.is_none() && self.label.is_none() && self.message.is_none()
+    }
+
+    /// String to encode in a QR code, a bare URI is encoded as the address only, uppercased if
+    /// segwit to use the denser alphanumeric mode
+    pub fn qr_string(&self) -> String {
+        match (&self.address.payload, self.is_bare()) {
+            (Payload::WitnessProgram { .. }, true) => self.address.to_string().to_uppercase(),
+            (_, true) => self.address.to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PaymentUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, self.address)?;
+        let mut params = vec![];
+        if let Some(amount) = self.amount {
+            let btc = amount.to_string_in(Denomination::Bitcoin);
+            let btc = btc.trim_end_matches('0').trim_end_matches('.');
+            params.push(format!("amount={}", btc));
+        }
+        if let Some(label) = self.label.as_ref() {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+        if let Some(message) = self.message.as_ref() {
+            params.push(format!("message={}", percent_encode(message)));
+        }
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PaymentUri {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let scheme = s.get(..SCHEME.len()).unwrap_or_default();
+        if !scheme.eq_ignore_ascii_case(SCHEME) {
+            return Err(io_err("payment uri must start with bitcoin:"));
+        }
+        let mut parts = s[SCHEME.len()..].splitn(2, '?');
+        let address = parts.next().unwrap_or_default();
+        let address = Address::from_str(address)
+            .map_err(|_| io_err("Address in payment uri is not valid"))?;
+        let mut uri = PaymentUri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+        };
+        for param in parts.next().unwrap_or_default().split('&') {
+            if param.is_empty() {
+                continue;
+            }
+            let mut key_value = param.splitn(2, '=');
+            let key = key_value.next().unwrap_or_default();
+            let value = key_value.next().unwrap_or_default();
+            match key {
+                "amount" => {
+                    let amount = Amount::from_str_in(value, Denomination::Bitcoin)
+                        .map_err(|_| io_err("Amount in payment uri is invalid, should be BTC"))?;
+                    uri.amount = Some(amount);
+                }
+                "label" => uri.label = Some(percent_decode(value)?),
+                "message" => uri.message = Some(percent_decode(value)?),
+                _ if key.starts_with("req-") => {
+                    return Err(io_err(&format!("payment uri requires unknown {}", key)))
+                }
+                _ => (),
+            }
+        }
+        Ok(uri)
+    }
+}
+
+/// Serialized as the URI string
+impl Serialize for PaymentUri {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PaymentUri {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        PaymentUri::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> std::result::Result<String, std::io::Error> {
+    let invalid = || io_err("invalid percent encoding in payment uri");
+    let bytes = s.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::bip21::PaymentUri;
+    use bitcoin::{Address, Amount};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_payment_uri() {
+        let address =
+            Address::from_str("tb1q5nrregep899vnvaa5vdpxcwg8794jqy38nu304kl4d7wm4e92yeqz4jfmk")
+                .unwrap();
+        let uri = PaymentUri {
+            address: address.clone(),
+            amount: Some(Amount::from_sat(150_000)),
+            label: Some("Luke Jr".to_string()),
+            message: Some("Donation for project xyz & co".to_string()),
+        };
+        let expected = "bitcoin:tb1q5nrregep899vnvaa5vdpxcwg8794jqy38nu304kl4d7wm4e92yeqz4jfmk?amount=0.0015&label=Luke%20Jr&message=Donation%20for%20project%20xyz%20%26%20co";
+        assert_eq!(uri.to_string(), expected);
+        assert_eq!(PaymentUri::from_str(expected).unwrap(), uri);
+        assert_eq!(uri.qr_string(), expected);
+
+        let bare = PaymentUri::from_str(&format!("BITCOIN:{}", address)).unwrap();
+        assert!(bare.is_bare());
+        assert_eq!(bare.address, address);
+        assert_eq!(bare.qr_string(), address.to_string().to_uppercase());
+        let uppercase = PaymentUri::from_str(&bare.to_string().to_uppercase()).unwrap();
+        assert_eq!(uppercase, bare);
+
+        let amount = PaymentUri::from_str("bitcoin:mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn?amount=1")
+            .unwrap()
+            .amount;
+        assert_eq!(amount, Some(Amount::from_sat(100_000_000)));
+        let ignored = "bitcoin:mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn?somethingyoudontunderstand=50";
+        assert!(PaymentUri::from_str(ignored).is_ok());
+
+        assert!(PaymentUri::from_str(&address.to_string()).is_err());
+        assert!(PaymentUri::from_str("bitcoin:notanaddress").is_err());
+        assert!(
+            PaymentUri::from_str("bitcoin:mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn?amount=x").is_err()
+        );
+        assert!(
+            PaymentUri::from_str("bitcoin:mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn?req-x=1").is_err()
+        );
+        assert!(
+            PaymentUri::from_str("bitcoin:mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn?label=%2").is_err()
+        );
+    }
+}
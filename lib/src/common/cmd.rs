@@ -1,7 +1,7 @@
 use crate::*;
+use bitcoincore_rpc::Auth;
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
-use std::fs;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -13,7 +13,36 @@ pub struct DaemonOpts {
 
     /// Bitcoin node cookie file
     #[structopt(long)]
-    pub cookie_file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie_file: Option<PathBuf>,
+
+    /// Bitcoin node rpc user, alternative to the cookie file, the password is read from standard
+    /// input with `--read-rpc-password` or from the FIRMA_RPC_PASSWORD environment variable
+    #[structopt(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_user: Option<String>,
+
+    /// Bitcoin node rpc password, saved only if the datadir is encrypted
+    /// in CLI it is populated from standard input or environment
+    #[structopt(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_password: Option<String>,
+
+    /// Timeout in seconds of the calls to the bitcoin node, no timeout if missing
+    #[structopt(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+impl DaemonOpts {
+    pub fn auth(&self) -> Result<Auth> {
+        match (&self.rpc_user, &self.rpc_password, &self.cookie_file) {
+            (Some(user), Some(password), _) => Ok(Auth::UserPass(user.clone(), password.clone())),
+            (Some(_), None, _) => Err(Error::MissingRpcPassword),
+            (None, _, Some(cookie_file)) => Ok(Auth::CookieFile(cookie_file.clone())),
+            (None, _, None) => Err(Error::MissingDaemonOpts),
+        }
+    }
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -22,13 +51,18 @@ pub struct Context {
     #[structopt(short, long, default_value = "testnet")]
     pub network: bitcoin::Network,
 
-    /// Name of the wallet
-    #[structopt(short, long)]
+    /// Name of the wallet, not needed by commands not related to a single wallet
+    #[structopt(short, long, default_value = "")]
     pub wallet_name: String,
 
     /// Directory where wallet info are saved
     #[structopt(short, long, default_value = "~/.firma/")]
     pub firma_datadir: String,
+
+    /// Optional datadir encryption key, when present wallet files are saved encrypted and QR
+    /// codes are not saved. In CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
 }
 
 impl Context {
@@ -44,11 +78,21 @@ impl Context {
         self.path_for_qr(Kind::Wallet, Some(self.wallet_name.to_string()))
     }
 
+    pub fn path_for_wallet_ur(&self) -> Result<PathBuf> {
+        self.path_builder_for(Kind::Wallet, Some(self.wallet_name.to_string()))
+            .file("ur")
+    }
+
     pub fn filename_for_wallet(&self, name: &str) -> Result<PathBuf> {
         self.path_builder_for(Kind::Wallet, Some(self.wallet_name.to_string()))
             .file(name)
     }
 
+    /// QR codes can't be encrypted, they are saved only if the datadir is not encrypted
+    pub fn qr_allowed(&self) -> bool {
+        self.encryption_key.is_none()
+    }
+
     pub fn psbts_dir(&self) -> Result<PathBuf> {
         self.path_builder_for(Kind::PSBT, None).type_path()
     }
@@ -59,7 +103,7 @@ impl Context {
             return Err(Error::FileExist(path));
         }
         info!("Saving wallet data in {:?}", &path);
-        fs::write(&path, serde_json::to_string_pretty(wallet)?)?;
+        save_maybe_encrypted(wallet, &path, self.encryption_key.as_ref())?;
         Ok(path)
     }
 
@@ -69,21 +113,26 @@ impl Context {
             return Err(Error::FileExist(path));
         }
         info!("Saving wallet signature data in {:?}", &path);
-        fs::write(&path, serde_json::to_string_pretty(wallet)?)?;
+        save_maybe_encrypted(wallet, &path, self.encryption_key.as_ref())?;
         Ok(path)
     }
 
     pub fn save_index(&self, indexes: &WalletIndexes) -> Result<()> {
         let path = self.filename_for_wallet("indexes.json")?;
         info!("Saving index data in {:?}", path);
-        fs::write(path, serde_json::to_string_pretty(indexes)?)?;
+        save_maybe_encrypted(indexes, &path, self.encryption_key.as_ref())?;
         Ok(())
     }
 
     pub fn save_daemon_opts(&self, daemon_opts: &DaemonOpts) -> Result<()> {
         let path = self.filename_for_wallet("daemon_opts.json")?;
         info!("Saving daemon_opts data in {:?}", path);
-        fs::write(path, serde_json::to_string_pretty(daemon_opts)?)?;
+        let mut daemon_opts = daemon_opts.clone();
+        if self.encryption_key.is_none() {
+            // the rpc password is never saved in plaintext
+            daemon_opts.rpc_password = None;
+        }
+        save_maybe_encrypted(&daemon_opts, &path, self.encryption_key.as_ref())?;
         Ok(())
     }
 
@@ -99,19 +148,57 @@ impl Context {
     pub fn load_wallet_index_daemon(&self) -> Result<(WalletJson, WalletIndexes, DaemonOpts)> {
         let wallet_path = self.filename_for_wallet("descriptor.json")?;
         debug!("load wallet: {:?}", wallet_path);
-        let wallet = read_wallet(&wallet_path)
+        let wallet = read_wallet(&wallet_path, self.encryption_key.as_ref())
             .map_err(|e| Error::FileNotFoundOrCorrupt(wallet_path.clone(), e.to_string()))?;
 
         let indexes_path = self.filename_for_wallet("indexes.json")?;
         debug!("load indexes: {:?}", indexes_path);
-        let indexes = read_indexes(&indexes_path)
+        let indexes = read_indexes(&indexes_path, self.encryption_key.as_ref())
             .map_err(|e| Error::FileNotFoundOrCorrupt(wallet_path.clone(), e.to_string()))?;
 
         let daemon_opts_path = self.filename_for_wallet("daemon_opts.json")?;
         debug!("load daemon_opts: {:?}", daemon_opts_path);
-        let daemon_opts = read_daemon_opts(&daemon_opts_path)
+        let daemon_opts = read_daemon_opts(&daemon_opts_path, self.encryption_key.as_ref())
             .map_err(|e| Error::FileNotFoundOrCorrupt(daemon_opts_path.clone(), e.to_string()))?;
 
         Ok((wallet, indexes, daemon_opts))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use bitcoin::Network;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rpc_password_not_saved_in_plaintext() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut context = Context {
+            network: Network::Regtest,
+            wallet_name: "w".to_string(),
+            firma_datadir: temp_dir.path().to_str().unwrap().to_string(),
+            encryption_key: None,
+        };
+        let daemon_opts = DaemonOpts {
+            url: "http://node:18443".to_string(),
+            cookie_file: None,
+            rpc_user: Some("firma".to_string()),
+            rpc_password: Some("secret".to_string()),
+            timeout: Some(10),
+        };
+        let path = context.filename_for_wallet("daemon_opts.json").unwrap();
+
+        context.save_daemon_opts(&daemon_opts).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+        let saved = read_daemon_opts(&path, None).unwrap();
+        assert_eq!(saved.rpc_password, None);
+        assert!(matches!(saved.auth(), Err(Error::MissingRpcPassword)));
+
+        context.encryption_key = Some(StringEncoding::new_base64(&[0u8; 32]));
+        context.save_daemon_opts(&daemon_opts).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("secret"));
+        let saved = read_daemon_opts(&path, context.encryption_key.as_ref()).unwrap();
+        assert_eq!(saved, daemon_opts);
+    }
+}
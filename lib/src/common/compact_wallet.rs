@@ -0,0 +1,199 @@
+use crate::offline::descriptor::extract_xpubs;
+use crate::*;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::util::base58;
+use bitcoin::util::bip32::{ExtendedPubKey, Fingerprint};
+use minicbor::data::Type;
+use minicbor::{Decoder, Encoder};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Version of the compact wallet encoding, first element of the CBOR array
+const COMPACT_VERSION: u8 = 1;
+
+/// First byte of the compact encoding, the CBOR header of an array of 8 elements
+const COMPACT_PREFIX: u8 = 0x88;
+
+/// Bytes of the checksum appended after the CBOR
+const CHECKSUM_LEN: usize = 4;
+
+/// Descriptor templates encoded as a single integer
+const TEMPLATE_MULTI: u8 = 0;
+const TEMPLATE_SORTED_MULTI: u8 = 1;
+
+/// Encode the wallet as CBOR array
+/// `[version, name, required_sig, created_at_height, template, descriptor_checksum, xpubs, fingerprints]`
+/// followed by the first 4 bytes of the sha256d of the CBOR.
+/// Xpubs are the raw 78 bytes and in the template they are replaced by `@{index}`, the template
+/// is an integer for the `wsh(multi(...))` and `wsh(sortedmulti(...))` created by firma
+pub fn wallet_to_compact(wallet: &WalletJson) -> Result<Vec<u8>> {
+    let (descriptor, checksum) = match wallet.descriptor.find('#') {
+        Some(i) => (&wallet.descriptor[..i], Some(&wallet.descriptor[i + 1..])),
+        None => (&wallet.descriptor[..], None),
+    };
+    let xpubs = extract_xpubs(descriptor)?;
+    let mut template = descriptor.to_string();
+    for (i, xpub) in xpubs.iter().enumerate() {
+        template = template.replacen(&xpub.to_string(), &format!("@{}", i), 1);
+    }
+
+    let mut e = Encoder::new(vec![]);
+    e.array(8)?;
+    e.u8(COMPACT_VERSION)?;
+    e.str(&wallet.name)?;
+    e.u64(wallet.required_sig as u64)?;
+    e.u64(wallet.created_at_height)?;
+    match template_id(&template, wallet.required_sig, xpubs.len()) {
+        Some(id) => e.u8(id)?,
+        None => e.str(&template)?,
+    };
+    match checksum {
+        Some(checksum) => e.str(checksum)?,
+        None => e.null()?,
+    };
+    e.array(xpubs.len() as u64)?;
+    for xpub in xpubs.iter() {
+        e.bytes(&base58::from_check(&xpub.to_string())?)?;
+    }
+    e.array(wallet.fingerprints.len() as u64)?;
+    for fingerprint in wallet.fingerprints.iter() {
+        e.bytes(fingerprint.as_bytes())?;
+    }
+
+    let mut result = e.into_writer();
+    let hash = sha256d::Hash::hash(&result);
+    result.extend(&hash[..CHECKSUM_LEN]);
+    Ok(result)
+}
+
+/// Decode a wallet encoded with `wallet_to_compact`
+pub fn wallet_from_compact(bytes: &[u8]) -> Result<WalletJson> {
+    if bytes.len() <= CHECKSUM_LEN {
+        return Err(Error::CompactWalletMalformed);
+    }
+    let (cbor, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    if sha256d::Hash::hash(cbor)[..CHECKSUM_LEN] != checksum[..] {
+        return Err(Error::CompactWalletChecksum);
+    }
+
+    let mut d = Decoder::new(cbor);
+    if d.array()? != Some(8) || d.u8()? != COMPACT_VERSION {
+        return Err(Error::CompactWalletMalformed);
+    }
+    let name = d.str()?.to_string();
+    let required_sig = d.u64()? as usize;
+    let created_at_height = d.u64()?;
+    // the template id is expanded once the number of keys is known
+    let template = match d.datatype()? {
+        Type::String => Ok(d.str()?.to_string()),
+        _ => Err(d.u8()?),
+    };
+    let checksum = match d.datatype()? {
+        Type::Null => {
+            d.skip()?;
+            None
+        }
+        _ => Some(d.str()?.to_string()),
+    };
+    let mut xpubs = vec![];
+    for _ in 0..d.array()?.ok_or(Error::CompactWalletMalformed)? {
+        let xpub = ExtendedPubKey::from_str(&base58::check_encode_slice(d.bytes()?))?;
+        xpubs.push(xpub);
+    }
+    let mut fingerprints = BTreeSet::new();
+    for _ in 0..d.array()?.ok_or(Error::CompactWalletMalformed)? {
+        fingerprints.insert(Fingerprint::from(d.bytes()?));
+    }
+
+    let mut template = match template {
+        Ok(template) => template,
+        Err(id) => expand_template_id(id, required_sig, xpubs.len())?,
+    };
+    // replace from the last so that `@1` doesn't match the prefix of `@10`
+    for (i, xpub) in xpubs.iter().enumerate().rev() {
+        template = template.replace(&format!("@{}", i), &xpub.to_string());
+    }
+    let descriptor = match checksum {
+        Some(checksum) => format!("{}#{}", template, checksum),
+        None => template,
+    };
+
+    Ok(WalletJson {
+        name,
+        descriptor,
+        fingerprints,
+        required_sig,
+        created_at_height,
+    })
+}
+
+/// Decode a wallet either in compact or in json encoding
+pub fn decode_wallet(bytes: &[u8]) -> Result<WalletJson> {
+    match bytes.first() {
+        Some(&COMPACT_PREFIX) => wallet_from_compact(bytes),
+        _ => Ok(serde_json::from_slice(bytes)?),
+    }
+}
+
+fn template_id(template: &str, required_sig: usize, keys: usize) -> Option<u8> {
+    [TEMPLATE_MULTI, TEMPLATE_SORTED_MULTI]
+        .iter()
+        .find(|id| expand_template_id(**id, required_sig, keys).ok().as_deref() == Some(template))
+        .copied()
+}
+
+fn expand_template_id(id: u8, required_sig: usize, keys: usize) -> Result<String> {
+    let fun = match id {
+        TEMPLATE_MULTI => "multi",
+        TEMPLATE_SORTED_MULTI => "sortedmulti",
+        _ => return Err(Error::CompactWalletMalformed),
+    };
+    let keys: Vec<String> = (0..keys).map(|i| format!("@{}/0/*", i)).collect();
+    Ok(format!("wsh({}({},{}))", fun, required_sig, keys.join(",")))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::compact_wallet::*;
+    use crate::qr::{save_qrs, QrFormat};
+    use tempfile::TempDir;
+
+    const WALLET_2OF3: &str = "{\"name\":\"w2of3\",\"descriptor\":\"wsh(multi(2,tpubD6NzVbkrYhZ4XuzR59W1JHQpXcufQVj64NDa4eiALMJxC2xAwpY7wy2J9RVQ7BHDYK3eWrVRsuMUcdwGn9xVBmC9wfpVawpNGLyrdgAhehd/0/*,tpubD6NzVbkrYhZ4WarEBpY5okLrjRQ8sgfoEsxZfprQDEbAjWM585LhNeT9GuSeFRGL7yLheiRgtCQCBb73y21EsLzRfwdrRmfaAT4yUTEKtu7/0/*,tpubD6NzVbkrYhZ4WRwbTYgdGDMxPUzq5WwX8HwnAR6PYB291uUH63pCU1WFV6RRWGyA2Xy8okiFAqfAXEErx1SVh7mKSVQa34hFaa8GcmuEeds/0/*))#a6gm7vtn\",\"fingerprints\":[\"171f9233\",\"37439b38\",\"ab4343d4\"],\"required_sig\":2,\"created_at_height\":1720730}";
+
+    #[test]
+    fn test_compact_wallet() {
+        let wallet: WalletJson = serde_json::from_str(WALLET_2OF3).unwrap();
+        let compact = wallet_to_compact(&wallet).unwrap();
+        assert_eq!(compact.len(), 285);
+        assert_eq!(WALLET_2OF3.len(), 497);
+        assert_eq!(wallet_from_compact(&compact).unwrap(), wallet);
+        assert_eq!(decode_wallet(&compact).unwrap(), wallet);
+        assert_eq!(decode_wallet(WALLET_2OF3.as_bytes()).unwrap(), wallet);
+
+        let mut tampered = compact.clone();
+        tampered[10] ^= 1;
+        assert!(matches!(
+            wallet_from_compact(&tampered),
+            Err(Error::CompactWalletChecksum)
+        ));
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut qr_dir = temp_dir.path().to_path_buf();
+        qr_dir.push("qr");
+        let files = save_qrs(compact, qr_dir, 20, QrFormat::default()).unwrap();
+        assert_eq!(files.len(), 1);
+
+        let mut sorted = wallet.clone();
+        sorted.descriptor = sorted.descriptor.replace("multi", "sortedmulti");
+        sorted
+            .descriptor
+            .truncate(sorted.descriptor.find('#').unwrap());
+        let compact = wallet_to_compact(&sorted).unwrap();
+        assert_eq!(wallet_from_compact(&compact).unwrap(), sorted);
+
+        let mut custom = sorted.clone();
+        custom.descriptor = custom.descriptor.replace("/0/*", "/1/*");
+        let compact = wallet_to_compact(&custom).unwrap();
+        assert_eq!(wallet_from_compact(&compact).unwrap(), custom);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::qr::QrFormat;
+use crate::*;
+use bitcoin::Network;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::PathBuf;
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Defaults of a network, every missing field keeps the built-in default
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Bitcoin node rpc url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    /// Bitcoin node cookie file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookie_file: Option<PathBuf>,
+
+    /// Bitcoin node rpc user, alternative to the cookie file, the password is never in the config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_user: Option<String>,
+
+    /// Timeout in seconds of the calls to the bitcoin node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+
+    /// Wallet used when `--wallet-name` is not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallet_name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_version: Option<i16>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_format: Option<QrFormat>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_derivations: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_any_derivations: Option<bool>,
+}
+
+/// Content of `config.toml` in the datadir, eg.
+/// ```toml
+/// network = "testnet"
+///
+/// [testnet]
+/// url = "http://127.0.0.1:18332"
+/// cookie_file = "/home/user/.bitcoin/testnet3/.cookie"
+/// wallet_name = "firma-wallet"
+/// qr_version = 10
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Network used when `--network` is not given
+    pub network: Option<Network>,
+    pub bitcoin: NetworkConfig,
+    pub testnet: NetworkConfig,
+    pub regtest: NetworkConfig,
+}
+
+impl Config {
+    pub fn path(datadir: &str) -> Result<PathBuf> {
+        Ok(expand_tilde(datadir)?.join(CONFIG_FILE))
+    }
+
+    /// Read the config of `datadir`, the default one if the file doesn't exist
+    pub fn load(datadir: &str) -> Result<Config> {
+        let path = Config::path(datadir)?;
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content).map_err(|e| Error::FileNotFoundOrCorrupt(path, e.to_string()))
+    }
+
+    pub fn for_network(&self, network: Network) -> &NetworkConfig {
+        match network {
+            Network::Bitcoin => &self.bitcoin,
+            Network::Testnet => &self.testnet,
+            Network::Regtest => &self.regtest,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Node options, if the url and the cookie file or the rpc user are configured
+    pub fn daemon_opts(&self) -> Option<DaemonOpts> {
+        if self.cookie_file.is_none() && self.rpc_user.is_none() {
+            return None;
+        }
+        Some(DaemonOpts {
+            url: self.url.clone()?,
+            cookie_file: self.cookie_file.clone(),
+            rpc_user: self.rpc_user.clone(),
+            rpc_password: None,
+            timeout: self.timeout,
+        })
+    }
+
+    /// The config with the built-in defaults in place of the missing fields
+    pub fn resolved(&self) -> NetworkConfig {
+        NetworkConfig {
+            qr_version: Some(self.qr_version.unwrap_or(14)),
+            qr_format: Some(self.qr_format.unwrap_or_default()),
+            total_derivations: Some(self.total_derivations.unwrap_or(1000)),
+            allow_any_derivations: Some(self.allow_any_derivations.unwrap_or(false)),
+            ..self.clone()
+        }
+    }
+
+    /// Command options fields with a configured default
+    fn defaults(&self) -> Map<String, Value> {
+        let mut defaults = Map::new();
+        if let Some(qr_version) = self.qr_version {
+            defaults.insert("qr_version".to_string(), qr_version.into());
+        }
+        if let Some(qr_format) = self.qr_format {
+            defaults.insert("qr_format".to_string(), qr_format.extension().into());
+        }
+        if let Some(total_derivations) = self.total_derivations {
+            defaults.insert("total_derivations".to_string(), total_derivations.into());
+        }
+        if let Some(allow_any_derivations) = self.allow_any_derivations {
+            defaults.insert("allow_any_derivations".into(), allow_any_derivations.into());
+        }
+        defaults
+    }
+
+    /// Set the defaults in the json `args` of a command, for the fields missing or present but
+    /// not `given` explicitly
+    pub fn apply_to_args<F: Fn(&str) -> bool>(&self, args: &mut Value, given: F) {
+        if let Value::Object(map) = args {
+            for (key, value) in self.defaults() {
+                if !map.contains_key(&key) || !given(&key) {
+                    map.insert(key, value);
+                }
+            }
+        }
+    }
+
+    /// `opt` with the defaults applied to the fields not `given` explicitly
+    pub fn apply<T, F>(&self, opt: &T, given: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Fn(&str) -> bool,
+    {
+        let mut args = serde_json::to_value(opt)?;
+        self.apply_to_args(&mut args, given);
+        Ok(serde_json::from_value(args)?)
+    }
+}
+
+/// The effective configuration of `network` in `datadir`
+pub fn show(datadir: &str, network: Network) -> Result<ConfigOutput> {
+    let config = Config::load(datadir)?;
+    Ok(ConfigOutput {
+        config_file: Config::path(datadir)?,
+        datadir: datadir.to_string(),
+        network,
+        config: config.for_network(network).resolved(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        assert_eq!(Config::load(datadir).unwrap(), Config::default());
+
+        let content = r#"
+network = "regtest"
+
+[regtest]
+url = "http://127.0.0.1:18443"
+cookie_file = "/tmp/.cookie"
+wallet_name = "w"
+qr_version = 10
+qr_format = "svg"
+"#;
+        fs::write(Config::path(datadir).unwrap(), content).unwrap();
+        let config = Config::load(datadir).unwrap();
+        assert_eq!(config.network, Some(Network::Regtest));
+        assert_eq!(
+            config.for_network(Network::Testnet),
+            &NetworkConfig::default()
+        );
+        let regtest = config.for_network(Network::Regtest);
+        assert_eq!(regtest.daemon_opts().unwrap().url, "http://127.0.0.1:18443");
+        assert!(config.testnet.daemon_opts().is_none());
+        let rpc_user = NetworkConfig {
+            url: Some("http://node:8332".to_string()),
+            rpc_user: Some("firma".to_string()),
+            timeout: Some(30),
+            ..Default::default()
+        };
+        let daemon_opts = rpc_user.daemon_opts().unwrap();
+        assert!(matches!(daemon_opts.auth(), Err(Error::MissingRpcPassword)));
+        assert_eq!(daemon_opts.timeout, Some(30));
+
+        // explicit args win, missing args are filled
+        let mut args = json!({"qr_version": 20, "qr_format": "png"});
+        regtest.apply_to_args(&mut args, |key| key == "qr_version");
+        assert_eq!(args, json!({"qr_version": 20, "qr_format": "svg"}));
+        let mut args = json!({"qr_version": 20});
+        regtest.apply_to_args(&mut args, |_| true);
+        assert_eq!(args, json!({"qr_version": 20, "qr_format": "svg"}));
+
+        let output = show(datadir, Network::Regtest).unwrap();
+        assert_eq!(output.config.qr_version, Some(10));
+        assert_eq!(output.config.total_derivations, Some(1000));
+        let output = show(datadir, Network::Testnet).unwrap();
+        assert_eq!(output.config.qr_version, Some(14));
+        assert!(output.config.url.is_none());
+
+        fs::write(Config::path(datadir).unwrap(), "network = 1").unwrap();
+        assert!(matches!(
+            Config::load(datadir),
+            Err(Error::FileNotFoundOrCorrupt(_, _))
+        ));
+    }
+}
@@ -0,0 +1,406 @@
+use crate::common::config::Config;
+use crate::common::journal::{self, JournalEvent, JournalRecord};
+use crate::common::list::ListOptions;
+use crate::offline::descriptor::DeriveAddressOpts;
+use crate::*;
+use bitcoin::Network;
+use common::error::ToJson;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Version of the request schema, increased when a method changes its arguments incompatibly
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Every method exposed by `dispatch`
+pub const METHODS: &[&str] = &[
+    "methods",
+    "config",
+    "random",
+    "dice",
+    "restore",
+    "list",
+    "sign",
+    "print",
+    "save_psbt",
+    "derive_key",
+    "derive_address",
+    "import_wallet",
+    "decode_wallet",
+    "sign_wallet",
+    "verify_wallet",
+    "sign_message",
+    "verify_message",
+    "decrypt",
+    "scan",
+    "backup_sheet",
+    "split",
+    "bip85",
+    "reencrypt",
+    "verify_backup",
+    "import",
+    "export",
+    "merge_qrs",
+    "merge_urs",
+    "journal",
+];
+
+/// Methods recording their action in the journal, which is checked before the action
+const JOURNALED_METHODS: &[&str] = &[
+    "random",
+    "dice",
+    "restore",
+    "sign",
+    "derive_key",
+    "sign_wallet",
+    "decrypt",
+    "bip85",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Request {
+    /// Schema version the caller is using, if missing it's the first one
+    pub version: u32,
+    pub datadir: String,
+    pub network: Network,
+    pub method: String,
+    pub args: Value,
+    /// Optional datadir encryption key, used for the journal
+    pub datadir_encryption_key: Option<StringEncoding>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MethodsOutput {
+    pub version: u32,
+    pub methods: Vec<String>,
+}
+
+impl Request {
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let version = match value.get("version") {
+            Some(v) => v.as_u64().ok_or(Error::UnsupportedSchemaVersion(0))? as u32,
+            None => 1,
+        };
+        let datadir = value
+            .get("datadir")
+            .and_then(|s| s.as_str())
+            .ok_or(Error::MissingDatadir)?;
+        let network = value
+            .get("network")
+            .and_then(|s| s.as_str())
+            .ok_or(Error::MissingNetwork)?;
+        let method = value
+            .get("method")
+            .and_then(|s| s.as_str())
+            .ok_or_else(|| Error::UnknownMethod("".to_string()))?;
+        Ok(Request {
+            version,
+            datadir: datadir.to_string(),
+            network: Network::from_str(network)?,
+            method: method.to_string(),
+            args: value.get("args").cloned().unwrap_or(Value::Null),
+            datadir_encryption_key: match value.get("datadir_encryption_key") {
+                Some(key) => serde_json::from_value(key.clone())?,
+                None => None,
+            },
+        })
+    }
+}
+
+/// Parse the json `request`, call the requested method and return its json result
+pub fn call(request: &str) -> Result<Value> {
+    let value: Value = serde_json::from_str(request)?;
+    let request = Request::from_value(&value)?;
+    if request.version == 0 || request.version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion(request.version));
+    }
+    info!(
+        "method:{} datadir:{} network:{} args:{:?}",
+        request.method, request.datadir, request.network, request.args
+    );
+    // options missing in the request take the defaults of the datadir config
+    let mut args = request.args;
+    Config::load(&request.datadir)?
+        .for_network(request.network)
+        .apply_to_args(&mut args, |_| true);
+    dispatch(
+        &request.method,
+        &request.datadir,
+        request.network,
+        args,
+        request.datadir_encryption_key.as_ref(),
+    )
+}
+
+fn parse<T: DeserializeOwned>(args: Value) -> Result<T> {
+    Ok(serde_json::from_value(args)?)
+}
+
+/// Call `method` deserializing `args` in its options, the journal and imported wallets are
+/// encrypted with `datadir_encryption_key` if given
+pub fn dispatch(
+    method: &str,
+    datadir: &str,
+    network: Network,
+    args: Value,
+    datadir_encryption_key: Option<&StringEncoding>,
+) -> Result<Value> {
+    use crate::offline::*;
+    let journal_key = datadir_encryption_key;
+    let mut journaled = JOURNALED_METHODS.contains(&method);
+    // callers without the datadir key, like android, can't read an encrypted journal, their
+    // actions happen without being journaled
+    if journaled && journal_key.is_none() && journal::is_encrypted(datadir, network)? {
+        warn!(
+            "journal is encrypted and no datadir key is given, {} is not journaled",
+            method
+        );
+        journaled = false;
+    }
+    if journaled {
+        journal::check(datadir, network, journal_key)?;
+    }
+    let append = |record: JournalRecord| -> Result<()> {
+        if journaled {
+            journal::append(datadir, network, record, journal_key)?;
+        }
+        Ok(())
+    };
+    let value = match method {
+        "methods" => serde_json::to_value(MethodsOutput {
+            version: SCHEMA_VERSION,
+            methods: METHODS.iter().map(|m| m.to_string()).collect(),
+        })?,
+        "config" => serde_json::to_value(common::config::show(datadir, network)?)?,
+        "random" => key_created(random::create_key(datadir, network, &parse(args)?)?, append)?,
+        "dice" => key_created(dice::roll(datadir, network, &parse(args)?)?, append)?,
+        "restore" => key_created(restore::start(datadir, network, &parse(args)?)?, append)?,
+        "list" => {
+            let opt: ListOptions = parse(args)?;
+            serde_json::to_value(common::list::list(datadir, network, &opt)?)?
+        }
+        "sign" => {
+            let (psbt_print, record) = sign::sign_psbt(&parse(args)?, network)?;
+            if let Some(record) = record {
+                append(record)?;
+            }
+            serde_json::to_value(psbt_print)?
+        }
+        "print" => serde_json::to_value(print::start(datadir, network, &parse(args)?)?)?,
+        "save_psbt" => {
+            let opt = parse(args)?;
+            serde_json::to_value(sign::save_psbt_options(datadir, network, &opt)?)?
+        }
+        "derive_key" => key_created(derive_key::start(datadir, network, &parse(args)?)?, append)?,
+        "derive_address" => {
+            let opt: DeriveAddressOpts = parse(args)?;
+            serde_json::to_value(descriptor::derive_address(network, &opt)?)?
+        }
+        "import_wallet" => {
+            let wallet: WalletJson = parse(args)?;
+            let result =
+                import_wallet::import_wallet(datadir, network, &wallet, datadir_encryption_key)?;
+            serde_json::to_value(result)?
+        }
+        "decode_wallet" => {
+            let hex_string: String = parse(args)?;
+            let result = common::compact_wallet::decode_wallet(&hex::decode(hex_string)?)?;
+            serde_json::to_value(result)?
+        }
+        "sign_wallet" => {
+            let opt: sign_wallet::SignWalletOptions = parse(args)?;
+            let signature = sign_wallet::sign_wallet(datadir, network, &opt)?;
+            let record = JournalRecord {
+                fingerprint: Some(signature.xpub.fingerprint()),
+                subjects: vec![opt.wallet_name],
+                ..JournalRecord::new(JournalEvent::SignWallet)
+            };
+            append(record)?;
+            serde_json::to_value(signature)?
+        }
+        "verify_wallet" => {
+            let opt = parse(args)?;
+            serde_json::to_value(sign_wallet::verify_wallet(datadir, network, &opt)?)?
+        }
+        "sign_message" => {
+            let opt = parse(args)?;
+            serde_json::to_value(message::sign_message(datadir, network, &opt)?)?
+        }
+        "verify_message" => serde_json::to_value(message::verify_message(network, &parse(args)?)?)?,
+        "decrypt" => {
+            let opt: decrypt::DecryptOptions = parse(args)?;
+            let value = decrypt::decrypt::<Value>(&opt)?;
+            let record = JournalRecord {
+                subjects: vec![opt.path.display().to_string()],
+                ..JournalRecord::new(JournalEvent::Decrypt)
+            };
+            append(record)?;
+            value
+        }
+        "scan" => serde_json::to_value(scan::scan(datadir, network, &parse(args)?)?)?,
+        "backup_sheet" => {
+            let opt = parse(args)?;
+            serde_json::to_value(backup_sheet::backup_sheet(datadir, network, &opt)?)?
+        }
+        "split" => serde_json::to_value(split::split(datadir, network, &parse(args)?)?)?,
+        "bip85" => {
+            let output = bip85::start(datadir, network, &parse(args)?)?;
+            if let Some(key_output) = output.key.as_ref() {
+                let record = JournalRecord::create_key(&key_output.key);
+                append(record)?;
+            }
+            serde_json::to_value(output)?
+        }
+        "reencrypt" => {
+            let opt = parse(args)?;
+            serde_json::to_value(reencrypt::reencrypt(datadir, network, &opt)?)?
+        }
+        "verify_backup" => {
+            let opt = parse(args)?;
+            serde_json::to_value(verify_backup::verify_backup(datadir, network, &opt)?)?
+        }
+        "import" => {
+            let opt = parse(args)?;
+            serde_json::to_value(common::exchange::import(datadir, network, &opt)?)?
+        }
+        "export" => {
+            let mut opt: common::exchange::ExportOptions = parse(args)?;
+            if opt.encryption_key.is_none() {
+                opt.encryption_key = journal_key.cloned();
+            }
+            serde_json::to_value(common::exchange::export_journaled(datadir, network, &opt)?)?
+        }
+        "merge_qrs" => {
+            let string_values: Vec<String> = parse(args)?;
+            let mut values = vec![];
+            for string in string_values {
+                values.push(hex::decode(&string)?);
+            }
+            match qr_code::structured::merge_qrs(values) {
+                Ok(merged) => hex::encode(merged).into(),
+                Err(e) => e.to_json(),
+            }
+        }
+        "merge_urs" => {
+            let parts: Vec<String> = parse(args)?;
+            serde_json::to_value(common::ur::merge_urs_payload(&parts, network)?)?
+        }
+        "journal" => journal::journal(datadir, network, &parse(args)?, journal_key)?,
+        _ => return Err(Error::UnknownMethod(method.to_string())),
+    };
+    Ok(value)
+}
+
+/// Record the creation of the key in the journal
+fn key_created(
+    output: MasterKeyOutput,
+    append: impl Fn(JournalRecord) -> Result<()>,
+) -> Result<Value> {
+    append(JournalRecord::create_key(&output.key))?;
+    Ok(serde_json::to_value(output)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::dispatcher::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_dispatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        for method in METHODS {
+            // wrong args must be rejected by the method, not as unknown method
+            let result = dispatch(method, datadir, Network::Testnet, Value::Bool(true), None);
+            assert!(
+                !matches!(result, Err(Error::UnknownMethod(_))),
+                "{}",
+                method
+            );
+        }
+        let result = dispatch("unknown", datadir, Network::Testnet, Value::Null, None);
+        assert!(matches!(result, Err(Error::UnknownMethod(_))));
+
+        let request = format!(
+            r#"{{"datadir":"{}","network":"testnet","method":"random","args":{{"key_name":"a","qr_version":14}}}}"#,
+            datadir
+        );
+        let value = call(&request).unwrap();
+        let output: MasterKeyOutput = serde_json::from_value(value).unwrap();
+        assert_eq!(output.key.name, "a");
+
+        // qr_version missing in the request is taken from the config
+        let config = Config::path(datadir).unwrap();
+        std::fs::write(&config, "[testnet]\nqr_version = 5\n").unwrap();
+        let value = call(
+            &request
+                .replace(r#","qr_version":14"#, "")
+                .replace(r#""a""#, r#""b""#),
+        );
+        let output: MasterKeyOutput = serde_json::from_value(value.unwrap()).unwrap();
+        assert_eq!(output.key.name, "b");
+        let value = dispatch("config", datadir, Network::Testnet, Value::Null, None).unwrap();
+        assert_eq!(value["config"]["qr_version"], 5);
+
+        // created keys are recorded in the journal, also with config defaults in the args
+        let request = format!(
+            r#"{{"datadir":"{}","network":"testnet","method":"journal","args":{{"command":"show","event":"create_key"}}}}"#,
+            datadir
+        );
+        let output: JournalShowOutput = serde_json::from_value(call(&request).unwrap()).unwrap();
+        let names: Vec<_> = output
+            .entries
+            .iter()
+            .map(|e| &e.record.subjects[0])
+            .collect();
+        assert_eq!(names, vec!["a", "b"]);
+        let args = serde_json::json!({"command": "verify"});
+        let value = dispatch("journal", datadir, Network::Testnet, args, None).unwrap();
+        assert_eq!(value["entries"], 2);
+
+        // with the datadir key the journal is encrypted and can't be read without it
+        let random = format!(
+            r#"{{"datadir":"{}","network":"testnet","method":"random","args":{{"key_name":"c"}},"datadir_encryption_key":{{"t":"hex","c":"{}"}}}}"#,
+            datadir,
+            "01".repeat(32)
+        );
+        call(&random).unwrap();
+        assert!(matches!(call(&request), Err(Error::MissingEncryptionKey)));
+
+        // callers without the datadir key act on an encrypted journal without journaling
+        let journal_file = journal::journal_path(datadir, Network::Testnet).unwrap();
+        let encrypted_journal = std::fs::read(&journal_file).unwrap();
+        let args = serde_json::json!({"key_name": "e"});
+        dispatch("random", datadir, Network::Testnet, args, None).unwrap();
+        assert_eq!(std::fs::read(&journal_file).unwrap(), encrypted_journal);
+
+        // an unreadable journal stops the action before it happens
+        std::fs::write(&journal_file, "not json\n").unwrap();
+        let result = call(&random.replace(r#""key_name":"c""#, r#""key_name":"d""#));
+        assert!(matches!(result, Err(Error::JournalTampered(0))));
+        let key_dir = PathBuilder::new(datadir, Network::Testnet, Kind::Key, None)
+            .type_path()
+            .unwrap();
+        assert!(key_dir.join("c").exists());
+        assert!(!key_dir.join("d").exists());
+
+        let request = format!(
+            r#"{{"version":{},"datadir":"{}","network":"testnet","method":"methods"}}"#,
+            SCHEMA_VERSION, datadir
+        );
+        let output: MethodsOutput = serde_json::from_value(call(&request).unwrap()).unwrap();
+        assert_eq!(output.methods.len(), METHODS.len());
+
+        let request = format!(
+            r#"{{"version":{},"datadir":"{}","network":"testnet","method":"methods"}}"#,
+            SCHEMA_VERSION + 1,
+            datadir
+        );
+        assert!(matches!(
+            call(&request),
+            Err(Error::UnsupportedSchemaVersion(_))
+        ));
+    }
+}
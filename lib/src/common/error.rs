@@ -1,4 +1,4 @@
-use crate::ErrorJson;
+use crate::{ErrorCategory, ErrorJson};
 use bitcoin::hashes::core::fmt::Formatter;
 use qr_code::types::QrError;
 use serde_json::Value;
@@ -8,6 +8,7 @@ use std::path::PathBuf;
 #[derive(Debug)]
 pub enum Error {
     Generic(String),
+    Panic(String),
 
     // Internal
     FileExist(PathBuf),
@@ -17,7 +18,10 @@ pub enum Error {
     MismatchPrevoutHash,
     MissingDatadir,
     MissingNetwork,
+    UnknownMethod(String),
+    UnsupportedSchemaVersion(u32),
     MissingDaemonOpts,
+    MissingRpcPassword,
     MissingOutpoint,
     MissingTxout,
     MissingKey,
@@ -39,11 +43,50 @@ pub enum Error {
     PSBTNotChangedAfterMerge,
     PSBTBadStringEncoding(String),
     PSBTCannotDeserialize(bitcoin::consensus::encode::Error),
+    PSBTBadMetadata(String),
     MaybeEncryptedWrongState,
     Encryption(aes_gcm_siv::aead::Error),
     EncryptionKeyNot32Bytes(usize),
     MissingEncryptionKey,
+    Kdf(argon2::Error),
+    KdfParamsTooHigh(u32, u32, u32),
     InvalidMessageSignature,
+    UrUnsupportedType(String),
+    UrMalformed,
+    UrIncomplete,
+    QrNotFound(PathBuf),
+    QrMissingParts,
+    QrMultiplePayloads,
+    QrUnknownPayload,
+    BackupSheetTarget,
+    CompactWalletMalformed,
+    CompactWalletChecksum,
+    MissingMnemonic,
+    Slip39Verification,
+    MissingPassphrase,
+    PassphraseMismatch,
+    PassphraseWithXprv,
+    ReencryptTarget,
+    DiceEntropyChecks(Vec<String>),
+    VerifyBackupSource,
+    VerifyBackupXorRandom,
+    ExchangeBadManifest(String),
+    ExchangeHashMismatch(String),
+    ExchangeInvalidName(String),
+    ReencryptVerification(PathBuf),
+    PSBTAlreadySigned(usize),
+    DerivationNotAllowed(usize, String),
+    DerivedPubkeyMismatch(usize),
+    SignMessageTarget,
+    VerifyMessageSource,
+    MessageUnsupportedAddress(String),
+    Bip322WrongPsbt,
+    PSBTMissingSignatures(usize, usize),
+    ReservesWrongPsbt,
+    AddressWrongNetwork(bitcoin::Address, bitcoin::Network),
+    PaymentUriMissingAmount(String),
+    RecipientDuplicated(bitcoin::Address),
+    JournalTampered(u64),
 
     // External
     BitcoinRpc(bitcoincore_rpc::Error),
@@ -66,8 +109,14 @@ pub enum Error {
     Utf8(std::str::Utf8Error),
     Nul(std::ffi::NulError),
     ParseInt(std::num::ParseIntError),
+    Slip39(sssmc39::Error),
     Miniscript(miniscript::Error),
     Bmp(qr_code::bmp_monochrome::BmpError),
+    Ur(::ur::ur::Error),
+    CborEncode(minicbor::encode::Error<std::convert::Infallible>),
+    CborDecode(minicbor::decode::Error),
+    Gif(gif::EncodingError),
+    Image(image::ImageError),
 }
 
 macro_rules! impl_error {
@@ -104,11 +153,22 @@ impl_error!(miniscript::Error, Miniscript);
 impl_error!(crate::common::mnemonic::Error, Mnemonic);
 impl_error!(qr_code::bmp_monochrome::BmpError, Bmp);
 impl_error!(aes_gcm_siv::aead::Error, Encryption);
+impl_error!(::ur::ur::Error, Ur);
+impl_error!(
+    minicbor::encode::Error<std::convert::Infallible>,
+    CborEncode
+);
+impl_error!(minicbor::decode::Error, CborDecode);
+impl_error!(gif::EncodingError, Gif);
+impl_error!(sssmc39::Error, Slip39);
+impl_error!(image::ImageError, Image);
+impl_error!(argon2::Error, Kdf);
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Error::Generic(e) => write!(f, "{}", e),
+            Error::Panic(e) => write!(f, "Unexpected panic: {}", e),
 
             Error::FileExist(s) => write!(f, "File {:?} already exist", s),
             Error::DiceValueErr(n, max) => {
@@ -119,7 +179,18 @@ impl fmt::Display for Error {
             Error::MismatchPrevoutHash => write!(f, "Prevout hash doesn't match previous tx"),
             Error::MissingDatadir => write!(f, "Missing datadir"),
             Error::MissingNetwork => write!(f, "Missing network"),
-            Error::MissingDaemonOpts => write!(f, "Missing daemon options (url and cookie file)"),
+            Error::UnknownMethod(m) => write!(f, "Unknown method \"{}\"", m),
+            Error::UnsupportedSchemaVersion(v) => write!(f, "Unsupported schema version {}", v),
+            Error::MissingDaemonOpts => {
+                write!(
+                    f,
+                    "Missing daemon options (url and cookie file or rpc user)"
+                )
+            }
+            Error::MissingRpcPassword => write!(
+                f,
+                "Missing rpc password, use --read-rpc-password or FIRMA_RPC_PASSWORD"
+            ),
             Error::FileNotFoundOrCorrupt(p, e) => {
                 write!(f, "{:?} file not found or corrupted: {}", p, e)
             }
@@ -144,13 +215,122 @@ impl fmt::Display for Error {
                 write!(f, "PSBT has bad {} string encoding", kind)
             }
             Error::PSBTCannotDeserialize(e) => write!(f, "Cannot deserialize PSBT ({})", e),
+            Error::PSBTBadMetadata(s) => write!(f, "PSBT has bad metadata ({})", s),
             Error::MaybeEncryptedWrongState => write!(f, "Wrong State"),
             Error::Encryption(e) => write!(f, "Encryption ({})", e),
             Error::EncryptionKeyNot32Bytes(s) => {
                 write!(f, "Encryption key must be 32 bytes but it's {} bytes", s)
             }
             Error::MissingEncryptionKey => write!(f, "MissingEncryptionKey"),
+            Error::Kdf(e) => write!(f, "Key derivation ({})", e),
+            Error::KdfParamsTooHigh(m_cost, t_cost, p_cost) => write!(
+                f,
+                "Key derivation parameters m_cost {} t_cost {} p_cost {} exceed the maximum",
+                m_cost, t_cost, p_cost
+            ),
             Error::InvalidMessageSignature => write!(f, "Invalid message signature"),
+            Error::UrUnsupportedType(s) => write!(f, "Unsupported UR type {}", s),
+            Error::UrMalformed => write!(f, "Malformed UR"),
+            Error::UrIncomplete => write!(f, "UR parts are not enough to rebuild the message"),
+            Error::QrNotFound(p) => write!(f, "QR code not found in {:?}", p),
+            Error::QrMissingParts => write!(f, "Some parts of the structured QR are missing"),
+            Error::QrMultiplePayloads => write!(f, "QR codes contain more than one payload"),
+            Error::QrUnknownPayload => {
+                write!(
+                    f,
+                    "QR payload is not a PSBT, a wallet, an xpub or an address"
+                )
+            }
+            Error::BackupSheetTarget => {
+                write!(f, "Specify exactly one of wallet name or key name")
+            }
+            Error::CompactWalletMalformed => write!(f, "Malformed compact wallet"),
+            Error::CompactWalletChecksum => write!(f, "Compact wallet checksum mismatch"),
+            Error::MissingMnemonic => write!(f, "The key doesn't have a mnemonic"),
+            Error::Slip39Verification => write!(f, "SLIP-39 shares don't recombine to the key"),
+            Error::MissingPassphrase => write!(f, "The key requires a passphrase"),
+            Error::PassphraseWithXprv => {
+                write!(
+                    f,
+                    "A passphrase can't be applied to a key restored from xprv"
+                )
+            }
+            Error::PassphraseMismatch => {
+                write!(
+                    f,
+                    "Fingerprint derived with the passphrase doesn't match the key"
+                )
+            }
+            Error::DiceEntropyChecks(failures) => write!(
+                f,
+                "Dice launches don't look random ({}), use --force to create the key anyway",
+                failures.join(", ")
+            ),
+            Error::VerifyBackupSource => {
+                write!(
+                    f,
+                    "Specify either the mnemonic or the faces and dice launches"
+                )
+            }
+            Error::VerifyBackupXorRandom => write!(
+                f,
+                "Key dice entropy has been XORed with random, verify the mnemonic instead"
+            ),
+            Error::ExchangeBadManifest(s) => write!(f, "Bad manifest: {}", s),
+            Error::ExchangeInvalidName(name) => write!(
+                f,
+                "Invalid name \"{}\", it must not be empty, `..` or contain path separators",
+                name
+            ),
+            Error::ExchangeHashMismatch(file) => {
+                write!(f, "Hash of {} doesn't match the manifest", file)
+            }
+            Error::ReencryptTarget => write!(f, "Specify exactly one of key name or --all"),
+            Error::ReencryptVerification(p) => {
+                write!(f, "{:?} doesn't read back correctly, original kept", p)
+            }
+            Error::PSBTAlreadySigned(i) => write!(
+                f,
+                "request to sign a PSBT already containing a signature from this key (input {})",
+                i
+            ),
+            Error::DerivationNotAllowed(i, s) => write!(f, "{} (input {})", s, i),
+            Error::SignMessageTarget => {
+                write!(f, "Specify exactly one of key file or wallet name")
+            }
+            Error::VerifyMessageSource => write!(
+                f,
+                "Specify either the address and the signature or the PSBT file"
+            ),
+            Error::MessageUnsupportedAddress(s) => write!(
+                f,
+                "{} is not supported, only p2pkh addresses or p2wsh multisig",
+                s
+            ),
+            Error::Bip322WrongPsbt => write!(f, "PSBT is not the BIP322 proof of the message"),
+            Error::ReservesWrongPsbt => {
+                write!(f, "PSBT is not the proof of reserves of the message")
+            }
+            Error::AddressWrongNetwork(address, network) => {
+                write!(f, "Address {} is not valid for {}", address, network)
+            }
+            Error::PaymentUriMissingAmount(uri) => {
+                write!(f, "Payment uri {} has no amount", uri)
+            }
+            Error::RecipientDuplicated(address) => {
+                write!(f, "Address {} is a recipient more than once", address)
+            }
+            Error::JournalTampered(i) => {
+                write!(f, "Journal entry {} is corrupted or has been tampered", i)
+            }
+            Error::PSBTMissingSignatures(n, required) => {
+                write!(f, "PSBT has {} signatures but {} are required", n, required)
+            }
+            Error::DerivedPubkeyMismatch(i) => write!(
+                f,
+                "pubkey derived and expected differs even if fingerprint matches! (input {})",
+                i
+            ),
 
             Error::BitcoinRpc(e) => write!(f, "{:?}", e),
             Error::Serde(e) => write!(f, "{:?}", e),
@@ -172,9 +352,218 @@ impl fmt::Display for Error {
             Error::Utf8(e) => write!(f, "{:?}", e),
             Error::Nul(e) => write!(f, "{:?}", e),
             Error::ParseInt(e) => write!(f, "{:?}", e),
+            Error::Slip39(e) => write!(f, "{}", e),
             Error::Miniscript(e) => write!(f, "{:?}", e),
             Error::Mnemonic(e) => write!(f, "{:?}", e),
             Error::Bmp(e) => write!(f, "{:?}", e),
+            Error::Ur(e) => write!(f, "{:?}", e),
+            Error::CborEncode(e) => write!(f, "{:?}", e),
+            Error::CborDecode(e) => write!(f, "{:?}", e),
+            Error::Gif(e) => write!(f, "{:?}", e),
+            Error::Image(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl Error {
+    /// Stable identifier of the error variant, part of the json output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Generic(_) => "generic",
+            Error::Panic(_) => "panic",
+            Error::FileExist(_) => "file_exist",
+            Error::DiceValueErr(_, _) => "dice_value",
+            Error::WrongKeyFileName => "wrong_key_file_name",
+            Error::MissingPrevoutTx => "missing_prevout_tx",
+            Error::MismatchPrevoutHash => "mismatch_prevout_hash",
+            Error::MissingDatadir => "missing_datadir",
+            Error::MissingNetwork => "missing_network",
+            Error::UnknownMethod(_) => "unknown_method",
+            Error::UnsupportedSchemaVersion(_) => "unsupported_schema_version",
+            Error::MissingDaemonOpts => "missing_daemon_opts",
+            Error::MissingRpcPassword => "missing_rpc_password",
+            Error::MissingOutpoint => "missing_outpoint",
+            Error::MissingTxout => "missing_txout",
+            Error::MissingKey => "missing_key",
+            Error::MissingSighash => "missing_sighash",
+            Error::MissingWitnessUtxo => "missing_witness_utxo",
+            Error::MissingAddress => "missing_address",
+            Error::MissingRescanUpTo => "missing_rescan_up_to",
+            Error::MissingHex => "missing_hex",
+            Error::FileNotFoundOrCorrupt(_, _) => "file_not_found_or_corrupt",
+            Error::MissingName => "missing_name",
+            Error::NeedAtLeastOne => "need_at_least_one",
+            Error::CannotRetrieveHomeDir => "cannot_retrieve_home_dir",
+            Error::AddressFromDescriptorFails => "address_from_descriptor_fails",
+            Error::CaptureGroupNotFound(_) => "capture_group_not_found",
+            Error::NonDefaultScript => "non_default_script",
+            Error::ScriptEmpty => "script_empty",
+            Error::IncompatibleNetworks => "incompatible_networks",
+            Error::Mnemonic(_) => "mnemonic",
+            Error::PSBTNotChangedAfterMerge => "psbt_not_changed_after_merge",
+            Error::PSBTBadStringEncoding(_) => "psbt_bad_string_encoding",
+            Error::PSBTCannotDeserialize(_) => "psbt_cannot_deserialize",
+            Error::PSBTBadMetadata(_) => "psbt_bad_metadata",
+            Error::PSBTAlreadySigned(_) => "psbt_already_signed",
+            Error::DerivationNotAllowed(_, _) => "derivation_not_allowed",
+            Error::DerivedPubkeyMismatch(_) => "derived_pubkey_mismatch",
+            Error::SignMessageTarget => "sign_message_target",
+            Error::VerifyMessageSource => "verify_message_source",
+            Error::MessageUnsupportedAddress(_) => "message_unsupported_address",
+            Error::Bip322WrongPsbt => "bip322_wrong_psbt",
+            Error::PSBTMissingSignatures(_, _) => "psbt_missing_signatures",
+            Error::ReservesWrongPsbt => "reserves_wrong_psbt",
+            Error::AddressWrongNetwork(_, _) => "address_wrong_network",
+            Error::PaymentUriMissingAmount(_) => "payment_uri_missing_amount",
+            Error::RecipientDuplicated(_) => "recipient_duplicated",
+            Error::JournalTampered(_) => "journal_tampered",
+            Error::MaybeEncryptedWrongState => "maybe_encrypted_wrong_state",
+            Error::Encryption(_) => "encryption",
+            Error::EncryptionKeyNot32Bytes(_) => "encryption_key_not_32_bytes",
+            Error::MissingEncryptionKey => "missing_encryption_key",
+            Error::Kdf(_) => "kdf",
+            Error::KdfParamsTooHigh(_, _, _) => "kdf_params_too_high",
+            Error::InvalidMessageSignature => "invalid_message_signature",
+            Error::UrUnsupportedType(_) => "ur_unsupported_type",
+            Error::UrMalformed => "ur_malformed",
+            Error::UrIncomplete => "ur_incomplete",
+            Error::QrNotFound(_) => "qr_not_found",
+            Error::QrMissingParts => "qr_missing_parts",
+            Error::QrMultiplePayloads => "qr_multiple_payloads",
+            Error::QrUnknownPayload => "qr_unknown_payload",
+            Error::BackupSheetTarget => "backup_sheet_target",
+            Error::CompactWalletMalformed => "compact_wallet_malformed",
+            Error::CompactWalletChecksum => "compact_wallet_checksum",
+            Error::MissingMnemonic => "missing_mnemonic",
+            Error::Slip39Verification => "slip39_verification",
+            Error::MissingPassphrase => "missing_passphrase",
+            Error::PassphraseMismatch => "passphrase_mismatch",
+            Error::PassphraseWithXprv => "passphrase_with_xprv",
+            Error::ReencryptTarget => "reencrypt_target",
+            Error::DiceEntropyChecks(_) => "dice_entropy_checks",
+            Error::VerifyBackupSource => "verify_backup_source",
+            Error::VerifyBackupXorRandom => "verify_backup_xor_random",
+            Error::ExchangeBadManifest(_) => "exchange_bad_manifest",
+            Error::ExchangeHashMismatch(_) => "exchange_hash_mismatch",
+            Error::ExchangeInvalidName(_) => "exchange_invalid_name",
+            Error::ReencryptVerification(_) => "reencrypt_verification",
+
+            Error::BitcoinRpc(_) => "bitcoin_rpc",
+            Error::BitcoinEncode(_) => "bitcoin_encode",
+            Error::BitcoinKey(_) => "bitcoin_key",
+            Error::BitcoinSecp256k1(_) => "bitcoin_secp256k1",
+            Error::BitcoinPSBT(_) => "bitcoin_psbt",
+            Error::BitcoinAddress(_) => "bitcoin_address",
+            Error::BitcoinBech32(_) => "bitcoin_bech32",
+            Error::BitcoinScriptError(_) => "bitcoin_script",
+            Error::Serde(_) => "serde",
+            Error::IO(_) => "io",
+            Error::Base58(_) => "base58",
+            Error::Bip32(_) => "bip32",
+            Error::Base64(_) => "base64",
+            Error::PathStrip(_) => "path_strip",
+            Error::Qr(_) => "qr",
+            Error::Hex(_) => "hex",
+            Error::Env(_) => "env",
+            Error::Utf8(_) => "utf8",
+            Error::Nul(_) => "nul",
+            Error::ParseInt(_) => "parse_int",
+            Error::Slip39(_) => "slip39",
+            Error::Miniscript(_) => "miniscript",
+            Error::Bmp(_) => "bmp",
+            Error::Ur(_) => "ur",
+            Error::CborEncode(_) => "cbor_encode",
+            Error::CborDecode(_) => "cbor_decode",
+            Error::Gif(_) => "gif",
+            Error::Image(_) => "image",
+        }
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Error::FileNotFoundOrCorrupt(_, _)
+            | Error::MissingPrevoutTx
+            | Error::MismatchPrevoutHash
+            | Error::MissingOutpoint
+            | Error::MissingTxout
+            | Error::MissingKey
+            | Error::MissingSighash
+            | Error::MissingWitnessUtxo
+            | Error::MissingHex
+            | Error::NonDefaultScript
+            | Error::ScriptEmpty
+            | Error::PSBTBadStringEncoding(_)
+            | Error::PSBTCannotDeserialize(_)
+            | Error::PSBTBadMetadata(_)
+            | Error::MaybeEncryptedWrongState
+            | Error::UrMalformed
+            | Error::CompactWalletMalformed
+            | Error::CompactWalletChecksum
+            | Error::ExchangeBadManifest(_)
+            | Error::ExchangeHashMismatch(_)
+            | Error::ExchangeInvalidName(_)
+            | Error::ReencryptVerification(_)
+            | Error::Bip322WrongPsbt
+            | Error::ReservesWrongPsbt
+            | Error::JournalTampered(_)
+            | Error::KdfParamsTooHigh(_, _, _)
+            | Error::BitcoinEncode(_)
+            | Error::BitcoinPSBT(_)
+            | Error::BitcoinScriptError(_)
+            | Error::Serde(_)
+            | Error::CborDecode(_) => ErrorCategory::CorruptedFile,
+
+            Error::PSBTNotChangedAfterMerge
+            | Error::PSBTAlreadySigned(_)
+            | Error::DerivationNotAllowed(_, _)
+            | Error::DiceEntropyChecks(_)
+            | Error::VerifyBackupXorRandom => ErrorCategory::PolicyViolation,
+
+            Error::BitcoinRpc(_) | Error::MissingRescanUpTo => ErrorCategory::Node,
+
+            Error::DerivedPubkeyMismatch(_)
+            | Error::Encryption(_)
+            | Error::Kdf(_)
+            | Error::InvalidMessageSignature
+            | Error::Slip39Verification
+            | Error::PassphraseMismatch
+            | Error::BitcoinKey(_)
+            | Error::BitcoinSecp256k1(_)
+            | Error::Bip32(_) => ErrorCategory::Crypto,
+
+            Error::CannotRetrieveHomeDir
+            | Error::Panic(_)
+            | Error::AddressFromDescriptorFails
+            | Error::IO(_)
+            | Error::PathStrip(_)
+            | Error::Env(_)
+            | Error::Bmp(_)
+            | Error::CborEncode(_)
+            | Error::Gif(_)
+            | Error::Image(_) => ErrorCategory::Internal,
+
+            _ => ErrorCategory::UserInput,
+        }
+    }
+
+    /// The file related to the error, if any
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            Error::FileExist(p)
+            | Error::FileNotFoundOrCorrupt(p, _)
+            | Error::QrNotFound(p)
+            | Error::ReencryptVerification(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// The index of the PSBT input related to the error, if any
+    pub fn input_index(&self) -> Option<usize> {
+        match self {
+            Error::PSBTAlreadySigned(i)
+            | Error::DerivationNotAllowed(i, _)
+            | Error::DerivedPubkeyMismatch(i) => Some(*i),
+            _ => None,
         }
     }
 }
@@ -203,6 +592,10 @@ impl ToJson for Error {
     fn to_json(&self) -> Value {
         let value = ErrorJson {
             error: self.to_string(),
+            code: self.code().to_string(),
+            category: self.category(),
+            path: self.path().cloned(),
+            input_index: self.input_index(),
         };
         serde_json::to_value(&value).unwrap() // safe to unwrap, ErrorJson does not contain map with non string keys
     }
@@ -212,7 +605,38 @@ impl ToJson for QrError {
     fn to_json(&self) -> Value {
         let value = ErrorJson {
             error: self.to_string(),
+            code: "qr".to_string(),
+            category: ErrorCategory::UserInput,
+            path: None,
+            input_index: None,
         };
         serde_json::to_value(&value).unwrap() // safe to unwrap, ErrorJson does not contain map with non string keys
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_error_json() {
+        let value = Error::PSBTAlreadySigned(2).to_json();
+        let json: ErrorJson = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(json.code, "psbt_already_signed");
+        assert_eq!(json.category, ErrorCategory::PolicyViolation);
+        assert_eq!(json.input_index, Some(2));
+        assert_eq!(value["category"], "policy_violation");
+        assert!(value.get("path").is_none());
+
+        let path = PathBuf::from("/tmp/a.json");
+        let json: ErrorJson =
+            serde_json::from_value(Error::FileExist(path.clone()).to_json()).unwrap();
+        assert_eq!(json.code, "file_exist");
+        assert_eq!(json.category, ErrorCategory::UserInput);
+        assert_eq!(json.path, Some(path));
+
+        let json: ErrorJson = serde_json::from_str(r#"{"error":"old"}"#).unwrap();
+        assert_eq!(json.category, ErrorCategory::Internal);
+    }
+}
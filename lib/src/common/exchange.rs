@@ -0,0 +1,444 @@
+use crate::common::journal::{self, JournalEvent, JournalRecord};
+use crate::common::proprietary::{get_psbt_name, set_psbt_name};
+use crate::file::save_public;
+use crate::offline::import_wallet::import_wallet;
+use crate::offline::sign::save_psbt;
+use crate::qr::QrFormat;
+use crate::*;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::bip32::ExtendedPubKey;
+use bitcoin::Network;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Export PSBTs, wallets and xpubs in a directory, with a manifest listing them with their hash
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct ExportOptions {
+    /// Directory where items and manifest are written, for example on removable storage
+    #[structopt(long, parse(from_os_str))]
+    pub to: PathBuf,
+
+    /// Name of the wallet to export with its signature if present, to be repeated
+    #[structopt(long = "wallet")]
+    #[serde(default)]
+    pub wallets: Vec<String>,
+
+    /// Name of the PSBT to export, to be repeated
+    #[structopt(long = "psbt")]
+    #[serde(default)]
+    pub psbts: Vec<String>,
+
+    /// Name of the key whose xpub is exported, to be repeated.
+    /// If no wallet, psbt or key is specified everything is exported
+    #[structopt(long = "key")]
+    #[serde(default)]
+    pub keys: Vec<String>,
+
+    /// Optional datadir encryption key, exported items are never encrypted
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+}
+
+/// Import the items listed in the manifest of a directory written with export
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct ImportOptions {
+    /// Directory containing the manifest
+    #[structopt(long, parse(from_os_str))]
+    pub from: PathBuf,
+
+    /// QR code max version to use (max size) for the imported PSBTs
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
+    /// Optional datadir encryption key
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+}
+
+pub fn export(datadir: &str, network: Network, opt: &ExportOptions) -> Result<ExportOutput> {
+    let manifest_file = opt.to.join(MANIFEST_FILE);
+    if manifest_file.exists() {
+        return Err(Error::FileExist(manifest_file));
+    }
+    let everything = opt.wallets.is_empty() && opt.psbts.is_empty() && opt.keys.is_empty();
+    let key = opt.encryption_key.as_ref();
+    let mut items: Vec<(ItemKind, String, Vec<u8>)> = vec![];
+
+    for (name, dir) in select(datadir, network, Kind::Key, &opt.keys, everything)? {
+        let public: PublicMasterKey = serde_json::from_slice(&fs::read(dir.join("public.json"))?)?;
+        items.push((ItemKind::Xpub, name, public.xpub.to_string().into_bytes()));
+    }
+    for (name, dir) in select(datadir, network, Kind::Wallet, &opt.wallets, everything)? {
+        let wallet = read_wallet(&dir.join("descriptor.json"), key)?;
+        items.push((
+            ItemKind::Wallet,
+            name.clone(),
+            serde_json::to_vec_pretty(&wallet)?,
+        ));
+        let signature_file = dir.join("signature.json");
+        if signature_file.exists() {
+            let signature = read_signature(&signature_file, key)?;
+            let content = serde_json::to_vec_pretty(&signature)?;
+            items.push((ItemKind::Signature, name, content));
+        }
+    }
+    for (name, dir) in select(datadir, network, Kind::PSBT, &opt.psbts, everything)? {
+        let psbt = read_psbt(&dir.join("psbt.json"), key)?;
+        items.push((ItemKind::Psbt, name, serialize(&psbt)));
+    }
+
+    fs::create_dir_all(&opt.to)?;
+    let mut manifest = Manifest {
+        version: MANIFEST_VERSION,
+        network,
+        items: vec![],
+    };
+    for (kind, name, content) in items {
+        let file = format!("{}-{}.{}", kind, name, kind.extension());
+        fs::write(opt.to.join(&file), &content)?;
+        manifest.items.push(ManifestItem {
+            kind,
+            name,
+            file,
+            sha256: sha256::Hash::hash(&content),
+        });
+    }
+    fs::write(&manifest_file, serde_json::to_string_pretty(&manifest)?)?;
+    info!("Exported {} items in {:?}", manifest.items.len(), opt.to);
+
+    Ok(ExportOutput {
+        manifest_file,
+        items: manifest.items,
+    })
+}
+
+/// Export recording the exported files in the journal, which is checked before exporting
+pub fn export_journaled(
+    datadir: &str,
+    network: Network,
+    opt: &ExportOptions,
+) -> Result<ExportOutput> {
+    let key = opt.encryption_key.as_ref();
+    journal::check(datadir, network, key)?;
+    let output = export(datadir, network, opt)?;
+    let record = JournalRecord {
+        subjects: output.items.iter().map(|i| i.file.clone()).collect(),
+        ..JournalRecord::new(JournalEvent::Export)
+    };
+    journal::append(datadir, network, record, key)?;
+    Ok(output)
+}
+
+/// Directories of the given `kind` named in `names` or all of them if `everything`
+fn select(
+    datadir: &str,
+    network: Network,
+    kind: Kind,
+    names: &[String],
+    everything: bool,
+) -> Result<Vec<(String, PathBuf)>> {
+    let type_path = PathBuilder::new(datadir, network, kind, None).type_path()?;
+    if !everything {
+        let mut selected = vec![];
+        for name in names {
+            check_name(name)?;
+            selected.push((name.clone(), type_path.join(name)));
+        }
+        return Ok(selected);
+    }
+    let mut result = vec![];
+    for entry in fs::read_dir(type_path)? {
+        let dir = entry?.path();
+        let expected_file = match kind {
+            Kind::Key => "public.json",
+            Kind::Wallet => "descriptor.json",
+            Kind::PSBT => "psbt.json",
+        };
+        if let (true, Some(name)) = (dir.join(expected_file).exists(), dir.file_name()) {
+            result.push((name.to_string_lossy().to_string(), dir));
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+pub fn import(datadir: &str, network: Network, opt: &ImportOptions) -> Result<ImportOutput> {
+    let manifest_file = opt.from.join(MANIFEST_FILE);
+    let manifest: Manifest = serde_json::from_slice(&fs::read(&manifest_file)?)?;
+    if manifest.version != MANIFEST_VERSION {
+        let msg = format!("unsupported version {}", manifest.version);
+        return Err(Error::ExchangeBadManifest(msg));
+    }
+    check_compatibility(network, manifest.network)?;
+
+    // validate every item before importing anything
+    let mut items = vec![];
+    for item in manifest.items {
+        check_name(&item.name)?;
+        if Path::new(&item.file).file_name() != Some(item.file.as_ref()) {
+            let msg = format!("file {} is not in the manifest dir", item.file);
+            return Err(Error::ExchangeBadManifest(msg));
+        }
+        let content = fs::read(opt.from.join(&item.file))?;
+        if sha256::Hash::hash(&content) != item.sha256 {
+            return Err(Error::ExchangeHashMismatch(item.file));
+        }
+        items.push((item, content));
+    }
+    items.sort_by_key(|(item, _)| item.kind);
+
+    let key = opt.encryption_key.as_ref();
+    let mut output = ImportOutput::default();
+    for (item, content) in items {
+        let imported = match item.kind {
+            ItemKind::Xpub => {
+                let xpub = ExtendedPubKey::from_str(std::str::from_utf8(&content)?.trim())?;
+                check_compatibility(network, xpub.network)?;
+                let path = PathBuilder::new(datadir, network, Kind::Key, Some(item.name.clone()))
+                    .file("public.json")?;
+                let public = PublicMasterKey { xpub };
+                if path.exists() {
+                    let existing: PublicMasterKey = serde_json::from_slice(&fs::read(&path)?)?;
+                    unchanged(existing == public, path)?
+                } else {
+                    save_public(&public, &path)?;
+                    Some(path)
+                }
+            }
+            ItemKind::Wallet => {
+                let wallet: WalletJson = serde_json::from_slice(&content)?;
+                check_name(&wallet.name)?;
+                let path =
+                    PathBuilder::new(datadir, network, Kind::Wallet, Some(wallet.name.clone()))
+                        .file("descriptor.json")?;
+                if path.exists() {
+                    unchanged(read_wallet(&path, key)? == wallet, path)?
+                } else {
+                    Some(import_wallet(datadir, network, &wallet, key)?)
+                }
+            }
+            ItemKind::Signature => {
+                let signature: WalletSignature = serde_json::from_slice(&content)?;
+                let context = Context {
+                    firma_datadir: datadir.to_string(),
+                    network,
+                    wallet_name: item.name.clone(),
+                    encryption_key: key.cloned(),
+                };
+                let path = context.filename_for_wallet("signature.json")?;
+                if path.exists() {
+                    unchanged(read_signature(&path, key)? == signature, path)?
+                } else {
+                    Some(context.save_signature(&signature)?)
+                }
+            }
+            ItemKind::Psbt => {
+                let mut psbt: PSBT = deserialize(&content)?;
+                match get_psbt_name(&psbt)? {
+                    Some(name) => check_name(&name)?,
+                    None => set_psbt_name(&mut psbt, &item.name)?,
+                }
+                let mut psbts_dir =
+                    PathBuilder::new(datadir, network, Kind::PSBT, None).type_path()?;
+                match save_psbt(
+                    &mut psbt,
+                    &mut psbts_dir,
+                    opt.qr_version,
+                    opt.qr_format,
+                    key,
+                ) {
+                    Ok((path, _, _)) => Some(path),
+                    Err(Error::PSBTNotChangedAfterMerge) => None,
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        match imported {
+            Some(path) => output.imported.push(path),
+            None => output.unchanged.push(item.file),
+        }
+    }
+
+    Ok(output)
+}
+
+/// Names become directories in the datadir, reject the ones escaping it
+pub(crate) fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(&['/', '\\'][..]) {
+        return Err(Error::ExchangeInvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// `None` if the existing item at `path` is `equal` to the imported one, an error otherwise
+fn unchanged(equal: bool, path: PathBuf) -> Result<Option<PathBuf>> {
+    if equal {
+        Ok(None)
+    } else {
+        Err(Error::FileExist(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::exchange::*;
+    use crate::offline::random::{create_key, RandomOptions};
+    use crate::offline::sign::save_psbt_options;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_export_import() {
+        let online_dir = TempDir::new().unwrap();
+        let online = online_dir.path().to_str().unwrap();
+        let offline_dir = TempDir::new().unwrap();
+        let offline = offline_dir.path().to_str().unwrap();
+        let exchange_dir = TempDir::new().unwrap();
+        let network = Network::Testnet;
+
+        let key = create_key(offline, network, &RandomOptions::new("a".into())).unwrap();
+        let wallet = WalletJson {
+            name: "w".to_string(),
+            descriptor: format!("wsh(multi(1,{}/0/*))", key.key.xpub),
+            fingerprints: vec![key.key.fingerprint].into_iter().collect(),
+            required_sig: 1,
+            created_at_height: 0,
+        };
+        import_wallet(online, network, &wallet, None).unwrap();
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.signed.json");
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        let opts = SavePSBTOptions {
+            psbt: StringEncoding::Base64(psbt_json.psbt),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+        };
+        save_psbt_options(online, Network::Testnet, &opts).unwrap();
+
+        let to = exchange_dir.path().join("to-offline");
+        let export_opt = ExportOptions {
+            to: to.clone(),
+            wallets: vec![],
+            psbts: vec![],
+            keys: vec![],
+            encryption_key: None,
+        };
+        let exported = export(online, network, &export_opt).unwrap();
+        assert_eq!(exported.items.len(), 2);
+        assert!(export(online, network, &export_opt).is_err());
+
+        let import_opt = ImportOptions {
+            from: to.clone(),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+        };
+        let imported = import(offline, network, &import_opt).unwrap();
+        assert_eq!(imported.imported.len(), 2);
+        let imported = import(offline, network, &import_opt).unwrap();
+        assert_eq!(imported.unchanged.len(), 2);
+
+        let export_opt = ExportOptions {
+            to: exchange_dir.path().join("to-online"),
+            wallets: vec![],
+            psbts: vec![],
+            keys: vec!["a".to_string()],
+            encryption_key: None,
+        };
+        let exported = export(offline, network, &export_opt).unwrap();
+        assert_eq!(exported.items[0].kind, ItemKind::Xpub);
+        let import_opt = ImportOptions {
+            from: export_opt.to.clone(),
+            ..import_opt
+        };
+        let imported = import(online, network, &import_opt).unwrap();
+        let public: PublicMasterKey =
+            serde_json::from_slice(&fs::read(&imported.imported[0]).unwrap()).unwrap();
+        assert_eq!(public.xpub, key.key.xpub);
+
+        let wallet_file = to.join("wallet-w.json");
+        fs::write(&wallet_file, b"tampered").unwrap();
+        let import_opt = ImportOptions {
+            from: to,
+            ..import_opt
+        };
+        let result = import(offline, network, &import_opt);
+        assert!(matches!(result, Err(Error::ExchangeHashMismatch(_))));
+    }
+
+    #[test]
+    fn test_import_invalid_names() {
+        let offline_dir = TempDir::new().unwrap();
+        let datadir = offline_dir.path().join("datadir");
+        let offline = datadir.to_str().unwrap();
+        let from = offline_dir.path().join("from");
+        fs::create_dir_all(&from).unwrap();
+        let network = Network::Testnet;
+        let import_opt = ImportOptions {
+            from: from.clone(),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+        };
+        let write_manifest = |kind: ItemKind, name: &str, content: &[u8]| {
+            let file = format!("{}-item.{}", kind, kind.extension());
+            fs::write(from.join(&file), content).unwrap();
+            let manifest = Manifest {
+                version: MANIFEST_VERSION,
+                network,
+                items: vec![ManifestItem {
+                    kind,
+                    name: name.to_string(),
+                    file,
+                    sha256: sha256::Hash::hash(content),
+                }],
+            };
+            let manifest = serde_json::to_vec(&manifest).unwrap();
+            fs::write(from.join(MANIFEST_FILE), manifest).unwrap();
+        };
+
+        let xpub = b"tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU";
+        for name in &["../../x", "", "..", "a/b", "a\\b"] {
+            write_manifest(ItemKind::Xpub, name, xpub);
+            let result = import(offline, network, &import_opt);
+            assert!(matches!(result, Err(Error::ExchangeInvalidName(_))));
+        }
+
+        let wallet = WalletJson {
+            name: "../../x".to_string(),
+            descriptor: String::new(),
+            fingerprints: Default::default(),
+            required_sig: 1,
+            created_at_height: 0,
+        };
+        write_manifest(ItemKind::Wallet, "w", &serde_json::to_vec(&wallet).unwrap());
+        let result = import(offline, network, &import_opt);
+        assert!(matches!(result, Err(Error::ExchangeInvalidName(_))));
+        assert!(!offline_dir.path().join("x").exists());
+        assert!(!datadir.exists());
+
+        let export_opt = ExportOptions {
+            to: offline_dir.path().join("to"),
+            wallets: vec!["../w".to_string()],
+            psbts: vec![],
+            keys: vec![],
+            encryption_key: None,
+        };
+        let result = export(offline, network, &export_opt);
+        assert!(matches!(result, Err(Error::ExchangeInvalidName(_))));
+    }
+}
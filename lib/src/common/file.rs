@@ -1,9 +1,12 @@
 use crate::offline::decrypt::MaybeEncrypted;
+use crate::qr::QrFormat;
 use crate::*;
+use bitcoin::consensus::serialize;
 use bitcoin::Network;
 use log::info;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::convert::Into;
+use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::string::ToString;
@@ -149,7 +152,7 @@ pub fn save_private(
     }
     let mut maybe_encrypted = MaybeEncrypted::plain(private_key.clone());
     if let Some(encryption_key) = encryption_key {
-        maybe_encrypted = maybe_encrypted.encrypt(&encryption_key.get_exactly_32()?)?;
+        maybe_encrypted = maybe_encrypted.encrypt_with(encryption_key)?;
     }
     save(serde_json::to_string_pretty(&maybe_encrypted)?, output)
 }
@@ -160,6 +163,7 @@ pub fn save_keys(
     key_name: &str,
     key: PrivateMasterKeyJson,
     qr_version: i16,
+    qr_format: QrFormat,
     encryption_key: Option<&StringEncoding>,
 ) -> Result<MasterKeyOutput> {
     let option_name = Some(key_name.to_string());
@@ -170,12 +174,21 @@ pub fn save_keys(
     let public_master_key = key.clone().into();
     save_public(&public_master_key, &public_key_file)?;
 
-    let path_for_qr = PathBuilder::new(datadir, network, Kind::Key, option_name).file("qr")?;
+    let path_for_qr = path_builder.file("qr")?;
 
     let public_qr_files = qr::save_qrs(
         public_master_key.xpub.to_string().as_bytes().to_vec(),
         path_for_qr,
         qr_version,
+        qr_format,
+    )?;
+
+    let public_ur_files = ur::save_ur_qrs(
+        ur::UrType::CryptoAccount,
+        &ur::account_to_cbor(&public_master_key.xpub)?,
+        path_builder.file("ur")?,
+        qr_version,
+        qr_format,
     )?;
 
     Ok(MasterKeyOutput {
@@ -183,35 +196,90 @@ pub fn save_keys(
         public_file: Some(public_key_file),
         private_file: private_key_file,
         public_qr_files,
+        public_ur_files,
+        entropy_checks: None,
     })
 }
 
-pub fn read_psbt_json(path: &Path) -> Result<PsbtJson> {
-    let slice = fs::read(path)?;
-    Ok(serde_json::from_slice(&slice)?)
+/// Save `value` as json, in an encrypted `MaybeEncrypted` envelope if `encryption_key` is given
+pub fn save_maybe_encrypted<T>(
+    value: &T,
+    output: &Path,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + Debug + Clone,
+{
+    let content = match encryption_key {
+        Some(encryption_key) => {
+            let encrypted = MaybeEncrypted::plain(value.clone()).encrypt_with(encryption_key)?;
+            serde_json::to_string_pretty(&encrypted)?
+        }
+        None => serde_json::to_string_pretty(value)?,
+    };
+    save(content, &output.to_path_buf())
+}
+
+/// Read json saved with `save_maybe_encrypted`, plain files are read also when a key is given
+pub fn read_maybe_encrypted<T>(path: &Path, encryption_key: Option<&StringEncoding>) -> Result<T>
+where
+    T: Serialize + DeserializeOwned + Debug,
+{
+    let content = fs::read(path)?;
+    match serde_json::from_slice::<MaybeEncrypted<T>>(&content) {
+        Ok(MaybeEncrypted::Plain(value)) => Ok(value),
+        Ok(encrypted) => {
+            let encryption_key = encryption_key.ok_or(Error::MissingEncryptionKey)?;
+            match encrypted.decrypt_with(encryption_key)? {
+                MaybeEncrypted::Plain(value) => Ok(value),
+                MaybeEncrypted::Encrypted(_) => Err(Error::MaybeEncryptedWrongState),
+            }
+        }
+        Err(_) => Ok(serde_json::from_slice(&content)?),
+    }
+}
+
+pub fn read_psbt_json(path: &Path, encryption_key: Option<&StringEncoding>) -> Result<PsbtJson> {
+    read_maybe_encrypted(path, encryption_key)
 }
 
-pub fn read_psbt(path: &Path) -> Result<PSBT> {
-    let psbt_json = read_psbt_json(&path)?;
-    Ok(psbt_from_base64(&psbt_json.psbt)?.1)
+/// Read a PSBT file in any of the supported formats: `PsbtJson` possibly encrypted,
+/// BIP174 binary, base64 or hex
+pub fn read_psbt(path: &Path, encryption_key: Option<&StringEncoding>) -> Result<PSBT> {
+    let content = fs::read(path)?;
+    if content.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        let psbt_json = read_psbt_json(path, encryption_key)?;
+        Ok(psbt_from_base64(&psbt_json.psbt)?.1)
+    } else {
+        psbt_from_bytes(&content)
+    }
 }
 
-pub fn read_wallet(path: &PathBuf) -> Result<WalletJson> {
-    let wallet = fs::read(path)?;
-    Ok(serde_json::from_slice(&wallet)?)
+/// Write the PSBT in BIP174 binary format, never encrypted
+pub fn save_psbt_binary(psbt: &PSBT, path: &Path) -> Result<()> {
+    fs::write(path, serialize(psbt))?;
+    info!("Saving binary PSBT {:?}", path);
+    Ok(())
 }
 
-pub fn read_indexes(path: &PathBuf) -> Result<WalletIndexes> {
-    let indexes = fs::read(path)?;
-    Ok(serde_json::from_slice(&indexes)?)
+pub fn read_wallet(path: &Path, encryption_key: Option<&StringEncoding>) -> Result<WalletJson> {
+    read_maybe_encrypted(path, encryption_key)
 }
 
-pub fn read_daemon_opts(path: &PathBuf) -> Result<DaemonOpts> {
-    let daemon_opts = fs::read(path)?;
-    Ok(serde_json::from_slice(&daemon_opts)?)
+pub fn read_indexes(path: &Path, encryption_key: Option<&StringEncoding>) -> Result<WalletIndexes> {
+    read_maybe_encrypted(path, encryption_key)
 }
 
-pub fn read_signature(path: &PathBuf) -> Result<WalletSignature> {
-    let wallet_signature = fs::read(path)?;
-    Ok(serde_json::from_slice(&wallet_signature)?)
+pub fn read_daemon_opts(
+    path: &Path,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<DaemonOpts> {
+    read_maybe_encrypted(path, encryption_key)
+}
+
+pub fn read_signature(
+    path: &Path,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<WalletSignature> {
+    read_maybe_encrypted(path, encryption_key)
 }
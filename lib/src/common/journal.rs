@@ -0,0 +1,435 @@
+use crate::offline::decrypt::MaybeEncrypted;
+use crate::*;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::{Address, Network, Txid};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalEvent {
+    Sign,
+    SignWallet,
+    CreateKey,
+    Decrypt,
+    Export,
+}
+
+impl FromStr for JournalEvent {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        serde_json::from_value(Value::String(s.to_string())).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "({}) valid values are: sign, sign_wallet, create_key, decrypt, export",
+                    s
+                ),
+            )
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalOutput {
+    pub destination: String,
+    pub amount: u64,
+}
+
+/// What happened, without the position in the chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub event: JournalEvent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<JournalOutput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u64>,
+    /// Names of the keys, wallets or files involved
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subjects: Vec<String>,
+}
+
+/// Line of the journal, `hash` commits to every other field including the `hash` of the previous
+/// entry, so that changing or removing an entry breaks the chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub index: u64,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub record: JournalRecord,
+    pub prev_hash: sha256::Hash,
+    pub hash: sha256::Hash,
+}
+
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JournalShowOptions {
+    /// Only the entries of this event (sign, sign_wallet, create_key, decrypt, export)
+    #[structopt(long)]
+    #[serde(default)]
+    pub event: Option<JournalEvent>,
+
+    /// Only the entries of the key with this fingerprint
+    #[structopt(long)]
+    #[serde(default)]
+    pub fingerprint: Option<Fingerprint>,
+
+    /// Only the entries of this transaction
+    #[structopt(long)]
+    #[serde(default)]
+    pub txid: Option<Txid>,
+
+    /// Only the entries since this unix timestamp
+    #[structopt(long)]
+    #[serde(default)]
+    pub since: Option<u64>,
+}
+
+#[derive(StructOpt, Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum JournalCommand {
+    /// Show the entries of the signing journal
+    Show(JournalShowOptions),
+
+    /// Verify the hash chain of the signing journal
+    Verify,
+}
+
+impl JournalRecord {
+    pub fn new(event: JournalEvent) -> Self {
+        JournalRecord {
+            event,
+            fingerprint: None,
+            txid: None,
+            outputs: vec![],
+            fee: None,
+            subjects: vec![],
+        }
+    }
+
+    pub fn sign(psbt: &PSBT, network: Network, fingerprint: Fingerprint, fee: u64) -> Self {
+        let tx = &psbt.global.unsigned_tx;
+        let outputs = tx
+            .output
+            .iter()
+            .map(|output| JournalOutput {
+                destination: Address::from_script(&output.script_pubkey, network)
+                    .map(|address| address.to_string())
+                    .unwrap_or_else(|| format!("{:x}", output.script_pubkey)),
+                amount: output.value,
+            })
+            .collect();
+        JournalRecord {
+            fingerprint: Some(fingerprint),
+            txid: Some(tx.txid()),
+            outputs,
+            fee: Some(fee),
+            ..JournalRecord::new(JournalEvent::Sign)
+        }
+    }
+
+    pub fn create_key(key: &PrivateMasterKeyJson) -> Self {
+        JournalRecord {
+            fingerprint: Some(key.fingerprint),
+            subjects: vec![key.name.clone()],
+            ..JournalRecord::new(JournalEvent::CreateKey)
+        }
+    }
+}
+
+impl JournalEntry {
+    fn compute_hash(&self) -> Result<sha256::Hash> {
+        let mut value = serde_json::to_value(self)?;
+        if let Value::Object(map) = &mut value {
+            map.remove("hash");
+        }
+        Ok(sha256::Hash::hash(&serde_json::to_vec(&value)?))
+    }
+}
+
+pub fn journal_path(datadir: &str, network: Network) -> Result<PathBuf> {
+    Ok(expand_tilde(datadir)?
+        .join(network.to_string())
+        .join(JOURNAL_FILE))
+}
+
+/// The journal is a json line per entry, or a single encrypted envelope of every entry when the
+/// datadir is encrypted, plain journals are read also when a key is given
+fn read_entries(path: &Path, encryption_key: Option<&StringEncoding>) -> Result<Vec<JournalEntry>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content = fs::read_to_string(path)?;
+    if let Ok(encrypted) = serde_json::from_str::<MaybeEncrypted<Vec<JournalEntry>>>(&content) {
+        let encryption_key = encryption_key.ok_or(Error::MissingEncryptionKey)?;
+        return match encrypted.decrypt_with(encryption_key)? {
+            MaybeEncrypted::Plain(entries) => Ok(entries),
+            MaybeEncrypted::Encrypted(_) => Err(Error::MaybeEncryptedWrongState),
+        };
+    }
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| serde_json::from_str(line).map_err(|_| Error::JournalTampered(i as u64)))
+        .collect()
+}
+
+/// Whether the journal of `network` is an encrypted envelope, readable only with the datadir key
+pub fn is_encrypted(datadir: &str, network: Network) -> Result<bool> {
+    let path = journal_path(datadir, network)?;
+    if !path.exists() {
+        return Ok(false);
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(matches!(
+        serde_json::from_str::<MaybeEncrypted<Vec<JournalEntry>>>(&content),
+        Ok(MaybeEncrypted::Encrypted(_))
+    ))
+}
+
+/// Check the journal of `network` can be read and appended to, to be called before an action
+/// that is journaled so that it doesn't happen without its entry
+pub fn check(
+    datadir: &str,
+    network: Network,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<()> {
+    read_entries(&journal_path(datadir, network)?, encryption_key)?;
+    Ok(())
+}
+
+/// Append `record` to the journal of `network`, chained to the last entry. With an
+/// `encryption_key` the whole journal is rewritten encrypted
+pub fn append(
+    datadir: &str,
+    network: Network,
+    record: JournalRecord,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<JournalEntry> {
+    let path = journal_path(datadir, network)?;
+    let mut entries = read_entries(&path, encryption_key)?;
+    let last = entries.last().cloned();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| Error::Generic(e.to_string()))?
+        .as_secs();
+    let mut entry = JournalEntry {
+        index: last.as_ref().map(|e| e.index + 1).unwrap_or(0),
+        timestamp,
+        record,
+        prev_hash: last
+            .map(|e| e.hash)
+            .unwrap_or_else(|| sha256::Hash::from_inner([0u8; 32])),
+        hash: sha256::Hash::from_inner([0u8; 32]),
+    };
+    entry.hash = entry.compute_hash()?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match encryption_key {
+        Some(encryption_key) => {
+            entries.push(entry.clone());
+            let encrypted = MaybeEncrypted::plain(entries).encrypt_with(encryption_key)?;
+            let temp = path.with_extension("jsonl.tmp");
+            let mut file = fs::File::create(&temp)?;
+            file.write_all(serde_json::to_string(&encrypted)?.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp, &path)?;
+        }
+        None => {
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            file.write_all(format!("{}\n", serde_json::to_string(&entry)?).as_bytes())?;
+        }
+    }
+    Ok(entry)
+}
+
+/// Check every entry is chained to the previous one, returning the last hash which should be
+/// noted elsewhere to detect the removal of the last entries
+pub fn verify(
+    datadir: &str,
+    network: Network,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<JournalVerifyOutput> {
+    let entries = read_entries(&journal_path(datadir, network)?, encryption_key)?;
+    let mut prev_hash = sha256::Hash::from_inner([0u8; 32]);
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.index != i as u64 || entry.prev_hash != prev_hash {
+            return Err(Error::JournalTampered(i as u64));
+        }
+        if entry.compute_hash()? != entry.hash {
+            return Err(Error::JournalTampered(i as u64));
+        }
+        prev_hash = entry.hash;
+    }
+    Ok(JournalVerifyOutput {
+        journal_file: journal_path(datadir, network)?,
+        entries: entries.len() as u64,
+        last_hash: entries.last().map(|e| e.hash),
+    })
+}
+
+pub fn show(
+    datadir: &str,
+    network: Network,
+    opt: &JournalShowOptions,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<JournalShowOutput> {
+    let entries = read_entries(&journal_path(datadir, network)?, encryption_key)?
+        .into_iter()
+        .filter(|e| opt.event.is_none_or(|event| e.record.event == event))
+        .filter(|e| {
+            opt.fingerprint
+                .is_none_or(|f| e.record.fingerprint == Some(f))
+        })
+        .filter(|e| opt.txid.is_none_or(|txid| e.record.txid == Some(txid)))
+        .filter(|e| opt.since.is_none_or(|since| e.timestamp >= since))
+        .collect();
+    Ok(JournalShowOutput { entries })
+}
+
+pub fn journal(
+    datadir: &str,
+    network: Network,
+    cmd: &JournalCommand,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<Value> {
+    Ok(match cmd {
+        JournalCommand::Show(opt) => {
+            serde_json::to_value(show(datadir, network, opt, encryption_key)?)?
+        }
+        JournalCommand::Verify => serde_json::to_value(verify(datadir, network, encryption_key)?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::journal::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_journal() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let network = Network::Testnet;
+        assert_eq!(verify(datadir, network, None).unwrap().entries, 0);
+
+        let fingerprint = Fingerprint::from_str("8f335370").unwrap();
+        let txid = Txid::from_inner([1u8; 32]);
+        let mut sign = JournalRecord::new(JournalEvent::Sign);
+        sign.fingerprint = Some(fingerprint);
+        sign.txid = Some(txid);
+        sign.outputs = vec![JournalOutput {
+            destination: "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn".to_string(),
+            amount: 10_000,
+        }];
+        sign.fee = Some(300);
+        let mut decrypt = JournalRecord::new(JournalEvent::Decrypt);
+        decrypt.subjects = vec!["/tmp/PRIVATE.json".to_string()];
+
+        let first = append(datadir, network, sign.clone(), None).unwrap();
+        let second = append(datadir, network, decrypt, None).unwrap();
+        append(datadir, network, sign, None).unwrap();
+        assert_eq!(first.index, 0);
+        assert_eq!(second.prev_hash, first.hash);
+        let output = verify(datadir, network, None).unwrap();
+        assert_eq!(output.entries, 3);
+        assert!(verify(datadir, Network::Regtest, None).is_ok());
+
+        let all = show(datadir, network, &JournalShowOptions::default(), None).unwrap();
+        assert_eq!(all.entries.len(), 3);
+        assert_eq!(output.last_hash, Some(all.entries[2].hash));
+        let opt = JournalShowOptions {
+            event: Some(JournalEvent::from_str("decrypt").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            show(datadir, network, &opt, None).unwrap().entries,
+            vec![second]
+        );
+        let opt = JournalShowOptions {
+            txid: Some(txid),
+            fingerprint: Some(fingerprint),
+            ..Default::default()
+        };
+        assert_eq!(show(datadir, network, &opt, None).unwrap().entries.len(), 2);
+        let opt = JournalShowOptions {
+            since: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(show(datadir, network, &opt, None)
+            .unwrap()
+            .entries
+            .is_empty());
+        assert!(JournalEvent::from_str("unknown").is_err());
+
+        // changing an amount or removing an entry breaks the chain
+        let path = journal_path(datadir, network).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replacen("10000", "20000", 1)).unwrap();
+        assert!(matches!(
+            verify(datadir, network, None),
+            Err(Error::JournalTampered(0))
+        ));
+        let lines: Vec<&str> = content.lines().collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(matches!(
+            verify(datadir, network, None),
+            Err(Error::JournalTampered(1))
+        ));
+        fs::write(&path, format!("{}\nnot json\n", lines[0])).unwrap();
+        assert!(matches!(
+            append(
+                datadir,
+                network,
+                JournalRecord::new(JournalEvent::Export),
+                None
+            ),
+            Err(Error::JournalTampered(1))
+        ));
+    }
+
+    #[test]
+    fn test_journal_encrypted() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let network = Network::Testnet;
+        let key = StringEncoding::new_base64(&[1u8; 32]);
+        let record = JournalRecord::new(JournalEvent::Export);
+
+        // a plain journal is converted when the first entry is appended with a key
+        let first = append(datadir, network, record.clone(), None).unwrap();
+        let second = append(datadir, network, record.clone(), Some(&key)).unwrap();
+        assert_eq!(second.prev_hash, first.hash);
+        let path = journal_path(datadir, network).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("export"));
+
+        assert!(matches!(
+            check(datadir, network, None),
+            Err(Error::MissingEncryptionKey)
+        ));
+        assert!(append(datadir, network, record.clone(), None).is_err());
+        let wrong_key = StringEncoding::new_base64(&[2u8; 32]);
+        assert!(check(datadir, network, Some(&wrong_key)).is_err());
+
+        append(datadir, network, record, Some(&key)).unwrap();
+        let output = verify(datadir, network, Some(&key)).unwrap();
+        assert_eq!(output.entries, 3);
+        let all = show(datadir, network, &JournalShowOptions::default(), Some(&key)).unwrap();
+        assert_eq!(all.entries[..2], [first, second]);
+    }
+}
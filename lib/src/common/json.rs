@@ -1,15 +1,19 @@
+use crate::common::config::NetworkConfig;
+use crate::common::journal::JournalEntry;
 use crate::common::mnemonic::Mnemonic;
-use crate::offline::sign::get_psbt_name;
+use crate::common::proprietary::{get_psbt_name, set_psbt_name};
+use crate::common::qr::QrFormat;
 use crate::{psbt_from_base64, psbt_to_base64, PSBT};
 use bitcoin::bech32::FromBase32;
+use bitcoin::hashes::sha256;
 use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint};
-use bitcoin::util::psbt::{raw, Map};
-use bitcoin::{bech32, Address, Amount, Network, OutPoint, Txid};
+use bitcoin::{bech32, Address, Amount, Network, OutPoint, Script, Txid};
 use bitcoincore_rpc::bitcoincore_rpc_json::WalletCreateFundedPsbtResult;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::{BTreeSet, HashSet};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::path::PathBuf;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -17,11 +21,26 @@ pub struct PrivateMasterKeyJson {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mnemonic: Option<Mnemonic>,
     pub xpub: ExtendedPubKey,
-    pub xprv: ExtendedPrivKey,
+    /// Missing if the key requires a passphrase, see `signing_xprv`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xprv: Option<ExtendedPrivKey>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dice: Option<Dice>,
     pub name: String,
     pub fingerprint: Fingerprint,
+    /// The key requires a BIP39 passphrase which is not saved, `xpub` and `fingerprint` are
+    /// derived with the passphrase and the xprv is not saved
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub passphrase: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bip85: Option<Bip85Origin>,
+}
+
+/// The master key and the path a BIP85 child key is derived from
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Bip85Origin {
+    pub parent_fingerprint: Fingerprint,
+    pub path: DerivationPath,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -29,6 +48,17 @@ pub struct Dice {
     pub launches: String,
     pub faces: u32,
     pub value: String,
+    /// `value` has been XORed with entropy from the machine RNG
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub xor_random: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EntropyChecks {
+    pub chi_square: f64,
+    pub longest_run: usize,
+    pub longest_sequence: usize,
+    pub failures: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -38,6 +68,10 @@ pub struct MasterKeyOutput {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub public_file: Option<PathBuf>,
     pub public_qr_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub public_ur_files: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entropy_checks: Option<EntropyChecks>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -57,6 +91,8 @@ pub struct PsbtJsonOutput {
     pub file: PathBuf,
     pub signatures: String,
     pub qr_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub ur_files: Vec<PathBuf>,
     pub unsigned_txid: Txid,
 }
 
@@ -76,6 +112,45 @@ pub struct WalletSignature {
     pub signature: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageSignatureOutput {
+    pub address: Address,
+    pub message: String,
+    /// Missing if the BIP322 PSBT must still be signed by the cosigners
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<DerivationPath>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psbt_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReservesOutput {
+    pub message: String,
+    pub coins: Vec<ReservesCoin>,
+    /// Total of the coins signed and still unspent
+    pub proven: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReservesCoin {
+    pub outpoint: OutPoint,
+    pub amount: u64,
+    pub signed: bool,
+    pub unspent: bool,
+}
+
+/// An entry of a UTXO set snapshot
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub script_pubkey: Script,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct WalletIndexes {
     pub main: u32,
@@ -100,6 +175,30 @@ pub struct GetAddressOutput {
     pub path: DerivationPath,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub qr_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConfigOutput {
+    pub config_file: PathBuf,
+    pub datadir: String,
+    pub network: Network,
+    pub config: NetworkConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JournalShowOutput {
+    pub entries: Vec<JournalEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JournalVerifyOutput {
+    pub journal_file: PathBuf,
+    pub entries: u64,
+    /// Hash of the last entry, to be noted elsewhere to detect the removal of the last entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_hash: Option<sha256::Hash>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -115,16 +214,153 @@ pub struct CreateTxOutput {
     pub funded_psbt: PsbtJson,
     pub address_reused: HashSet<Address>,
     pub qr_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub ur_files: Vec<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct CreateWalletOutput {
     pub qr_files: Vec<PathBuf>,
+    #[serde(default)]
+    pub ur_files: Vec<PathBuf>,
     pub wallet_file: PathBuf,
     pub wallet: WalletJson,
     pub signature: Option<WalletSignature>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPayload {
+    Psbt,
+    Wallet,
+    Xpub,
+    Address,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScanOutput {
+    pub kind: ScanPayload,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SlipGroupShares {
+    pub member_threshold: u8,
+    pub shares: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SplitOutput {
+    pub key_name: String,
+    pub fingerprint: Fingerprint,
+    pub group_threshold: u8,
+    pub groups: Vec<SlipGroupShares>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Bip85Output {
+    pub path: DerivationPath,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<MasterKeyOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entropy: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VerifyBackupOutput {
+    pub key_name: String,
+    pub fingerprint: Fingerprint,
+    pub verified: bool,
+    /// Unix time in seconds of the last successful verification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_verified: Option<u64>,
+}
+
+/// Content of the file recording backup verifications in the key directory
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BackupVerification {
+    pub last_verified: u64,
+}
+
+/// Manifest of a directory used to move items between the online and the offline machine
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    pub network: Network,
+    pub items: Vec<ManifestItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ManifestItem {
+    pub kind: ItemKind,
+    pub name: String,
+    /// File name relative to the manifest directory
+    pub file: String,
+    pub sha256: sha256::Hash,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+    Xpub,
+    Wallet,
+    Signature,
+    Psbt,
+}
+
+impl ItemKind {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ItemKind::Xpub => "txt",
+            ItemKind::Wallet | ItemKind::Signature => "json",
+            ItemKind::Psbt => "psbt",
+        }
+    }
+}
+
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ItemKind::Xpub => "xpub",
+            ItemKind::Wallet => "wallet",
+            ItemKind::Signature => "signature",
+            ItemKind::Psbt => "psbt",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExportOutput {
+    pub manifest_file: PathBuf,
+    pub items: Vec<ManifestItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ImportOutput {
+    pub imported: Vec<PathBuf>,
+    /// Files of the items already present and equal
+    pub unchanged: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReencryptOutput {
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BackupSheetOutput {
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MergeUrOutput {
+    pub ur_type: String,
+    pub hex: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub struct ListOutput {
     pub keys: Vec<MasterKeyOutput>,
@@ -148,6 +384,33 @@ pub struct Coin {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ErrorJson {
     pub error: String,
+    /// Stable identifier of the error, `error` message may change between versions
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub category: ErrorCategory,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_index: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// Wrong or missing arguments
+    UserInput,
+    /// A file or a PSBT cannot be parsed or is not consistent
+    CorruptedFile,
+    /// The request is valid but refused by firma checks
+    PolicyViolation,
+    /// Error returned by the bitcoin node
+    Node,
+    /// Encryption, key derivation or signature failures
+    Crypto,
+    /// Errors from the environment like I/O
+    #[default]
+    Internal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -181,6 +444,23 @@ pub struct PsbtPrettyPrint {
     pub info: Vec<String>,
     pub psbt_file: PathBuf,
     pub balances: String,
+    pub metadata: PsbtMetadata,
+}
+
+/// Metadata stored in the firma proprietary keys of the PSBT
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct PsbtMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creator: Option<String>,
+    /// Unix time in seconds of the creation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallet_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
@@ -201,6 +481,11 @@ pub struct Size {
 pub struct SavePSBTOptions {
     pub psbt: StringEncoding,
     pub qr_version: i16,
+    #[serde(default)]
+    pub qr_format: QrFormat,
+    /// Optional datadir encryption key
+    #[serde(default)]
+    pub encryption_key: Option<StringEncoding>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -216,6 +501,8 @@ pub enum StringEncoding {
     Base64(String),
     Hex(String),
     Bech32(String),
+    /// Utf-8 text used as password, the encryption key is derived with a KDF
+    Password(String),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -247,6 +534,7 @@ impl StringEncoding {
                 let (_, vec_u5) = bech32::decode(s)?;
                 Vec::<u8>::from_base32(&vec_u5)?
             }
+            StringEncoding::Password(s) => s.as_bytes().to_vec(),
         })
     }
 
@@ -265,34 +553,25 @@ impl StringEncoding {
             StringEncoding::Base64(_) => "base64",
             StringEncoding::Hex(_) => "hex",
             StringEncoding::Bech32(_) => "bech32",
+            StringEncoding::Password(_) => "password",
         }
         .to_string()
     }
 }
 
-pub fn get_name_key() -> raw::Key {
-    raw::Key {
-        type_value: 0xFC,
-        key: b"name".to_vec(),
-    }
-}
-
 pub fn psbt_from_rpc(psbt: &WalletCreateFundedPsbtResult, name: &str) -> crate::Result<PSBT> {
     let (_, mut psbt_with_name) = psbt_from_base64(&psbt.psbt)?;
-
-    let pair = raw::Pair {
-        key: get_name_key(),
-        value: name.as_bytes().to_vec(),
-    };
-    psbt_with_name.global.insert_pair(pair)?;
+    set_psbt_name(&mut psbt_with_name, name)?;
     Ok(psbt_with_name)
 }
 
-impl From<&PSBT> for PsbtJson {
-    fn from(psbt: &PSBT) -> Self {
+impl TryFrom<&PSBT> for PsbtJson {
+    type Error = crate::Error;
+
+    fn try_from(psbt: &PSBT) -> Result<Self, Self::Error> {
         let (_, base64) = psbt_to_base64(psbt);
-        let name = get_psbt_name(psbt).expect("PSBT without name"); //TODO
-        PsbtJson { psbt: base64, name }
+        let name = get_psbt_name(psbt)?.ok_or(crate::Error::MissingName)?;
+        Ok(PsbtJson { psbt: base64, name })
     }
 }
 
@@ -313,33 +592,63 @@ impl PrivateMasterKeyJson {
         network: Network,
         mnemonic: &Mnemonic,
         name: &str,
+        passphrase: Option<&str>,
     ) -> crate::Result<PrivateMasterKeyJson> {
         let secp = bitcoin::secp256k1::Secp256k1::signing_only();
-        let seed = mnemonic.to_seed(None);
+        let passphrase = passphrase.filter(|p| !p.is_empty());
+        let seed = mnemonic.to_seed(passphrase);
 
         let xprv = ExtendedPrivKey::new_master(network, &seed.0)?;
         let xpub = ExtendedPubKey::from_private(&secp, &xprv);
 
         Ok(PrivateMasterKeyJson {
             mnemonic: Some(mnemonic.clone()),
-            xprv,
+            // the passphrase is not saved, neither the key derived with it
+            xprv: Some(xprv).filter(|_| passphrase.is_none()),
             xpub,
             dice: None,
             name: name.to_string(),
             fingerprint: xpub.fingerprint(),
+            passphrase: passphrase.is_some(),
+            bip85: None,
         })
     }
 
+    /// The master private key to sign with, derived again from the mnemonic if a `passphrase`
+    /// is given, fails if the resulting fingerprint doesn't match the saved one
+    pub fn signing_xprv(&self, passphrase: Option<&str>) -> crate::Result<ExtendedPrivKey> {
+        let passphrase = passphrase.filter(|p| !p.is_empty());
+        match (self.passphrase, passphrase) {
+            (false, None) => self.xprv.ok_or(crate::Error::MissingPassphrase),
+            (true, None) => Err(crate::Error::MissingPassphrase),
+            (_, Some(passphrase)) => {
+                let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+                let mnemonic = self
+                    .mnemonic
+                    .as_ref()
+                    .ok_or(crate::Error::MissingMnemonic)?;
+                let seed = mnemonic.to_seed(Some(passphrase));
+                let xprv = ExtendedPrivKey::new_master(self.xpub.network, &seed.0)?;
+                if ExtendedPubKey::from_private(&secp, &xprv).fingerprint() != self.fingerprint {
+                    return Err(crate::Error::PassphraseMismatch);
+                }
+                Ok(xprv)
+            }
+        }
+    }
+
     pub fn from_xprv(xprv: ExtendedPrivKey, name: &str) -> Self {
         let secp = bitcoin::secp256k1::Secp256k1::signing_only();
         let xpub = ExtendedPubKey::from_private(&secp, &xprv);
         PrivateMasterKeyJson {
-            xprv,
+            xprv: Some(xprv),
             xpub,
             mnemonic: None,
             dice: None,
             name: name.to_string(),
             fingerprint: xpub.fingerprint(),
+            passphrase: false,
+            bip85: None,
         }
     }
 }
@@ -364,8 +673,22 @@ impl_try_into!(BalanceOutput);
 impl_try_into!(ListCoinsOutput);
 impl_try_into!(GetAddressOutput);
 impl_try_into!(ListOutput);
+impl_try_into!(MergeUrOutput);
+impl_try_into!(ScanOutput);
+impl_try_into!(BackupSheetOutput);
+impl_try_into!(SplitOutput);
+impl_try_into!(Bip85Output);
+impl_try_into!(ReencryptOutput);
+impl_try_into!(VerifyBackupOutput);
+impl_try_into!(ExportOutput);
+impl_try_into!(ConfigOutput);
+impl_try_into!(JournalShowOutput);
+impl_try_into!(JournalVerifyOutput);
+impl_try_into!(ImportOutput);
 impl_try_into!(WalletSignature);
 impl_try_into!(VerifyWalletResult);
+impl_try_into!(MessageSignatureOutput);
+impl_try_into!(ReservesOutput);
 
 #[cfg(test)]
 mod tests {
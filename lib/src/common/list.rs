@@ -7,7 +7,7 @@ use bitcoin::Network;
 use log::{debug, warn};
 use serde::{Deserialize, Serialize};
 use std::iter::once;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug, Serialize, Deserialize)]
@@ -39,12 +39,14 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                     let secp = Secp256k1::verification_only();
                     path.push("descriptor.json");
                     debug!("try to read wallet {:?}", path);
-                    match read_wallet(&path) {
-                        Ok(wallet) => {
+                    match read_with_keys(&path, &opt.encryption_keys, read_wallet) {
+                        Ok((wallet, encryption_key)) => {
                             let wallet_path = path.clone();
-                            let qr_files = read_qrs(&path)?;
+                            let qr_files = read_qrs(&path, "qr")?;
+                            let ur_files = read_qrs(&path, "ur")?;
                             let mut wallet_output = CreateWalletOutput {
                                 qr_files, //TODO check if file exist?
+                                ur_files,
                                 wallet,
                                 wallet_file: path.clone(),
                                 signature: None,
@@ -56,7 +58,12 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                             if !opt.verify_wallets_signatures {
                                 list.wallets.push(wallet_output);
                             } else {
-                                match verify_wallet_internal(&wallet_path, &signature_path, &secp) {
+                                match verify_wallet_internal(
+                                    &wallet_path,
+                                    &signature_path,
+                                    encryption_key,
+                                    &secp,
+                                ) {
                                     Ok(result) => {
                                         if result.verified {
                                             wallet_output.signature = Some(result.signature);
@@ -77,17 +84,19 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                 Kind::PSBT => {
                     path.push("psbt.json");
                     debug!("try to read psbt {:?}", path);
-                    match read_psbt_json(&path) {
-                        Ok(psbt_json) => {
+                    match read_with_keys(&path, &opt.encryption_keys, read_psbt_json) {
+                        Ok((psbt_json, _)) => {
                             let (_, psbt) = psbt_from_base64(&psbt_json.psbt)?;
                             let pretty = pretty_print(&psbt, network, &[])?;
-                            let qr_files = read_qrs(&path)?;
+                            let qr_files = read_qrs(&path, "qr")?;
+                            let ur_files = read_qrs(&path, "ur")?;
                             let psbt_out = PsbtJsonOutput {
                                 psbt: psbt_json,
                                 signatures: signatures_needed(&pretty.inputs),
                                 unsigned_txid: psbt.global.unsigned_tx.txid(),
                                 file: path.clone(),
                                 qr_files,
+                                ur_files,
                             };
                             list.psbts.push(psbt_out);
                         }
@@ -104,12 +113,15 @@ pub fn list(datadir: &str, network: Network, opt: &ListOptions) -> Result<ListOu
                         debug!("using encryption_key {:?}", encryption_key);
                         match read_key(&path, encryption_key) {
                             Ok(key) => {
-                                let public_qr_files = read_qrs(&path)?;
+                                let public_qr_files = read_qrs(&path, "qr")?;
+                                let public_ur_files = read_qrs(&path, "ur")?;
                                 let key = MasterKeyOutput {
                                     key,
                                     private_file: path.clone(),
                                     public_file: None,
                                     public_qr_files, //TODO populate if they exists
+                                    public_ur_files,
+                                    entropy_checks: None,
                                 };
                                 list.keys.push(key);
                                 debug!("key decrypted");
@@ -138,9 +150,26 @@ fn signatures_needed(inputs: &[TxIn]) -> String {
     }
 }
 
-fn read_qrs(path: &PathBuf) -> Result<Vec<PathBuf>> {
+/// Read `path` with the first of `encryption_keys` that works, plain files are read with any key
+fn read_with_keys<'a, T>(
+    path: &Path,
+    encryption_keys: &'a [StringEncoding],
+    read: fn(&Path, Option<&StringEncoding>) -> Result<T>,
+) -> Result<(T, Option<&'a StringEncoding>)> {
+    let mut last_error = Error::MissingEncryptionKey;
+    for encryption_key in encryption_keys.iter().map(Option::Some).chain(once(None)) {
+        match read(path, encryption_key) {
+            Ok(value) => return Ok((value, encryption_key)),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// list files in the `dir` sibling of `path`, used for "qr" and "ur" dirs
+fn read_qrs(path: &Path, dir: &str) -> Result<Vec<PathBuf>> {
     let mut path = path.parent().expect("root has no parent").to_path_buf();
-    path.push("qr");
+    path.push(dir);
     let mut vec = vec![];
     if path.is_dir() {
         for entry in std::fs::read_dir(path)? {
@@ -155,7 +184,7 @@ fn read_qrs(path: &PathBuf) -> Result<Vec<PathBuf>> {
 mod tests {
     use crate::common::list::{list, ListOptions};
     use crate::offline::random::RandomOptions;
-    use crate::Kind;
+    use crate::{read_wallet, Context, Kind, StringEncoding, WalletJson};
     use bitcoin::Network;
     use tempfile::TempDir;
 
@@ -183,4 +212,54 @@ mod tests {
             .iter()
             .any(|key| key.key.name == rand_opts.key_name));
     }
+
+    #[test]
+    fn test_list_encrypted_wallets() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let encryption_key = StringEncoding::new_base64(&[3u8; 32]);
+        let descriptor = "wsh(multi(1,tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU/0/*))";
+        let mut context = Context {
+            firma_datadir: datadir.to_string(),
+            network: Network::Testnet,
+            wallet_name: "encrypted".to_string(),
+            encryption_key: Some(encryption_key.clone()),
+        };
+        let wallet = WalletJson {
+            name: context.wallet_name.clone(),
+            descriptor: descriptor.to_string(),
+            fingerprints: Default::default(),
+            required_sig: 1,
+            created_at_height: 0,
+        };
+        let path = context.save_wallet(&wallet).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("tpub"));
+        assert!(read_wallet(&path, None).is_err());
+        assert_eq!(read_wallet(&path, Some(&encryption_key)).unwrap(), wallet);
+
+        context.wallet_name = "plain".to_string();
+        context.encryption_key = None;
+        let plain = WalletJson {
+            name: context.wallet_name.clone(),
+            ..wallet
+        };
+        let path = context.save_wallet(&plain).unwrap();
+        assert_eq!(read_wallet(&path, Some(&encryption_key)).unwrap(), plain);
+
+        let mut opt = ListOptions {
+            kind: Kind::Wallet,
+            encryption_keys: vec![],
+            verify_wallets_signatures: false,
+        };
+        assert_eq!(
+            list(datadir, Network::Testnet, &opt).unwrap().wallets.len(),
+            1
+        );
+        opt.encryption_keys = vec![encryption_key];
+        assert_eq!(
+            list(datadir, Network::Testnet, &opt).unwrap().wallets.len(),
+            2
+        );
+    }
 }
@@ -55,30 +55,35 @@ impl FromStr for Mnemonic {
 
     /// Parse a string into a mnemonic, if valid according to the rules
     fn from_str(s: &str) -> Result<Mnemonic, Error> {
-        let words: Vec<_> = s.split(' ').collect();
-        if words.len() < 6 || words.len() % 6 != 0 {
-            return Err(Error::MnemonicNotDivisibleBy6);
-        }
-        let mut data = Vec::new();
-        let mut writer = BitStreamWriter::new(&mut data);
-        for word in &words {
-            if let Ok(idx) = WORDS.binary_search(word) {
-                writer.write(idx as u64, 11).unwrap();
-            } else {
-                return Err(Error::UnknownWord);
-            }
-        }
-        writer.flush().unwrap();
-        let l = data.len();
-        let (payload, checksum) = data.split_at(l - if l > 33 { 2 } else { 1 });
-        if Self::checksum(payload).as_slice() != checksum {
-            return Err(Error::ChecksumFailed);
-        }
-
+        decode(s)?;
         Ok(Mnemonic(s.to_string()))
     }
 }
 
+/// returns the data encoded in the mnemonic `s` if checksum is valid
+fn decode(s: &str) -> Result<Vec<u8>, Error> {
+    let words: Vec<_> = s.split(' ').collect();
+    if words.len() < 6 || words.len() % 6 != 0 {
+        return Err(Error::MnemonicNotDivisibleBy6);
+    }
+    let mut data = Vec::new();
+    let mut writer = BitStreamWriter::new(&mut data);
+    for word in &words {
+        if let Ok(idx) = WORDS.binary_search(word) {
+            writer.write(idx as u64, 11).unwrap();
+        } else {
+            return Err(Error::UnknownWord);
+        }
+    }
+    writer.flush().unwrap();
+    let l = data.len();
+    let (payload, checksum) = data.split_at(l - if l > 33 { 2 } else { 1 });
+    if Mnemonic::checksum(payload).as_slice() != checksum {
+        return Err(Error::ChecksumFailed);
+    }
+    Ok(payload.to_vec())
+}
+
 impl Display for Mnemonic {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -131,6 +136,11 @@ impl Mnemonic {
         Self::new(random.as_slice())
     }
 
+    /// the data encoded in this mnemonic, the inverse of `Mnemonic::new`
+    pub fn to_entropy(&self) -> Vec<u8> {
+        decode(&self.0).expect("checked at creation")
+    }
+
     /// create a mnemonic for some data
     pub fn new(data: &[u8]) -> Result<Mnemonic, Error> {
         if data.len() % 4 != 0 {
@@ -201,6 +211,7 @@ mod test {
                 mnemonic.to_string(),
                 Mnemonic::new(data.as_slice()).unwrap().to_string()
             );
+            assert_eq!(mnemonic.to_entropy(), data);
             assert_eq!(seed.0, Vec::from_hex(values[2].as_str().unwrap()).unwrap());
 
             if values.len() == 4 {
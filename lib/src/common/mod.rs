@@ -9,13 +9,23 @@ use std::fs::OpenOptions;
 use std::io::BufWriter;
 use std::io::Write;
 
+pub mod bip21;
 pub mod cmd;
+pub mod compact_wallet;
+pub mod config;
+pub mod dispatcher;
 pub mod error;
+pub mod exchange;
 pub mod file;
+pub mod journal;
 pub mod json;
 pub mod list;
 pub mod mnemonic;
+pub mod proprietary;
 pub mod qr;
+pub mod ur;
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
 
 static LOGGER: SimpleLogger = SimpleLogger;
 
@@ -66,6 +76,25 @@ pub fn psbt_from_base64(s: &str) -> Result<(Vec<u8>, PSBT)> {
     Ok((bytes, psbt))
 }
 
+/// Parse a PSBT given as BIP174 binary, base64 or hex
+pub fn psbt_from_bytes(content: &[u8]) -> Result<PSBT> {
+    if content.starts_with(PSBT_MAGIC) {
+        return Ok(deserialize(content)?);
+    }
+    psbt_from_str(std::str::from_utf8(content)?)
+}
+
+/// Parse a PSBT given as base64 or hex string
+pub fn psbt_from_str(s: &str) -> Result<PSBT> {
+    let s = s.trim();
+    let hex_magic = hex::encode(PSBT_MAGIC);
+    if s.len() > 10 && s.as_bytes()[..10].eq_ignore_ascii_case(hex_magic.as_bytes()) {
+        Ok(deserialize(&hex::decode(s)?)?)
+    } else {
+        Ok(psbt_from_base64(s)?.1)
+    }
+}
+
 pub fn psbt_to_base64(psbt: &PSBT) -> (Vec<u8>, String) {
     let bytes = serialize(psbt);
     let string = base64::encode(&bytes);
@@ -166,7 +195,11 @@ pub fn check_compatibility(n1: Network, n2: Network) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{check_compatibility, strip_witness};
+    use crate::common::proprietary::get_psbt_name;
+    use crate::{
+        check_compatibility, psbt_from_bytes, psbt_from_str, psbt_to_base64, read_psbt,
+        save_psbt_binary, strip_witness,
+    };
     use bitcoin::consensus::deserialize;
     use bitcoin::{Network, Transaction};
 
@@ -179,6 +212,39 @@ mod tests {
         assert!(stripped.get_weight() < segwit_tx.get_weight());
     }
 
+    #[test]
+    fn test_psbt_formats() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let json_file = temp_dir.path().join("psbt.json");
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.signed.json");
+        std::fs::write(&json_file, &bytes[..]).unwrap();
+        let psbt = read_psbt(&json_file, None).unwrap();
+        let (binary, base64) = psbt_to_base64(&psbt);
+
+        let contents = vec![
+            binary.clone(),
+            format!("{}\n", base64).into_bytes(),
+            hex::encode(&binary).into_bytes(),
+            hex::encode(&binary).to_uppercase().into_bytes(),
+        ];
+        for content in contents {
+            let file = temp_dir.path().join("psbt.any");
+            std::fs::write(&file, &content).unwrap();
+            assert_eq!(read_psbt(&file, None).unwrap(), psbt);
+            assert_eq!(psbt_from_bytes(&content).unwrap(), psbt);
+        }
+
+        let binary_file = temp_dir.path().join("psbt.psbt");
+        save_psbt_binary(&psbt, &binary_file).unwrap();
+        assert_eq!(std::fs::read(&binary_file).unwrap(), binary);
+        assert_eq!(
+            get_psbt_name(&psbt_from_str(&base64).unwrap()).unwrap(),
+            get_psbt_name(&psbt).unwrap()
+        );
+        assert!(psbt_from_str("not a psbt").is_err());
+        assert!(psbt_from_str("aéééééé").is_err());
+    }
+
     #[test]
     fn test_compatible_networks() {
         assert!(check_compatibility(Network::Bitcoin, Network::Bitcoin).is_ok());
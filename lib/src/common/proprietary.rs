@@ -0,0 +1,155 @@
+use crate::*;
+use bitcoin::util::psbt::{raw, Map};
+use std::convert::TryInto;
+
+/// BIP174 key type of proprietary global keys
+pub const PROPRIETARY_TYPE: u8 = 0xFC;
+
+/// Identifier prefix of the proprietary keys written by firma
+pub const FIRMA_IDENTIFIER: &[u8] = b"firma";
+
+/// Subtypes of the firma proprietary keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Subtype {
+    Name = 0x00,
+    Creator = 0x01,
+    CreatedAt = 0x02,
+    WalletName = 0x03,
+    Memo = 0x04,
+}
+
+/// Proprietary key as specified in BIP174:
+/// `<compact size identifier len><identifier><compact size subtype>` with empty key data
+pub fn proprietary_key(subtype: Subtype) -> raw::Key {
+    let mut key = vec![FIRMA_IDENTIFIER.len() as u8];
+    key.extend(FIRMA_IDENTIFIER);
+    key.push(subtype as u8);
+    raw::Key {
+        type_value: PROPRIETARY_TYPE,
+        key,
+    }
+}
+
+/// Key used by previous versions to store the PSBT name, not a valid proprietary key
+pub fn legacy_name_key() -> raw::Key {
+    raw::Key {
+        type_value: PROPRIETARY_TYPE,
+        key: b"name".to_vec(),
+    }
+}
+
+fn get_value(psbt: &PSBT, subtype: Subtype) -> Option<&Vec<u8>> {
+    psbt.global.unknown.get(&proprietary_key(subtype))
+}
+
+fn get_string(psbt: &PSBT, subtype: Subtype) -> Result<Option<String>> {
+    let value = get_value(psbt, subtype).or_else(|| match subtype {
+        Subtype::Name => psbt.global.unknown.get(&legacy_name_key()),
+        _ => None,
+    });
+    value
+        .map(|v| {
+            std::str::from_utf8(v)
+                .map(|s| s.to_string())
+                .map_err(|_| Error::PSBTBadMetadata(format!("{:?} not utf8", subtype)))
+        })
+        .transpose()
+}
+
+/// Set the value of the proprietary key `subtype`, replacing the existing one
+pub fn set_value(psbt: &mut PSBT, subtype: Subtype, value: Vec<u8>) -> Result<()> {
+    let key = proprietary_key(subtype);
+    psbt.global.unknown.remove(&key);
+    psbt.global.insert_pair(raw::Pair { key, value })?;
+    Ok(())
+}
+
+/// extract the PSBT name from the firma proprietary key, or the legacy one, if present
+pub fn get_psbt_name(psbt: &PSBT) -> Result<Option<String>> {
+    get_string(psbt, Subtype::Name)
+}
+
+pub fn set_psbt_name(psbt: &mut PSBT, name: &str) -> Result<()> {
+    set_value(psbt, Subtype::Name, name.as_bytes().to_vec())
+}
+
+pub fn get_psbt_metadata(psbt: &PSBT) -> Result<PsbtMetadata> {
+    let created_at = match get_value(psbt, Subtype::CreatedAt) {
+        Some(v) => {
+            let bytes: [u8; 8] = v[..]
+                .try_into()
+                .map_err(|_| Error::PSBTBadMetadata("CreatedAt not 8 bytes".to_string()))?;
+            Some(u64::from_le_bytes(bytes))
+        }
+        None => None,
+    };
+    Ok(PsbtMetadata {
+        name: get_psbt_name(psbt)?,
+        creator: get_string(psbt, Subtype::Creator)?,
+        created_at,
+        wallet_name: get_string(psbt, Subtype::WalletName)?,
+        memo: get_string(psbt, Subtype::Memo)?,
+    })
+}
+
+/// Set the non-empty fields of `metadata` in the PSBT proprietary keys
+pub fn set_psbt_metadata(psbt: &mut PSBT, metadata: &PsbtMetadata) -> Result<()> {
+    let strings = vec![
+        (Subtype::Name, &metadata.name),
+        (Subtype::Creator, &metadata.creator),
+        (Subtype::WalletName, &metadata.wallet_name),
+        (Subtype::Memo, &metadata.memo),
+    ];
+    for (subtype, value) in strings {
+        if let Some(value) = value {
+            set_value(psbt, subtype, value.as_bytes().to_vec())?;
+        }
+    }
+    if let Some(created_at) = metadata.created_at {
+        set_value(psbt, Subtype::CreatedAt, created_at.to_le_bytes().to_vec())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::proprietary::*;
+    use bitcoin::consensus::{deserialize, serialize};
+
+    #[test]
+    fn test_proprietary_keys() {
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.signed.json");
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        let (_, mut psbt) = psbt_from_base64(&psbt_json.psbt).unwrap();
+        psbt.global.unknown.clear();
+        assert_eq!(get_psbt_metadata(&psbt).unwrap(), PsbtMetadata::default());
+
+        psbt.global
+            .insert_pair(raw::Pair {
+                key: legacy_name_key(),
+                value: b"legacy".to_vec(),
+            })
+            .unwrap();
+        assert_eq!(get_psbt_name(&psbt).unwrap(), Some("legacy".to_string()));
+
+        let metadata = PsbtMetadata {
+            name: Some("new".to_string()),
+            creator: Some("firma".to_string()),
+            created_at: Some(1_600_000_000),
+            wallet_name: Some("wallet".to_string()),
+            memo: Some("rent".to_string()),
+        };
+        set_psbt_metadata(&mut psbt, &metadata).unwrap();
+        let psbt: PSBT = deserialize(&serialize(&psbt)).unwrap();
+        assert_eq!(get_psbt_metadata(&psbt).unwrap(), metadata);
+        let key = proprietary_key(Subtype::Memo);
+        assert_eq!(key.key, b"\x05firma\x04".to_vec());
+
+        let mut psbt = psbt;
+        set_value(&mut psbt, Subtype::Name, vec![0xff]).unwrap();
+        assert!(get_psbt_name(&psbt).is_err());
+        set_value(&mut psbt, Subtype::CreatedAt, vec![1]).unwrap();
+        assert!(get_psbt_metadata(&psbt).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::*;
 use log::info;
 use qr_code::structured::SplittedQr;
+use qr_code::QrCode;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::Write;
@@ -12,7 +13,12 @@ use std::{fs, io};
 pub enum QrError {}
 
 /// path contains up to the filename (use dummy value) that will be replaced by qr file name
-pub fn save_qrs(bytes: Vec<u8>, qr_dir: PathBuf, version: i16) -> Result<Vec<PathBuf>> {
+pub fn save_qrs(
+    bytes: Vec<u8>,
+    qr_dir: PathBuf,
+    version: i16,
+    format: QrFormat,
+) -> Result<Vec<PathBuf>> {
     match version {
         0 => return Ok(vec![]),
         5..=20 => info!("save_qrs data len:{} version:{}", bytes.len(), version),
@@ -48,13 +54,13 @@ pub fn save_qrs(bytes: Vec<u8>, qr_dir: PathBuf, version: i16) -> Result<Vec<Pat
     qr_file.push("dummy");
     for (i, qr) in qrs.iter().enumerate() {
         if single {
-            qr_file.set_file_name("qr.bmp");
+            qr_file.set_file_name("qr");
         } else {
-            qr_file.set_file_name(&format!("qr-{}.bmp", i));
+            qr_file.set_file_name(format!("qr-{}", i));
         }
-        let qr_data = qr.to_bmp().mul(4)?.add_white_border(12)?;
+        qr_file.set_extension(format.extension());
         info!("Saving qr in {:?}", &qr_file);
-        qr_data.write(File::create(&qr_file)?)?;
+        write_qr(qr, &qr_file, format)?;
 
         wallet_qr_files.push(qr_file.clone());
 
@@ -71,6 +77,110 @@ pub fn save_qrs(bytes: Vec<u8>, qr_dir: PathBuf, version: i16) -> Result<Vec<Pat
     Ok(wallet_qr_files)
 }
 
+/// Pixels per QR module in image files
+const MODULE_PIXELS: u32 = 4;
+
+/// Quiet zone around the QR code, in modules
+const BORDER_MODULES: u32 = 3;
+
+/// write `qr` in `path` as image in the given `format`
+pub fn write_qr(qr: &QrCode, path: &PathBuf, format: QrFormat) -> Result<()> {
+    match format {
+        QrFormat::Bmp => {
+            let bmp = qr
+                .to_bmp()
+                .mul(MODULE_PIXELS as u8)?
+                .add_white_border((BORDER_MODULES * MODULE_PIXELS) as u8)?;
+            bmp.write(File::create(path)?)?;
+        }
+        QrFormat::Png => {
+            let width = qr.width() as u32;
+            let modules = qr.to_vec();
+            let side = (width + BORDER_MODULES * 2) * MODULE_PIXELS;
+            let image = image::GrayImage::from_fn(side, side, |x, y| {
+                let x = (x / MODULE_PIXELS) as i64 - BORDER_MODULES as i64;
+                let y = (y / MODULE_PIXELS) as i64 - BORDER_MODULES as i64;
+                let inside = (0..width as i64).contains(&x) && (0..width as i64).contains(&y);
+                let dark = inside && modules[(y as u32 * width + x as u32) as usize];
+                image::Luma([if dark { 0u8 } else { 255u8 }])
+            });
+            image.save_with_format(path, image::ImageFormat::Png)?;
+        }
+        QrFormat::Svg => fs::write(path, to_svg(qr))?,
+    }
+    Ok(())
+}
+
+/// side of `qr` including the quiet zone and the coordinates of its dark modules, in modules
+pub fn dark_modules(qr: &QrCode) -> (usize, Vec<(usize, usize)>) {
+    let width = qr.width();
+    let border = BORDER_MODULES as usize;
+    let modules = qr
+        .to_vec()
+        .iter()
+        .enumerate()
+        .filter(|(_, dark)| **dark)
+        .map(|(i, _)| (i % width + border, i / width + border))
+        .collect();
+    (width + border * 2, modules)
+}
+
+/// svg path of `qr` made of one square per dark module, the unit is a module
+pub fn to_svg_path(qr: &QrCode) -> (usize, String) {
+    let (side, modules) = dark_modules(qr);
+    let path = modules
+        .iter()
+        .map(|(x, y)| format!("M{},{}h1v1h-1z", x, y))
+        .collect();
+    (side, path)
+}
+
+/// svg of `qr`, the unit is a module
+pub fn to_svg(qr: &QrCode) -> String {
+    let (side, path) = to_svg_path(qr);
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {s} {s}\" shape-rendering=\"crispEdges\">\
+         <rect width=\"{s}\" height=\"{s}\" fill=\"#fff\"/><path d=\"{p}\" fill=\"#000\"/></svg>\n",
+        s = side,
+        p = path
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Bmp,
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            QrFormat::Bmp => "bmp",
+            QrFormat::Png => "png",
+            QrFormat::Svg => "svg",
+        }
+    }
+}
+
+impl FromStr for QrFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bmp" => Ok(QrFormat::Bmp),
+            "png" => Ok(QrFormat::Png),
+            "svg" => Ok(QrFormat::Svg),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("({}) valid values are: bmp, png, svg", s),
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum QrMode {
     Text { inverted: bool },
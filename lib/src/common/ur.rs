@@ -0,0 +1,569 @@
+use crate::common::qr::{write_qr, QrFormat};
+use crate::*;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::bip32::{ChainCode, ChildNumber, ExtendedPubKey, Fingerprint};
+use bitcoin::{secp256k1, Network, PublicKey};
+use log::info;
+use minicbor::data::{Tag, Type};
+use minicbor::{Decoder, Encoder};
+use qr_code::{EcLevel, QrCode, Version};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const TAG_HDKEY: u64 = 303;
+const TAG_KEYPATH: u64 = 304;
+const TAG_COIN_INFO: u64 = 305;
+const TAG_WSH: u64 = 401;
+const TAG_MULTI: u64 = 406;
+const TAG_SORTED_MULTI: u64 = 407;
+
+/// Frames per fragment written for multipart UR, the surplus are fountain mixed parts
+const FRAMES_PER_FRAGMENT: usize = 2;
+
+/// Delay between animated GIF frames in hundredths of a second
+const GIF_FRAME_DELAY: u16 = 25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UrType {
+    CryptoPsbt,
+    CryptoAccount,
+    CryptoOutput,
+}
+
+impl fmt::Display for UrType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            UrType::CryptoPsbt => "crypto-psbt",
+            UrType::CryptoAccount => "crypto-account",
+            UrType::CryptoOutput => "crypto-output",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for UrType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "crypto-psbt" => Ok(UrType::CryptoPsbt),
+            "crypto-account" => Ok(UrType::CryptoAccount),
+            "crypto-output" => Ok(UrType::CryptoOutput),
+            _ => Err(Error::UrUnsupportedType(s.to_string())),
+        }
+    }
+}
+
+type CborEncoder = Encoder<Vec<u8>>;
+
+/// cbor of `crypto-psbt`, a byte string containing the serialized PSBT
+pub fn psbt_to_cbor(psbt_bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut e = Encoder::new(vec![]);
+    e.bytes(psbt_bytes)?;
+    Ok(e.into_writer())
+}
+
+/// cbor of `crypto-account` for a master key, exported as a single `wsh` cosigner output
+pub fn account_to_cbor(xpub: &ExtendedPubKey) -> Result<Vec<u8>> {
+    let fingerprint = xpub.fingerprint();
+    let mut e = Encoder::new(vec![]);
+    e.map(2)?;
+    e.u8(1)?.u32(fingerprint_to_u32(&fingerprint))?;
+    e.u8(2)?.array(1)?;
+    e.tag(Tag::Unassigned(TAG_WSH))?;
+    encode_hdkey(&mut e, xpub, fingerprint)?;
+    Ok(e.into_writer())
+}
+
+/// cbor of `crypto-output` for a wallet descriptor in the form "wsh(multi({n},{x}/0/*,...))"
+pub fn output_to_cbor(wallet: &WalletJson) -> Result<Vec<u8>> {
+    let end = wallet
+        .descriptor
+        .find('#')
+        .unwrap_or(wallet.descriptor.len());
+    let descriptor = &wallet.descriptor[..end];
+    let tag = if descriptor.starts_with("wsh(multi(") {
+        TAG_MULTI
+    } else if descriptor.starts_with("wsh(sortedmulti(") {
+        TAG_SORTED_MULTI
+    } else {
+        return Err(Error::NonDefaultScript);
+    };
+    let xpubs = crate::offline::descriptor::extract_xpubs(descriptor)?;
+    let mut e = Encoder::new(vec![]);
+    e.tag(Tag::Unassigned(TAG_WSH))?;
+    e.tag(Tag::Unassigned(tag))?;
+    e.map(2)?;
+    e.u8(1)?.u64(wallet.required_sig as u64)?;
+    e.u8(2)?.array(xpubs.len() as u64)?;
+    for xpub in xpubs.iter() {
+        encode_hdkey(&mut e, xpub, xpub.fingerprint())?;
+    }
+    Ok(e.into_writer())
+}
+
+/// encode a firma master xpub as `crypto-hdkey` with children `0/*`
+fn encode_hdkey(e: &mut CborEncoder, xpub: &ExtendedPubKey, source: Fingerprint) -> Result<()> {
+    let is_testnet = xpub.network != Network::Bitcoin;
+    e.tag(Tag::Unassigned(TAG_HDKEY))?;
+    e.map(6)?;
+    e.u8(3)?.bytes(&xpub.public_key.key.serialize())?;
+    e.u8(4)?.bytes(xpub.chain_code.as_bytes())?;
+    e.u8(5)?.tag(Tag::Unassigned(TAG_COIN_INFO))?;
+    e.map(2)?.u8(1)?.u8(0)?.u8(2)?.u8(is_testnet as u8)?;
+    e.u8(6)?.tag(Tag::Unassigned(TAG_KEYPATH))?;
+    e.map(3)?;
+    e.u8(1)?.array(0)?;
+    e.u8(2)?.u32(fingerprint_to_u32(&source))?;
+    e.u8(3)?.u8(xpub.depth)?;
+    e.u8(7)?.tag(Tag::Unassigned(TAG_KEYPATH))?;
+    e.map(1)?;
+    e.u8(1)?
+        .array(4)?
+        .u32(0)?
+        .bool(false)?
+        .array(0)?
+        .bool(false)?;
+    e.u8(8)?.u32(fingerprint_to_u32(&xpub.parent_fingerprint))?;
+    Ok(())
+}
+
+fn fingerprint_to_u32(fingerprint: &Fingerprint) -> u32 {
+    u32::from_be_bytes(*fingerprint.as_bytes())
+}
+
+/// Payload of a `crypto-psbt`, the serialized PSBT
+pub fn psbt_from_cbor(cbor: &[u8]) -> Result<Vec<u8>> {
+    Ok(Decoder::new(cbor).bytes()?.to_vec())
+}
+
+/// Rebuild a wallet from a `crypto-output` created by `output_to_cbor`
+pub fn wallet_from_cbor(cbor: &[u8]) -> Result<WalletJson> {
+    let mut d = Decoder::new(cbor);
+    expect_tag(&mut d, TAG_WSH)?;
+    let fun = match d.tag()? {
+        Tag::Unassigned(TAG_MULTI) => "multi",
+        Tag::Unassigned(TAG_SORTED_MULTI) => "sortedmulti",
+        _ => return Err(Error::NonDefaultScript),
+    };
+    let mut required_sig = None;
+    let mut keys = vec![];
+    let mut fingerprints = BTreeSet::new();
+    for _ in 0..d.map()?.unwrap_or(0) {
+        match d.u8()? {
+            1 => required_sig = Some(d.u64()? as usize),
+            2 => {
+                for _ in 0..d.array()?.unwrap_or(0) {
+                    let (xpub, source, children) = decode_hdkey(&mut d)?;
+                    fingerprints.insert(source);
+                    keys.push(format!("{}{}", xpub, children));
+                }
+            }
+            _ => d.skip()?,
+        }
+    }
+    let required_sig = required_sig.ok_or(Error::UrMalformed)?;
+    if keys.is_empty() {
+        return Err(Error::UrMalformed);
+    }
+    let descriptor = format!("wsh({}({},{}))", fun, required_sig, keys.join(","));
+    let hash = sha256::Hash::hash(descriptor.as_bytes());
+    let name = format!("wallet-{}", &hash.to_string()[..8]);
+
+    Ok(WalletJson {
+        name,
+        descriptor,
+        fingerprints,
+        required_sig,
+        created_at_height: 0,
+    })
+}
+
+fn expect_tag(d: &mut Decoder, tag: u64) -> Result<()> {
+    match d.tag()? {
+        Tag::Unassigned(t) if t == tag => Ok(()),
+        _ => Err(Error::UrMalformed),
+    }
+}
+
+/// returns the xpub, the source fingerprint and the children path in the form "/0/*"
+fn decode_hdkey(d: &mut Decoder) -> Result<(ExtendedPubKey, Fingerprint, String)> {
+    expect_tag(d, TAG_HDKEY)?;
+    let mut key = None;
+    let mut chain_code = None;
+    let mut network = Network::Bitcoin;
+    let mut source = None;
+    let mut depth = 0u8;
+    let mut child_number = ChildNumber::from(0);
+    let mut children = String::new();
+    let mut parent_fingerprint = Fingerprint::default();
+    for _ in 0..d.map()?.unwrap_or(0) {
+        match d.u8()? {
+            3 => key = Some(secp256k1::PublicKey::from_slice(d.bytes()?)?),
+            4 => match d.bytes()? {
+                bytes if bytes.len() == 32 => chain_code = Some(ChainCode::from(bytes)),
+                _ => return Err(Error::UrMalformed),
+            },
+            5 => {
+                expect_tag(d, TAG_COIN_INFO)?;
+                for _ in 0..d.map()?.unwrap_or(0) {
+                    match d.u8()? {
+                        2 => {
+                            if d.u8()? == 1 {
+                                network = Network::Testnet;
+                            }
+                        }
+                        _ => d.skip()?,
+                    }
+                }
+            }
+            6 => {
+                let (components, fingerprint, origin_depth) = decode_keypath(d)?;
+                if let Some(last) = components.last() {
+                    child_number = *last;
+                }
+                depth = origin_depth.unwrap_or(components.len() as u8);
+                source = fingerprint;
+            }
+            7 => {
+                let (components, _, _) = decode_keypath(d)?;
+                for c in components {
+                    children.push_str(&format!("/{}", c));
+                }
+                children.push_str("/*");
+            }
+            8 => parent_fingerprint = Fingerprint::from(&d.u32()?.to_be_bytes()[..]),
+            _ => d.skip()?,
+        }
+    }
+    let xpub = ExtendedPubKey {
+        network,
+        depth,
+        parent_fingerprint,
+        child_number,
+        public_key: PublicKey {
+            compressed: true,
+            key: key.ok_or(Error::UrMalformed)?,
+        },
+        chain_code: chain_code.ok_or(Error::UrMalformed)?,
+    };
+    let source = source.unwrap_or_else(|| xpub.fingerprint());
+    Ok((xpub, source, children))
+}
+
+/// returns the non-wildcard components, the source fingerprint and the depth of a `crypto-keypath`
+fn decode_keypath(d: &mut Decoder) -> Result<(Vec<ChildNumber>, Option<Fingerprint>, Option<u8>)> {
+    expect_tag(d, TAG_KEYPATH)?;
+    let mut components = vec![];
+    let mut fingerprint = None;
+    let mut depth = None;
+    for _ in 0..d.map()?.unwrap_or(0) {
+        match d.u8()? {
+            1 => {
+                let len = d.array()?.unwrap_or(0);
+                for _ in 0..len / 2 {
+                    let index = match d.datatype()? {
+                        Type::Array => {
+                            d.skip()?; // wildcard
+                            None
+                        }
+                        _ => Some(d.u32()?),
+                    };
+                    let hardened = d.bool()?;
+                    if let Some(index) = index {
+                        components.push(match hardened {
+                            true => ChildNumber::from_hardened_idx(index)?,
+                            false => ChildNumber::from_normal_idx(index)?,
+                        });
+                    }
+                }
+            }
+            2 => fingerprint = Some(Fingerprint::from(&d.u32()?.to_be_bytes()[..])),
+            3 => depth = Some(d.u8()?),
+            _ => d.skip()?,
+        }
+    }
+    Ok((components, fingerprint, depth))
+}
+
+/// Split `cbor` in UR parts each fitting a QR code of the given `version`
+pub fn ur_parts(ur_type: UrType, cbor: &[u8], version: i16) -> Result<Vec<String>> {
+    let version = Version::Normal(version);
+    let single = ::ur::ur::encode(cbor, ur_type.to_string()).to_uppercase();
+    if fits(&single, version) {
+        return Ok(vec![single]);
+    }
+
+    // ~2 alphanumeric chars per byte in bytewords, minus type, sequence and fountain header
+    let capacity = qr_code::bits::Bits::new(version).max_len(EcLevel::L)?;
+    let mut max_fragment_len = (capacity - 17) * 2 / 11 / 2;
+    loop {
+        if max_fragment_len < 10 {
+            return Err(Error::UrMalformed);
+        }
+        let mut encoder = ::ur::Encoder::new(cbor, max_fragment_len, ur_type.to_string())?;
+        let frames = encoder.fragment_count() * FRAMES_PER_FRAGMENT;
+        let parts = (0..frames)
+            .map(|_| encoder.next_part().map(|p| p.to_uppercase()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if parts.iter().all(|p| fits(p, version)) {
+            info!("ur splitted in {} frames", parts.len());
+            return Ok(parts);
+        }
+        max_fragment_len -= 5;
+    }
+}
+
+fn fits(part: &str, version: Version) -> bool {
+    QrCode::with_version(part, version, EcLevel::L).is_ok()
+}
+
+/// Save UR frames as `ur-{i}.{ext}` (or `ur.{ext}` if single), an animated `ur.gif` and `urs.txt`
+pub fn save_ur_qrs(
+    ur_type: UrType,
+    cbor: &[u8],
+    ur_dir: PathBuf,
+    version: i16,
+    format: QrFormat,
+) -> Result<Vec<PathBuf>> {
+    match version {
+        0 => return Ok(vec![]),
+        5..=20 => info!(
+            "save_ur_qrs {} len:{} version:{}",
+            ur_type,
+            cbor.len(),
+            version
+        ),
+        _ => return Err(format!("invalid qr version {}", version).into()),
+    }
+    let parts = ur_parts(ur_type, cbor, version)?;
+
+    if ur_dir.exists() {
+        if !ur_dir.is_dir() {
+            return Err("save_ur_qrs ur_dir is not a dir".into());
+        }
+        for entry in fs::read_dir(&ur_dir)? {
+            fs::remove_file(entry?.path())?;
+        }
+    } else {
+        fs::create_dir(&ur_dir)?;
+    }
+
+    let mut files = vec![];
+    let mut ur_file = ur_dir;
+    ur_file.push("dummy");
+    let mut qrs = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        let qr = QrCode::with_version(part, Version::Normal(version), EcLevel::L)?;
+        if parts.len() == 1 {
+            ur_file.set_file_name("ur");
+        } else {
+            ur_file.set_file_name(format!("ur-{}", i));
+        }
+        ur_file.set_extension(format.extension());
+        write_qr(&qr, &ur_file, format)?;
+        files.push(ur_file.clone());
+        qrs.push(qr);
+    }
+
+    ur_file.set_file_name("ur.gif");
+    save_gif(&qrs, &ur_file)?;
+    files.push(ur_file.clone());
+
+    ur_file.set_file_name("urs.txt");
+    let mut txt = File::create(&ur_file)?;
+    for part in parts.iter() {
+        writeln!(txt, "{}", part)?;
+    }
+
+    Ok(files)
+}
+
+/// all qrs must have the same version
+fn save_gif(qrs: &[QrCode], path: &PathBuf) -> Result<()> {
+    const SCALE: usize = 4;
+    const BORDER: usize = 3;
+    let width = qrs.first().map(|qr| qr.width()).unwrap_or(0);
+    let side = (width + BORDER * 2) * SCALE;
+    let mut encoder = gif::Encoder::new(
+        File::create(path)?,
+        side as u16,
+        side as u16,
+        &[0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00],
+    )?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    for qr in qrs {
+        let modules = qr.to_vec();
+        let mut pixels = vec![0u8; side * side];
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            let x = (i % side) / SCALE;
+            let y = (i / side) / SCALE;
+            if x >= BORDER && y >= BORDER && x < width + BORDER && y < width + BORDER {
+                *pixel = modules[(y - BORDER) * width + (x - BORDER)] as u8;
+            }
+        }
+        let frame = gif::Frame {
+            width: side as u16,
+            height: side as u16,
+            delay: GIF_FRAME_DELAY,
+            buffer: Cow::Owned(pixels),
+            ..Default::default()
+        };
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
+
+/// Reassemble UR frames, in any order and possibly repeated, into the type and the cbor payload
+pub fn merge_urs(parts: &[String]) -> Result<(UrType, Vec<u8>)> {
+    let mut ur_type = None;
+    let mut decoder = ::ur::ur::Decoder::default();
+    for part in parts {
+        let part = part.trim().to_lowercase();
+        let current = part
+            .strip_prefix("ur:")
+            .and_then(|s| s.split('/').next())
+            .ok_or(Error::UrMalformed)?
+            .parse::<UrType>()?;
+        if *ur_type.get_or_insert(current) != current {
+            return Err(Error::UrMalformed);
+        }
+        match ::ur::ur::decode(&part)? {
+            (::ur::ur::Kind::SinglePart, cbor) => return Ok((current, cbor)),
+            (::ur::ur::Kind::MultiPart, _) => decoder.receive(&part)?,
+        }
+        if decoder.complete() {
+            break;
+        }
+    }
+    match (ur_type, decoder.message()?) {
+        (Some(ur_type), Some(cbor)) => Ok((ur_type, cbor)),
+        _ => Err(Error::UrIncomplete),
+    }
+}
+
+/// Merge UR frames returning the bytes usable by the caller: the PSBT for `crypto-psbt`,
+/// the wallet json for `crypto-output` and the xpub for `crypto-account`
+pub fn merge_urs_payload(parts: &[String], network: Network) -> Result<MergeUrOutput> {
+    let (ur_type, cbor) = merge_urs(parts)?;
+    let payload = match ur_type {
+        UrType::CryptoPsbt => psbt_from_cbor(&cbor)?,
+        UrType::CryptoOutput => {
+            let wallet = wallet_from_cbor(&cbor)?;
+            for xpub in crate::offline::descriptor::extract_xpubs(&wallet.descriptor)? {
+                check_compatibility(network, xpub.network)?;
+            }
+            serde_json::to_vec(&wallet)?
+        }
+        UrType::CryptoAccount => {
+            let mut d = Decoder::new(&cbor);
+            let mut xpub = None;
+            for _ in 0..d.map()?.unwrap_or(0) {
+                match d.u8()? {
+                    2 if d.array()?.unwrap_or(0) > 0 => {
+                        d.tag()?; // script expression
+                        xpub = Some(decode_hdkey(&mut d)?.0);
+                        break;
+                    }
+                    _ => d.skip()?,
+                }
+            }
+            let xpub = xpub.ok_or(Error::UrMalformed)?;
+            check_compatibility(network, xpub.network)?;
+            xpub.to_string().into_bytes()
+        }
+    };
+    Ok(MergeUrOutput {
+        ur_type: ur_type.to_string(),
+        hex: hex::encode(payload),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::ur::*;
+    use tempfile::TempDir;
+
+    const DESCRIPTOR: &str = "wsh(multi(2,tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU/0/*,tpubD6NzVbkrYhZ4WpudNKLizFbGzpsG3jkLF7mc8Vfh1fTDbbBPjDP29My6TaLncaS8VeDPcaNMdUkybucr8Kz9CHSdAtvxnaXyBxPRocefdXN/0/*))#5wstxmwd";
+
+    #[test]
+    fn test_ur_psbt_roundtrip() {
+        let psbt = vec![7u8; 1000];
+        let cbor = psbt_to_cbor(&psbt).unwrap();
+        let parts = ur_parts(UrType::CryptoPsbt, &cbor, 8).unwrap();
+        assert!(parts.len() > 2);
+        assert!(parts.iter().all(|p| p.starts_with("UR:CRYPTO-PSBT/")));
+
+        // a scanner can miss frames, the fountain mixed parts recover them
+        let some_parts: Vec<String> = parts.iter().skip(1).cloned().collect();
+        let (ur_type, merged) = merge_urs(&some_parts).unwrap();
+        assert_eq!(ur_type, UrType::CryptoPsbt);
+        assert_eq!(psbt_from_cbor(&merged).unwrap(), psbt);
+
+        assert!(merge_urs(&parts[..1]).is_err());
+
+        let single = ur_parts(UrType::CryptoPsbt, &cbor, 40).unwrap();
+        assert_eq!(single.len(), 1);
+    }
+
+    #[test]
+    fn test_ur_wallet_roundtrip() {
+        let xpubs = crate::offline::descriptor::extract_xpubs(DESCRIPTOR).unwrap();
+        let wallet = WalletJson {
+            name: "test".to_string(),
+            descriptor: DESCRIPTOR.to_string(),
+            fingerprints: xpubs.iter().map(|x| x.fingerprint()).collect(),
+            required_sig: 2,
+            created_at_height: 0,
+        };
+        let cbor = output_to_cbor(&wallet).unwrap();
+        let decoded = wallet_from_cbor(&cbor).unwrap();
+        assert_eq!(
+            decoded.descriptor,
+            DESCRIPTOR[..DESCRIPTOR.find('#').unwrap()]
+        );
+        assert_eq!(decoded.fingerprints, wallet.fingerprints);
+        assert_eq!(decoded.required_sig, 2);
+
+        let parts = ur_parts(UrType::CryptoOutput, &cbor, 20).unwrap();
+        let merged = merge_urs_payload(&parts, Network::Testnet).unwrap();
+        assert_eq!(merged.ur_type, "crypto-output");
+        let merged: WalletJson = serde_json::from_slice(&hex::decode(merged.hex).unwrap()).unwrap();
+        assert_eq!(merged, decoded);
+        assert!(merge_urs_payload(&parts, Network::Bitcoin).is_err());
+
+        let account = account_to_cbor(&xpubs[0]).unwrap();
+        let parts = ur_parts(UrType::CryptoAccount, &account, 20).unwrap();
+        let merged = merge_urs_payload(&parts, Network::Testnet).unwrap();
+        let xpub = String::from_utf8(hex::decode(merged.hex).unwrap()).unwrap();
+        assert_eq!(xpub, xpubs[0].to_string());
+    }
+
+    #[test]
+    fn test_save_ur_qrs() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut ur_dir = temp_dir.path().to_path_buf();
+        ur_dir.push("ur");
+        let cbor = psbt_to_cbor(&[1u8; 500]).unwrap();
+        let files =
+            save_ur_qrs(UrType::CryptoPsbt, &cbor, ur_dir.clone(), 10, QrFormat::Bmp).unwrap();
+        assert!(files.len() > 2);
+        assert!(files.iter().all(|f| f.exists()));
+        assert_eq!(files.last().unwrap().file_name().unwrap(), "ur.gif");
+
+        let files =
+            save_ur_qrs(UrType::CryptoPsbt, &cbor, ur_dir.clone(), 20, QrFormat::Bmp).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read_dir(&ur_dir).unwrap().count(), 3); // old frames deleted
+        assert!(
+            save_ur_qrs(UrType::CryptoPsbt, &cbor, ur_dir.clone(), 0, QrFormat::Bmp)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Plain C ABI to embed firma in other applications, the header is `include/firma.h`
+
+use crate::common::dispatcher;
+use crate::*;
+use log::debug;
+use std::any::Any;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+
+fn call(json: *const c_char) -> Result<serde_json::Value> {
+    if json.is_null() {
+        return Err(Error::Generic("null request".to_string()));
+    }
+    // SAFETY: not null and the caller guarantees it is a valid nul terminated string
+    let request = unsafe { CStr::from_ptr(json) }.to_str()?;
+    dispatcher::call(request)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown".to_string(),
+        },
+    }
+}
+
+/// Call the firma method specified in the json `request`, for example
+/// `{"datadir":"/tmp/firma","network":"testnet","method":"random","args":{"key_name":"a"}}`.
+/// Returns a json string with the result or with an `error` field, also if the library panics.
+///
+/// # Safety
+///
+/// `request` must be a valid nul terminated string, the returned string must be released with
+/// `firma_free`
+#[no_mangle]
+pub unsafe extern "C" fn firma_call(request: *const c_char) -> *mut c_char {
+    // a panic unwinding through `extern "C"` would abort the host application
+    let value = panic::catch_unwind(AssertUnwindSafe(|| call(request)))
+        .unwrap_or_else(|payload| Err(Error::Panic(panic_message(payload))))
+        .unwrap_or_else(|e| e.to_json());
+    let result = serde_json::to_string(&value).expect("Value serialization can't fail");
+    debug!("result: ({})", result);
+    // serde_json escapes nul characters, so the string doesn't contain any
+    CString::new(result)
+        .expect("json doesn't contain nul")
+        .into_raw()
+}
+
+/// Release a string returned by `firma_call`
+///
+/// # Safety
+///
+/// `s` must be returned by `firma_call` and not already released
+#[no_mangle]
+pub unsafe extern "C" fn firma_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
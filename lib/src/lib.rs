@@ -1,6 +1,7 @@
 //#![warn(missing_docs)]
 
 pub mod common;
+pub mod ffi;
 pub mod offline;
 pub mod online;
 
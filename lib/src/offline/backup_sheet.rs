@@ -0,0 +1,495 @@
+use crate::offline::descriptor::{derive_address, DeriveAddressOpts};
+use crate::offline::sign::read_key;
+use crate::qr::{dark_modules, to_svg_path};
+use crate::*;
+use bitcoin::Network;
+use log::info;
+use qr_code::QrCode;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct BackupSheetOptions {
+    /// Name of the wallet to print, the sheet contains the descriptor and the first addresses
+    #[structopt(long)]
+    pub wallet_name: Option<String>,
+
+    /// Name of the key to print, the sheet contains the fingerprint and the xpub, never secrets
+    #[structopt(long)]
+    pub key_name: Option<String>,
+
+    /// Number of receive addresses printed in the wallet sheet
+    #[structopt(long, default_value = "5")]
+    pub addresses: u32,
+
+    /// Sheet format (svg, pdf)
+    #[structopt(long, default_value = "pdf")]
+    pub format: SheetFormat,
+
+    /// in CLI it is populated from standard input
+    /// It is an Option so that structopt could skip,
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+
+    /// Optional datadir encryption key, for the wallet file
+    #[structopt(skip)]
+    #[serde(default)]
+    pub datadir_encryption_key: Option<StringEncoding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SheetFormat {
+    Svg,
+    Pdf,
+}
+
+impl SheetFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SheetFormat::Svg => "svg",
+            SheetFormat::Pdf => "pdf",
+        }
+    }
+}
+
+impl FromStr for SheetFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(SheetFormat::Svg),
+            "pdf" => Ok(SheetFormat::Pdf),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("({}) valid values are: svg, pdf", s),
+            )),
+        }
+    }
+}
+
+/// A4 in points
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 40.0;
+const TITLE_SIZE: f64 = 16.0;
+const FONT_SIZE: f64 = 9.0;
+const LINE_HEIGHT: f64 = 12.0;
+/// monospace chars fitting the page width at `FONT_SIZE`
+const WRAP_CHARS: usize = 90;
+const QR_SIZE: f64 = 200.0;
+
+/// text lines and captioned QR codes of a sheet
+type SheetContent = (Vec<String>, Vec<(String, QrCode)>);
+
+enum Element {
+    Text {
+        x: f64,
+        y: f64,
+        size: f64,
+        text: String,
+    },
+    Qr {
+        x: f64,
+        y: f64,
+        qr: QrCode,
+    },
+}
+
+/// Write a printable page with the wallet or the key data in the wallet or key directory
+pub fn backup_sheet(
+    datadir: &str,
+    network: Network,
+    opt: &BackupSheetOptions,
+) -> Result<BackupSheetOutput> {
+    let (path_builder, title, lines, qrs) = match (&opt.wallet_name, &opt.key_name) {
+        (Some(name), None) => {
+            let path_builder =
+                PathBuilder::new(datadir, network, Kind::Wallet, Some(name.to_string()));
+            let wallet = read_wallet(
+                &path_builder.file("descriptor.json")?,
+                opt.datadir_encryption_key.as_ref(),
+            )?;
+            let (lines, qrs) = wallet_content(network, &wallet, opt.addresses)?;
+            (path_builder, format!("Wallet {}", name), lines, qrs)
+        }
+        (None, Some(name)) => {
+            let path_builder =
+                PathBuilder::new(datadir, network, Kind::Key, Some(name.to_string()));
+            let key_file = path_builder.file("PRIVATE.json")?;
+            let key = read_key(&key_file, opt.encryption_key.as_ref())?;
+            let (lines, qrs) = key_content(network, &key)?;
+            (path_builder, format!("Key {}", name), lines, qrs)
+        }
+        _ => return Err(Error::BackupSheetTarget),
+    };
+
+    let pages = layout(&title, &lines, qrs);
+    let content = match opt.format {
+        SheetFormat::Svg => to_svg(&pages).into_bytes(),
+        SheetFormat::Pdf => to_pdf(&pages),
+    };
+    let file = path_builder.file(&format!("sheet.{}", opt.format.extension()))?;
+    info!("Saving backup sheet in {:?}", file);
+    fs::write(&file, content)?;
+
+    Ok(BackupSheetOutput { file })
+}
+
+fn wallet_content(network: Network, wallet: &WalletJson, addresses: u32) -> Result<SheetContent> {
+    let fingerprints: Vec<String> = wallet.fingerprints.iter().map(|f| f.to_string()).collect();
+    let mut lines = vec![
+        format!("Network: {}", network),
+        format!(
+            "Required signatures: {} of {}",
+            wallet.required_sig,
+            wallet.fingerprints.len()
+        ),
+        format!("Fingerprints: {}", fingerprints.join(" ")),
+        format!("Created at height: {}", wallet.created_at_height),
+        String::new(),
+        "Descriptor:".to_string(),
+        wallet.descriptor.clone(),
+        String::new(),
+        "Receive addresses:".to_string(),
+    ];
+    for index in 0..addresses {
+        let opts = DeriveAddressOpts {
+            descriptor: wallet.descriptor.clone(),
+            index,
+        };
+        let address = derive_address(network, &opts)?;
+        lines.push(format!("{} {}", address.path, address.address));
+    }
+    let qrs = vec![(
+        "Wallet".to_string(),
+        QrCode::new(compact_wallet::wallet_to_compact(wallet)?)?,
+    )];
+    Ok((lines, qrs))
+}
+
+fn key_content(network: Network, key: &PrivateMasterKeyJson) -> Result<SheetContent> {
+    let lines = vec![
+        format!("Network: {}", network),
+        format!("Fingerprint: {}", key.fingerprint),
+        String::new(),
+        "Xpub:".to_string(),
+        key.xpub.to_string(),
+        String::new(),
+    ];
+    let qrs = vec![("Xpub".to_string(), QrCode::new(key.xpub.to_string())?)];
+    Ok((lines, qrs))
+}
+
+/// Bottom of an element of `height` placed below `y`, on a new page if it doesn't fit
+fn advance(pages: &mut Vec<Vec<Element>>, y: f64, height: f64) -> f64 {
+    if y + height > PAGE_HEIGHT - MARGIN {
+        pages.push(vec![]);
+        MARGIN + height
+    } else {
+        y + height
+    }
+}
+
+/// place title, lines wrapped at `WRAP_CHARS` and then the captioned QRs two per row, in as
+/// many pages as needed, coordinates are in points from the top left corner of the page
+fn layout(title: &str, lines: &[String], qrs: Vec<(String, QrCode)>) -> Vec<Vec<Element>> {
+    let mut pages = vec![vec![]];
+    let mut y = MARGIN + TITLE_SIZE;
+    pages[0].push(Element::Text {
+        x: MARGIN,
+        y,
+        size: TITLE_SIZE,
+        text: title.to_string(),
+    });
+    y += LINE_HEIGHT;
+
+    for line in lines {
+        let chars: Vec<char> = line.chars().collect();
+        for chunk in chars.chunks(WRAP_CHARS) {
+            y = advance(&mut pages, y, LINE_HEIGHT);
+            pages.last_mut().unwrap().push(Element::Text {
+                x: MARGIN,
+                y,
+                size: FONT_SIZE,
+                text: chunk.iter().collect(),
+            });
+        }
+        if chars.is_empty() {
+            y = advance(&mut pages, y, LINE_HEIGHT);
+        }
+    }
+
+    y += LINE_HEIGHT;
+    let row_height = QR_SIZE + LINE_HEIGHT * 2.0;
+    let mut top = y;
+    for (i, (caption, qr)) in qrs.into_iter().enumerate() {
+        if i % 2 == 0 {
+            y = advance(&mut pages, y, row_height);
+            top = y - row_height;
+        }
+        let x = MARGIN + (i % 2) as f64 * (QR_SIZE + MARGIN);
+        let page = pages.last_mut().unwrap();
+        page.push(Element::Text {
+            x,
+            y: top + LINE_HEIGHT,
+            size: FONT_SIZE,
+            text: caption,
+        });
+        page.push(Element::Qr {
+            x,
+            y: top + LINE_HEIGHT * 1.5,
+            qr,
+        });
+    }
+    pages
+}
+
+/// pages are stacked vertically in a single svg
+fn to_svg(pages: &[Vec<Element>]) -> String {
+    let height = PAGE_HEIGHT * pages.len() as f64;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}pt\" height=\"{h}pt\" viewBox=\"0 0 {w} {h}\">\n\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"#fff\"/>\n",
+        w = PAGE_WIDTH,
+        h = height
+    );
+    let elements = pages.iter().enumerate().flat_map(|(i, page)| {
+        let offset = PAGE_HEIGHT * i as f64;
+        page.iter().map(move |element| (offset, element))
+    });
+    for (offset, element) in elements {
+        match element {
+            Element::Text { x, y, size, text } => {
+                let y = y + offset;
+                let text = text
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;");
+                svg.push_str(&format!(
+                    "<text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"{}\">{}</text>\n",
+                    x, y, size, text
+                ));
+            }
+            Element::Qr { x, y, qr } => {
+                let y = y + offset;
+                let (side, path) = to_svg_path(qr);
+                svg.push_str(&format!(
+                    "<svg x=\"{}\" y=\"{}\" width=\"{s}\" height=\"{s}\" viewBox=\"0 0 {m} {m}\" shape-rendering=\"crispEdges\">\
+                     <path d=\"{}\" fill=\"#000\"/></svg>\n",
+                    x,
+                    y,
+                    path,
+                    s = QR_SIZE,
+                    m = side
+                ));
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// pdf using the standard Courier font, QR modules are filled rectangles
+fn to_pdf(pages: &[Vec<Element>]) -> Vec<u8> {
+    // catalog, pages and font are the first objects, then page and content of every page
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", 4 + i * 2))
+        .collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (i, page) in pages.iter().enumerate() {
+        let stream = pdf_stream(page);
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH,
+            PAGE_HEIGHT,
+            5 + i * 2
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            stream.len(),
+            stream
+        ));
+    }
+
+    let mut pdf = "%PDF-1.4\n".to_string();
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+    }
+    let xref = pdf.len();
+    pdf.push_str(&format!(
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        pdf.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    pdf.into_bytes()
+}
+
+/// content stream of a page
+fn pdf_stream(elements: &[Element]) -> String {
+    let mut stream = String::new();
+    for element in elements {
+        match element {
+            Element::Text { x, y, size, text } => {
+                // standard fonts don't need embedding but support only latin chars
+                let text: String = text
+                    .chars()
+                    .map(|c| if c.is_ascii() { c } else { '?' })
+                    .collect::<String>()
+                    .replace('\\', "\\\\")
+                    .replace('(', "\\(")
+                    .replace(')', "\\)");
+                stream.push_str(&format!(
+                    "BT /F1 {} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+                    size,
+                    x,
+                    PAGE_HEIGHT - y,
+                    text
+                ));
+            }
+            Element::Qr { x, y, qr } => {
+                let (side, modules) = dark_modules(qr);
+                let module = QR_SIZE / side as f64;
+                for (mx, my) in modules {
+                    stream.push_str(&format!(
+                        "{:.2} {:.2} {:.2} {:.2} re\n",
+                        x + mx as f64 * module,
+                        PAGE_HEIGHT - y - (my + 1) as f64 * module,
+                        module,
+                        module
+                    ));
+                }
+                stream.push_str("f\n");
+            }
+        }
+    }
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::backup_sheet::*;
+    use crate::offline::random::{create_key, RandomOptions};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_backup_sheet() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let key_name = "sheet".to_string();
+        let rand_opts = RandomOptions::new(key_name.clone());
+        let key = create_key(datadir, Network::Testnet, &rand_opts).unwrap();
+
+        let mut opt = BackupSheetOptions {
+            wallet_name: None,
+            key_name: Some(key_name),
+            addresses: 5,
+            format: SheetFormat::Pdf,
+            encryption_key: None,
+            datadir_encryption_key: None,
+        };
+        let result = backup_sheet(datadir, Network::Testnet, &opt).unwrap();
+        let pdf = fs::read_to_string(&result.file).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains(&key.key.xpub.to_string()[..WRAP_CHARS]));
+        assert!(!pdf.contains("Mnemonic"));
+        assert!(!pdf.contains(&key.key.xprv.unwrap().to_string()[..WRAP_CHARS]));
+        let mnemonic = key.key.mnemonic.as_ref().unwrap().to_string();
+        assert!(!pdf.contains(&mnemonic[..WRAP_CHARS.min(mnemonic.len())]));
+
+        opt.format = SheetFormat::Svg;
+        let result = backup_sheet(datadir, Network::Testnet, &opt).unwrap();
+        assert_eq!(result.file.extension().unwrap(), "svg");
+
+        opt.wallet_name = Some("wallet".to_string());
+        assert!(backup_sheet(datadir, Network::Testnet, &opt).is_err());
+
+        let descriptor = format!("wsh(multi(1,{}/0/*))", key.key.xpub);
+        let wallet = WalletJson {
+            name: "wallet".to_string(),
+            descriptor: descriptor.clone(),
+            fingerprints: vec![key.key.fingerprint].into_iter().collect(),
+            required_sig: 1,
+            created_at_height: 0,
+        };
+        let context = Context {
+            firma_datadir: datadir.to_string(),
+            network: Network::Testnet,
+            wallet_name: "wallet".to_string(),
+            encryption_key: None,
+        };
+        context.save_wallet(&wallet).unwrap();
+        opt.key_name = None;
+        let result = backup_sheet(datadir, Network::Testnet, &opt).unwrap();
+        let svg = fs::read_to_string(&result.file).unwrap();
+        let address = derive_address(
+            Network::Testnet,
+            &DeriveAddressOpts {
+                descriptor,
+                index: 4,
+            },
+        )
+        .unwrap();
+        assert!(svg.contains(&address.address.to_string()));
+    }
+
+    #[test]
+    fn test_layout() {
+        let lines = vec!["a".repeat(WRAP_CHARS * 2 + 1), String::new()];
+        let qrs = vec![
+            ("1".to_string(), QrCode::new(b"1").unwrap()),
+            ("2".to_string(), QrCode::new(b"2").unwrap()),
+            ("3".to_string(), QrCode::new(b"3").unwrap()),
+        ];
+        let pages = layout("title", &lines, qrs);
+        assert_eq!(pages.len(), 1);
+        let texts = pages[0]
+            .iter()
+            .filter(|e| matches!(e, Element::Text { .. }))
+            .count();
+        assert_eq!(texts, 1 + 3 + 3);
+        assert_eq!(pages[0].len(), texts + 3);
+
+        // content longer than a page continues in the next ones
+        let lines = vec!["a".to_string(); 100];
+        let qrs = vec![
+            ("1".to_string(), QrCode::new(b"1").unwrap()),
+            ("2".to_string(), QrCode::new(b"2").unwrap()),
+            ("3".to_string(), QrCode::new(b"3").unwrap()),
+        ];
+        let pages = layout("title", &lines, qrs);
+        assert!(pages.len() > 1);
+        assert_eq!(pages.iter().map(|p| p.len()).sum::<usize>(), 1 + 100 + 6);
+        for element in pages.iter().flatten() {
+            let bottom = match element {
+                Element::Text { y, .. } => *y,
+                Element::Qr { y, .. } => y + QR_SIZE,
+            };
+            assert!(bottom <= PAGE_HEIGHT - MARGIN, "{}", bottom);
+        }
+        let pdf = String::from_utf8(to_pdf(&pages)).unwrap();
+        assert!(pdf.contains(&format!("/Count {}", pages.len())));
+    }
+}
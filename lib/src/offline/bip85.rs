@@ -0,0 +1,281 @@
+use crate::file::save_keys;
+use crate::mnemonic::Mnemonic;
+use crate::offline::sign::read_key;
+use crate::qr::QrFormat;
+use crate::*;
+use bitcoin::hashes::{sha512, Hash, HashEngine, Hmac, HmacEngine};
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::util::bip32::{ChainCode, ChildNumber, DerivationPath, ExtendedPrivKey};
+use bitcoin::{Network, PrivateKey};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+const PURPOSE: u32 = 83696968;
+const APP_BIP39: u32 = 39;
+const APP_XPRV: u32 = 32;
+const APP_HEX: u32 = 128169;
+const APP_PASSWORD: u32 = 707764;
+const LANGUAGE_ENGLISH: u32 = 0;
+
+/// Derive a child mnemonic, xprv or entropy from a master key as specified in BIP85
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct Bip85Options {
+    /// File containing the master key to derive from
+    #[structopt(short, long, parse(from_os_str))]
+    pub from_key_file: PathBuf,
+
+    /// BIP85 application: mnemonic-12, mnemonic-18, mnemonic-24, xprv, hex-{16..64 bytes} or
+    /// password-{20..86 chars}
+    #[structopt(short, long)]
+    pub application: Bip85Application,
+
+    /// Index of the derived child
+    #[structopt(short, long, default_value = "0")]
+    pub index: u32,
+
+    /// Name of the derived key, required by mnemonic and xprv applications
+    #[structopt(short, long)]
+    pub to_key_name: Option<String>,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
+    /// Optional encryption key for reading/writing the key file encrypted.
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+
+    /// BIP39 passphrase, required if the master key has been created with one
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    #[serde(default)]
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Bip85Application {
+    Mnemonic(u32),
+    Xprv,
+    Hex(u32),
+    Password(u32),
+}
+
+impl FromStr for Bip85Application {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '-');
+        let app = parts.next().unwrap_or("");
+        let len = parts.next().map(|l| l.parse::<u32>());
+        match (app, len) {
+            ("mnemonic", Some(Ok(words))) if [12, 18, 24].contains(&words) => {
+                Ok(Bip85Application::Mnemonic(words))
+            }
+            ("xprv", None) => Ok(Bip85Application::Xprv),
+            ("hex", Some(Ok(bytes))) if (16..=64).contains(&bytes) => {
+                Ok(Bip85Application::Hex(bytes))
+            }
+            ("password", Some(Ok(chars))) if (20..=86).contains(&chars) => {
+                Ok(Bip85Application::Password(chars))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("({}) valid values are: mnemonic-12, mnemonic-18, mnemonic-24, xprv, hex-{{16..64}}, password-{{20..86}}", s),
+            )),
+        }
+    }
+}
+
+impl Bip85Application {
+    fn path(&self, index: u32) -> Result<DerivationPath> {
+        let path = match self {
+            Bip85Application::Mnemonic(words) => {
+                vec![PURPOSE, APP_BIP39, LANGUAGE_ENGLISH, *words, index]
+            }
+            Bip85Application::Xprv => vec![PURPOSE, APP_XPRV, index],
+            Bip85Application::Hex(bytes) => vec![PURPOSE, APP_HEX, *bytes, index],
+            Bip85Application::Password(chars) => vec![PURPOSE, APP_PASSWORD, *chars, index],
+        };
+        let path = path
+            .into_iter()
+            .map(ChildNumber::from_hardened_idx)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(path.into())
+    }
+}
+
+/// 64 bytes of entropy derived from `xprv` at `path`
+fn derive_entropy(xprv: &ExtendedPrivKey, path: &DerivationPath) -> Result<[u8; 64]> {
+    let secp = Secp256k1::signing_only();
+    let derived = xprv.derive_priv(&secp, path)?;
+    let mut engine: HmacEngine<sha512::Hash> = HmacEngine::new(b"bip-entropy-from-k");
+    engine.input(&derived.private_key.key[..]);
+    Ok(Hmac::from_engine(engine).into_inner())
+}
+
+pub fn start(datadir: &str, network: Network, opt: &Bip85Options) -> Result<Bip85Output> {
+    let parent = read_key(&opt.from_key_file, opt.encryption_key.as_ref())?;
+    let path = opt.application.path(opt.index)?;
+    let entropy = derive_entropy(&parent.signing_xprv(opt.passphrase.as_deref())?, &path)?;
+
+    let (key, entropy) = match opt.application {
+        Bip85Application::Mnemonic(words) => {
+            let name = opt.to_key_name.as_ref().ok_or(Error::MissingName)?;
+            let len = words as usize * 4 / 3;
+            let mnemonic = Mnemonic::new(&entropy[..len])?;
+            (
+                Some(PrivateMasterKeyJson::new(network, &mnemonic, name, None)?),
+                None,
+            )
+        }
+        Bip85Application::Xprv => {
+            let name = opt.to_key_name.as_ref().ok_or(Error::MissingName)?;
+            let xprv = ExtendedPrivKey {
+                network,
+                depth: 0,
+                parent_fingerprint: Default::default(),
+                child_number: ChildNumber::from_normal_idx(0)?,
+                private_key: PrivateKey {
+                    compressed: true,
+                    network,
+                    key: SecretKey::from_slice(&entropy[32..])?,
+                },
+                chain_code: ChainCode::from(&entropy[..32]),
+            };
+            (Some(PrivateMasterKeyJson::from_xprv(xprv, name)), None)
+        }
+        Bip85Application::Hex(bytes) => (None, Some(hex::encode(&entropy[..bytes as usize]))),
+        Bip85Application::Password(chars) => {
+            let password = base64::encode(&entropy[..]);
+            (None, Some(password[..chars as usize].to_string()))
+        }
+    };
+
+    let key = match key {
+        Some(mut key) => {
+            key.bip85 = Some(Bip85Origin {
+                parent_fingerprint: parent.fingerprint,
+                path: path.clone(),
+            });
+            let output = save_keys(
+                datadir,
+                network,
+                &key.name.clone(),
+                key,
+                opt.qr_version,
+                opt.qr_format,
+                opt.encryption_key.as_ref(),
+            )?;
+            Some(output)
+        }
+        None => None,
+    };
+
+    Ok(Bip85Output { path, key, entropy })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::bip85::*;
+    use tempfile::TempDir;
+
+    const MASTER: &str = "xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb";
+
+    fn derive(application: &str, to_key_name: Option<&str>) -> Bip85Output {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let key = PrivateMasterKeyJson::from_xprv(ExtendedPrivKey::from_str(MASTER).unwrap(), "m");
+        let key_file = save_keys(datadir, Network::Bitcoin, "m", key, 0, QrFormat::Bmp, None)
+            .unwrap()
+            .private_file;
+        let opt = Bip85Options {
+            from_key_file: key_file,
+            application: Bip85Application::from_str(application).unwrap(),
+            index: 0,
+            to_key_name: to_key_name.map(|s| s.to_string()),
+            qr_version: 0,
+            qr_format: QrFormat::Bmp,
+            encryption_key: None,
+            passphrase: None,
+        };
+        start(datadir, Network::Bitcoin, &opt).unwrap()
+    }
+
+    #[test]
+    fn test_bip85_vectors() {
+        let path = DerivationPath::from_str("m/83696968'/0'/0'").unwrap();
+        let entropy = derive_entropy(&ExtendedPrivKey::from_str(MASTER).unwrap(), &path).unwrap();
+        assert_eq!(hex::encode(&entropy[..]), "efecfbccffea313214232d29e71563d941229afb4338c21f9517c41aaa0d16f00b83d2a09ef747e7a64e8e2bd5a14869e693da66ce94ac2da570ab7ee48618f7");
+
+        let result = derive("mnemonic-12", Some("child"));
+        assert_eq!(result.path.to_string(), "m/83696968'/39'/0'/12'/0'");
+        let key = result.key.unwrap().key;
+        assert_eq!(
+            key.mnemonic.unwrap().to_string(),
+            "girl mad pet galaxy egg matter matrix prison refuse sense ordinary nose"
+        );
+        let origin = key.bip85.unwrap();
+        let master = ExtendedPrivKey::from_str(MASTER).unwrap();
+        let secp = Secp256k1::signing_only();
+        assert_eq!(origin.parent_fingerprint, master.fingerprint(&secp));
+        assert_eq!(origin.path, result.path);
+
+        let key = derive("mnemonic-24", Some("child")).key.unwrap().key;
+        assert_eq!(key.mnemonic.unwrap().to_string(), "puppy ocean match cereal symbol another shed magic wrap hammer bulb intact gadget divorce twin tonight reason outdoor destroy simple truth cigar social volcano");
+
+        let key = derive("xprv", Some("child")).key.unwrap().key;
+        assert_eq!(key.xprv.unwrap().to_string(), "xprv9s21ZrQH143K2srSbCSg4m4kLvPMzcWydgmKEnMmoZUurYuBuYG46c6P71UGXMzmriLzCCBvKQWBUv3vPB3m1SATMhp3uEjXHJ42jFg7myX");
+
+        let entropy = derive("hex-64", None).entropy.unwrap();
+        assert_eq!(entropy, "492db4698cf3b73a5a24998aa3e9d7fa96275d85724a91e71aa2d645442f878555d078fd1f1f67e368976f04137b1f7a0d19232136ca50c44614af72b5582a5c");
+
+        let password = derive("password-21", None).entropy.unwrap();
+        assert_eq!(password, "dKLoepugzdVJvdL56ogNV");
+
+        assert!(Bip85Application::from_str("mnemonic-13").is_err());
+        assert!(Bip85Application::from_str("hex-65").is_err());
+        assert!(Bip85Application::from_str("xprv-1").is_err());
+    }
+
+    #[test]
+    fn test_bip85_passphrase() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let mnemonic = Mnemonic::new(&[0u8; 16]).unwrap();
+        let key = PrivateMasterKeyJson::new(Network::Bitcoin, &mnemonic, "m", Some("TREZOR"));
+        let key = key.unwrap();
+        let xprv = key.signing_xprv(Some("TREZOR")).unwrap();
+        let key_file = save_keys(datadir, Network::Bitcoin, "m", key, 0, QrFormat::Bmp, None)
+            .unwrap()
+            .private_file;
+        let mut opt = Bip85Options {
+            from_key_file: key_file,
+            application: Bip85Application::Hex(16),
+            index: 0,
+            to_key_name: None,
+            qr_version: 0,
+            qr_format: QrFormat::Bmp,
+            encryption_key: None,
+            passphrase: None,
+        };
+        assert!(matches!(
+            start(datadir, Network::Bitcoin, &opt),
+            Err(Error::MissingPassphrase)
+        ));
+
+        opt.passphrase = Some("TREZOR".to_string());
+        let entropy = start(datadir, Network::Bitcoin, &opt).unwrap().entropy;
+        let path = opt.application.path(0).unwrap();
+        let expected = derive_entropy(&xprv, &path).unwrap();
+        assert_eq!(entropy, Some(hex::encode(&expected[..16])));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{Error, Result, StringEncoding};
 use aes_gcm_siv::aead::{generic_array::GenericArray, Aead, NewAead};
 use aes_gcm_siv::Aes256GcmSiv;
+use argon2::{Algorithm, Argon2, Params, Version};
 use log::warn;
 use rand::{thread_rng, Rng};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -29,7 +30,7 @@ where
     match (maybe_encrypted, opt.encryption_key.clone()) {
         (MaybeEncrypted::Plain(value), None) => Ok(value),
         (maybe_encrypted @ MaybeEncrypted::Encrypted(_), Some(encryption_key)) => {
-            match maybe_encrypted.decrypt(&encryption_key.get_exactly_32()?) {
+            match maybe_encrypted.decrypt_with(&encryption_key) {
                 Ok(MaybeEncrypted::Plain(value)) => Ok(value),
                 Ok(_) => Err(Error::MaybeEncryptedWrongState),
                 Err(e) => {
@@ -55,7 +56,72 @@ impl DecryptOptions {
 #[serde(tag = "t", content = "c", rename_all = "lowercase")]
 pub enum MaybeEncrypted<T> {
     Plain(T),
-    Encrypted(StringEncoding),
+    Encrypted(Ciphertext),
+}
+
+/// Content of the `Encrypted` envelope, nonce is prepended to the ciphertext
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Ciphertext {
+    /// Encrypted with a raw 32 bytes key
+    Raw(StringEncoding),
+    /// Encrypted with a key derived from a password
+    Kdf {
+        kdf: Kdf,
+        salt: StringEncoding,
+        data: StringEncoding,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "alg", rename_all = "lowercase")]
+pub enum Kdf {
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+}
+
+/// Maximum Argon2id parameters accepted, since they are read from the envelope a crafted file
+/// could otherwise require unbounded memory and time: 1 GiB, 16 passes and 16 lanes
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+impl Default for Kdf {
+    fn default() -> Self {
+        // OWASP recommended minimum
+        Kdf::Argon2id {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+impl Kdf {
+    fn derive(&self, password: &[u8], salt: &[u8]) -> Result<[u8; 32]> {
+        let mut result = [0u8; 32];
+        match self {
+            Kdf::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => {
+                if *m_cost > MAX_M_COST || *t_cost > MAX_T_COST || *p_cost > MAX_P_COST {
+                    return Err(Error::KdfParamsTooHigh(*m_cost, *t_cost, *p_cost));
+                }
+                let params = Params::new(*m_cost, *t_cost, *p_cost, Some(result.len()))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(
+                    password,
+                    salt,
+                    &mut result,
+                )?;
+            }
+        }
+        Ok(result)
+    }
 }
 
 impl<T> MaybeEncrypted<T>
@@ -67,40 +133,87 @@ where
     }
 
     pub fn encrypt(&self, encryption_key: &[u8; 32]) -> crate::Result<Self> {
+        match self {
+            MaybeEncrypted::Plain(plaintext) => Ok(MaybeEncrypted::Encrypted(Ciphertext::Raw(
+                StringEncoding::new_base64(&seal(plaintext, encryption_key)?),
+            ))),
+            MaybeEncrypted::Encrypted(_) => Err(Error::MaybeEncryptedWrongState),
+        }
+    }
+
+    pub fn encrypt_with_password(&self, password: &[u8], kdf: Kdf) -> crate::Result<Self> {
         match self {
             MaybeEncrypted::Plain(plaintext) => {
-                let cipher = get_cipher(encryption_key);
-                let mut nonce_bytes = [0u8; 12]; // Suggested 96 bits
-                thread_rng().fill(&mut nonce_bytes);
-                let nonce = GenericArray::from_slice(&nonce_bytes);
-                let plaintext = serde_json::to_vec(plaintext)?;
-                let ciphertext = cipher.encrypt(&nonce, &plaintext[..])?;
-                let mut result = nonce_bytes.to_vec();
-                result.extend(ciphertext);
-                Ok(MaybeEncrypted::Encrypted(StringEncoding::new_base64(
-                    &result,
-                )))
+                let mut salt = [0u8; 16];
+                thread_rng().fill(&mut salt);
+                let encryption_key = kdf.derive(password, &salt)?;
+                Ok(MaybeEncrypted::Encrypted(Ciphertext::Kdf {
+                    kdf,
+                    salt: StringEncoding::new_base64(&salt),
+                    data: StringEncoding::new_base64(&seal(plaintext, &encryption_key)?),
+                }))
             }
             MaybeEncrypted::Encrypted(_) => Err(Error::MaybeEncryptedWrongState),
         }
     }
 
+    /// Encrypt with a key derived with the default KDF if `encryption_key` is a password,
+    /// otherwise `encryption_key` must be 32 bytes
+    pub fn encrypt_with(&self, encryption_key: &StringEncoding) -> crate::Result<Self> {
+        match encryption_key {
+            StringEncoding::Password(_) => {
+                self.encrypt_with_password(&encryption_key.as_bytes()?, Kdf::default())
+            }
+            _ => self.encrypt(&encryption_key.get_exactly_32()?),
+        }
+    }
+
     pub fn decrypt(&self, encryption_key: &[u8; 32]) -> crate::Result<Self> {
         match self {
-            MaybeEncrypted::Plain(_) => Err(Error::MaybeEncryptedWrongState),
-            MaybeEncrypted::Encrypted(ciphertext) => {
-                let cipher = get_cipher(encryption_key);
-                let ciphertext = ciphertext.as_bytes()?;
-                let nonce_bytes = &ciphertext[0..12];
-                let nonce = GenericArray::from_slice(&nonce_bytes);
-                let plaintext = cipher.decrypt(&nonce, &ciphertext[12..])?;
-                let result = serde_json::from_slice(&plaintext)?;
-                Ok(MaybeEncrypted::Plain(result))
+            MaybeEncrypted::Encrypted(Ciphertext::Raw(ciphertext)) => Ok(MaybeEncrypted::Plain(
+                open(&ciphertext.as_bytes()?, encryption_key)?,
+            )),
+            _ => Err(Error::MaybeEncryptedWrongState),
+        }
+    }
+
+    /// Decrypt with the mode stored in the envelope, accepting either a raw key or a password
+    pub fn decrypt_with(&self, encryption_key: &StringEncoding) -> crate::Result<Self> {
+        match self {
+            MaybeEncrypted::Encrypted(Ciphertext::Kdf { kdf, salt, data }) => {
+                let encryption_key = kdf.derive(&encryption_key.as_bytes()?, &salt.as_bytes()?)?;
+                Ok(MaybeEncrypted::Plain(open(
+                    &data.as_bytes()?,
+                    &encryption_key,
+                )?))
             }
+            _ => self.decrypt(&encryption_key.get_exactly_32()?),
         }
     }
 }
 
+fn seal<T: Serialize>(plaintext: &T, encryption_key: &[u8; 32]) -> Result<Vec<u8>> {
+    let cipher = get_cipher(encryption_key);
+    let mut nonce_bytes = [0u8; 12]; // Suggested 96 bits
+    thread_rng().fill(&mut nonce_bytes);
+    let nonce = GenericArray::from_slice(&nonce_bytes);
+    let plaintext = serde_json::to_vec(plaintext)?;
+    let ciphertext = cipher.encrypt(nonce, &plaintext[..])?;
+    let mut result = nonce_bytes.to_vec();
+    result.extend(ciphertext);
+    Ok(result)
+}
+
+fn open<T: DeserializeOwned>(ciphertext: &[u8], encryption_key: &[u8; 32]) -> Result<T> {
+    if ciphertext.len() < 12 {
+        return Err(Error::MaybeEncryptedWrongState);
+    }
+    let cipher = get_cipher(encryption_key);
+    let nonce = GenericArray::from_slice(&ciphertext[0..12]);
+    let plaintext = cipher.decrypt(nonce, &ciphertext[12..])?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
 fn get_cipher(encryption_key: &[u8; 32]) -> Aes256GcmSiv {
     let encryption_key = GenericArray::from_slice(&encryption_key[..]);
     Aes256GcmSiv::new(&encryption_key)
@@ -109,8 +222,8 @@ fn get_cipher(encryption_key: &[u8; 32]) -> Aes256GcmSiv {
 #[cfg(test)]
 mod tests {
     use crate::common::mnemonic::Mnemonic;
-    use crate::offline::decrypt::MaybeEncrypted;
-    use crate::PrivateMasterKeyJson;
+    use crate::offline::decrypt::{Ciphertext, Kdf, MaybeEncrypted};
+    use crate::{Error, PrivateMasterKeyJson, StringEncoding};
     use bitcoin::util::bip32::ExtendedPubKey;
     use bitcoin::Network;
     use rand::{thread_rng, Rng};
@@ -145,6 +258,7 @@ mod tests {
             )
             .unwrap(),
             "ciao",
+            None,
         )
         .unwrap();
         let maybe_plain = MaybeEncrypted::plain(key_json);
@@ -152,4 +266,49 @@ mod tests {
         let maybe_plain_again = maybe_encrypt.decrypt(&cipher_key).unwrap();
         assert_eq!(maybe_plain, maybe_plain_again);
     }
+
+    #[test]
+    fn test_maybe_encrypted_password() {
+        let kdf = Kdf::Argon2id {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let maybe_plain = MaybeEncrypted::plain("secret".to_string());
+        let maybe_encrypt = maybe_plain
+            .encrypt_with_password(b"correct horse", kdf.clone())
+            .unwrap();
+        match &maybe_encrypt {
+            MaybeEncrypted::Encrypted(Ciphertext::Kdf { kdf: stored, .. }) => {
+                assert_eq!(stored, &kdf)
+            }
+            _ => panic!("expected kdf envelope"),
+        }
+        let json = serde_json::to_string(&maybe_encrypt).unwrap();
+        let maybe_encrypt: MaybeEncrypted<String> = serde_json::from_str(&json).unwrap();
+        let password = StringEncoding::Password("correct horse".to_string());
+        assert_eq!(maybe_encrypt.decrypt_with(&password).unwrap(), maybe_plain);
+        let wrong = StringEncoding::Password("wrong horse".to_string());
+        assert!(maybe_encrypt.decrypt_with(&wrong).is_err());
+        assert!(maybe_encrypt.decrypt(&[0u8; 32]).is_err());
+
+        // parameters in the envelope are bounded before deriving
+        let json = json.replace(r#""m_cost":64"#, r#""m_cost":4294967295"#);
+        let maybe_encrypt: MaybeEncrypted<String> = serde_json::from_str(&json).unwrap();
+        let result = maybe_encrypt.decrypt_with(&password);
+        assert!(matches!(
+            result,
+            Err(Error::KdfParamsTooHigh(u32::MAX, 1, 1))
+        ));
+
+        let mut cipher_key = [0u8; 32];
+        thread_rng().fill(&mut cipher_key);
+        let raw_key = StringEncoding::new_base64(&cipher_key);
+        let maybe_encrypt = maybe_plain.encrypt_with(&raw_key).unwrap();
+        let json = serde_json::to_string(&maybe_encrypt).unwrap();
+        assert!(json.starts_with(r#"{"t":"encrypted","c":{"t":"base64","c":"#));
+        let maybe_encrypt: MaybeEncrypted<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(maybe_encrypt.decrypt_with(&raw_key).unwrap(), maybe_plain);
+        assert!(maybe_encrypt.decrypt_with(&password).is_err());
+    }
 }
@@ -1,14 +1,16 @@
 use crate::file::save_keys;
 use crate::offline::sign::read_key;
+use crate::qr::QrFormat;
 use crate::{MasterKeyOutput, PrivateMasterKeyJson, StringEncoding};
 use bitcoin::secp256k1::Secp256k1;
 use bitcoin::util::bip32::ChildNumber;
 use bitcoin::Network;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 /// Restore a master key from the secret component
-#[derive(StructOpt, Debug, Clone)]
+#[derive(StructOpt, Debug, Clone, Serialize, Deserialize)]
 #[structopt(name = "derive_key")]
 pub struct DeriveKeyOptions {
     /// Name of the master^2 key
@@ -23,10 +25,21 @@ pub struct DeriveKeyOptions {
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
 
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
     /// Optional encryption key for reading/writing the key file encrypted.
     /// in CLI it is populated from standard input
     #[structopt(skip)]
     pub encryption_key: Option<StringEncoding>,
+
+    /// BIP39 passphrase, required if the master^2 key has been created with one
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    #[serde(default)]
+    pub passphrase: Option<String>,
 }
 
 pub fn start(
@@ -39,7 +52,7 @@ pub fn start(
     }
     let secp = Secp256k1::signing_only();
     let from_key_json = read_key(&opt.from_key_file, opt.encryption_key.as_ref())?;
-    let mut child_key = from_key_json.xprv;
+    let mut child_key = from_key_json.signing_xprv(opt.passphrase.as_deref())?;
     let bytes = opt.to_key_name.as_bytes();
     for byte in bytes {
         let path = [ChildNumber::from_hardened_idx(*byte as u32)?];
@@ -53,6 +66,7 @@ pub fn start(
         &opt.to_key_name,
         child_key_json,
         opt.qr_version,
+        opt.qr_format,
         opt.encryption_key.as_ref(),
     )?;
 
@@ -61,8 +75,11 @@ pub fn start(
 
 #[cfg(test)]
 mod tests {
+    use crate::file::save_keys;
     use crate::offline::derive_key::DeriveKeyOptions;
     use crate::offline::random::RandomOptions;
+    use crate::qr::QrFormat;
+    use crate::{Error, PrivateMasterKeyJson};
     use bitcoin::Network;
     use tempfile::TempDir;
 
@@ -81,7 +98,9 @@ mod tests {
             from_key_file: key.private_file.clone(),
             to_key_name,
             qr_version: 14,
+            qr_format: QrFormat::default(),
             encryption_key: None,
+            passphrase: None,
         };
         let derived =
             crate::offline::derive_key::start(&temp_dir_str, Network::Testnet, &der_opts.clone())
@@ -106,4 +125,53 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_derive_key_passphrase() {
+        let temp_dir = TempDir::new().unwrap();
+        let temp_dir_str = format!("{}/", temp_dir.path().display());
+
+        let mut rand_opts = RandomOptions::new("random".to_string());
+        rand_opts.passphrase = Some("TREZOR".to_string());
+        let key = crate::offline::random::create_key(&temp_dir_str, Network::Testnet, &rand_opts)
+            .unwrap();
+
+        let mut der_opts = DeriveKeyOptions {
+            from_key_file: key.private_file.clone(),
+            to_key_name: "derived".to_string(),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+            passphrase: None,
+        };
+        let result = crate::offline::derive_key::start(&temp_dir_str, Network::Testnet, &der_opts);
+        assert!(matches!(result, Err(Error::MissingPassphrase)));
+
+        der_opts.passphrase = Some("TREZOR".to_string());
+        let derived =
+            crate::offline::derive_key::start(&temp_dir_str, Network::Testnet, &der_opts).unwrap();
+
+        // same child of the key restored with the passphrase in another datadir
+        let temp_dir_2 = TempDir::new().unwrap();
+        let temp_dir_str_2 = format!("{}/", temp_dir_2.path().display());
+        let xprv = key.key.signing_xprv(Some("TREZOR")).unwrap();
+        let restored = PrivateMasterKeyJson::from_xprv(xprv, "restored");
+        let restored_file = save_keys(
+            &temp_dir_str_2,
+            Network::Testnet,
+            "restored",
+            restored,
+            14,
+            QrFormat::default(),
+            None,
+        )
+        .unwrap()
+        .private_file;
+        der_opts.from_key_file = restored_file;
+        der_opts.passphrase = None;
+        let derived_2 =
+            crate::offline::derive_key::start(&temp_dir_str_2, Network::Testnet, &der_opts)
+                .unwrap();
+        assert_eq!(derived.key, derived_2.key);
+    }
 }
@@ -33,6 +33,7 @@ pub fn derive_address(network: Network, opt: &DeriveAddressOpts) -> Result<GetAd
         address,
         path,
         qr_file: None,
+        uri: None,
     })
 }
 
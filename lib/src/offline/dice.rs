@@ -1,7 +1,9 @@
 use crate::mnemonic::Mnemonic;
+use crate::qr::QrFormat;
 use crate::*;
 use bitcoin::Network;
 use num_bigint::BigUint;
+use rand::Rng;
 use serde::{de, Deserialize, Deserializer, Serialize};
 use std::io;
 use std::str::FromStr;
@@ -31,11 +33,37 @@ pub struct DiceOptions {
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
 
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
     /// in CLI it is populated from standard input
     #[structopt(skip)]
     pub encryption_key: Option<StringEncoding>,
+
+    /// Optional BIP39 passphrase, it is not saved in the key file
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub passphrase: Option<String>,
+
+    /// Create the key even if the launches fail the randomness checks
+    #[structopt(long)]
+    #[serde(default)]
+    pub force: bool,
+
+    /// XOR the dice entropy with entropy from this machine RNG
+    #[structopt(long)]
+    #[serde(default)]
+    pub xor_random: bool,
 }
 
+/// A pattern expected less than this number of times in random launches is considered suspicious
+const SUSPICIOUS_EXPECTATION: f64 = 0.001;
+
+/// The chi-square test is meaningful only if every face is expected at least this number of times
+const MIN_CHI_SQUARE_EXPECTATION: f64 = 5.0;
+
 #[derive(Debug, Clone, Serialize)]
 pub enum Bits {
     _128,
@@ -70,32 +98,113 @@ impl DiceOptions {
             .into());
         }
 
-        for n in self.launches.iter() {
-            if *n > faces || *n == 0 {
-                return Err(Error::DiceValueErr(*n, faces));
-            }
-        }
+        check_launches(&self.launches, faces)
+    }
+}
 
-        Ok(())
+/// Every launch must be a face of the dice, from 1 to `faces`
+pub fn check_launches(launches: &[u32], faces: u32) -> Result<()> {
+    for n in launches.iter() {
+        if *n > faces || *n == 0 {
+            return Err(Error::DiceValueErr(*n, faces));
+        }
     }
+    Ok(())
 }
 
 pub fn roll(datadir: &str, network: Network, opt: &DiceOptions) -> Result<MasterKeyOutput> {
     opt.validate()?;
 
-    let master_key = calculate_key(&opt.launches, opt.faces as u32, network, &opt.key_name)?;
-    let output = save_keys(
+    let checks = entropy_checks(&opt.launches, opt.faces);
+    if !checks.failures.is_empty() && !opt.force {
+        return Err(Error::DiceEntropyChecks(checks.failures));
+    }
+
+    let random = rand::thread_rng().gen::<[u8; 32]>();
+    let master_key = calculate_key(
+        &opt.launches,
+        opt.faces as u32,
+        network,
+        &opt.key_name,
+        opt.passphrase.as_deref(),
+        if opt.xor_random { Some(&random) } else { None },
+    )?;
+    let mut output = save_keys(
         datadir,
         network,
         &opt.key_name,
         master_key,
         opt.qr_version,
+        opt.qr_format,
         opt.encryption_key.as_ref(),
     )?;
+    output.entropy_checks = Some(checks);
 
     Ok(output)
 }
 
+/// Statistical checks on the launches, failures are reported as human readable strings
+fn entropy_checks(launches: &[u32], faces: Base) -> EntropyChecks {
+    let n = launches.len();
+    let faces_f64 = faces as u32 as f64;
+
+    let expected = n as f64 / faces_f64;
+    let chi_square = (1..=faces as u32)
+        .map(|face| {
+            let observed = launches.iter().filter(|l| **l == face).count() as f64;
+            (observed - expected).powi(2) / expected
+        })
+        .sum();
+
+    let steps: Vec<u32> = launches
+        .windows(2)
+        .map(|w| (w[1] + faces as u32 - w[0]) % faces as u32)
+        .collect();
+    let longest_run = longest_repetition(&steps, |step| step == 0) + 1;
+    let longest_sequence = longest_repetition(&steps, |step| step != 0) + 1;
+
+    let mut failures = vec![];
+    // with few launches per face, like 128 bits with a d20, the test would fail fair dice
+    if expected >= MIN_CHI_SQUARE_EXPECTATION && chi_square > faces.chi_square_critical() {
+        failures.push(format!("faces frequency chi-square is {:.1}", chi_square));
+    }
+    // a run of `k` equal launches is expected `n / faces^(k-1)` times
+    if n as f64 / faces_f64.powi(longest_run as i32 - 1) < SUSPICIOUS_EXPECTATION {
+        failures.push(format!("{} equal launches in a row", longest_run));
+    }
+    // a sequence of `k` launches with constant step is expected about `n / faces^(k-2)` times
+    if n as f64 / faces_f64.powi(longest_sequence as i32 - 2) < SUSPICIOUS_EXPECTATION {
+        failures.push(format!(
+            "{} launches in a sequence with constant step",
+            longest_sequence
+        ));
+    }
+
+    EntropyChecks {
+        chi_square,
+        longest_run,
+        longest_sequence,
+        failures,
+    }
+}
+
+/// Longest number of consecutive equal `steps` satisfying `filter`
+fn longest_repetition(steps: &[u32], filter: impl Fn(u32) -> bool) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for (i, step) in steps.iter().enumerate() {
+        if !filter(*step) {
+            current = 0;
+        } else if i > 0 && steps[i - 1] == *step {
+            current += 1;
+        } else {
+            current = 1;
+        }
+        longest = longest.max(current);
+    }
+    longest
+}
+
 fn multiply_dice_launches(launches: &[u32], base: u32) -> BigUint {
     let init = BigUint::from(launches[0] - 1);
     launches.iter().skip(1).fold(init, |mut sum, i| {
@@ -118,28 +227,48 @@ fn required_dice_launches(faces: u32, max: &BigUint) -> u32 {
     }
 }
 
-fn calculate_key(
+pub(crate) fn calculate_key(
     launches: &[u32],
     faces: u32,
     network: Network,
     name: &str,
+    passphrase: Option<&str>,
+    random: Option<&[u8; 32]>,
 ) -> Result<PrivateMasterKeyJson> {
     let acc = multiply_dice_launches(&launches, faces);
 
-    let sec = acc.to_bytes_be();
+    let mut sec = acc.to_bytes_be();
+    if let Some(random) = random {
+        sec.iter_mut().zip(random.iter()).for_each(|(s, r)| *s ^= r);
+    }
     let mnemonic = Mnemonic::new(&sec)?;
 
-    let mut key = PrivateMasterKeyJson::new(network, &mnemonic, name)?;
+    let mut key = PrivateMasterKeyJson::new(network, &mnemonic, name, passphrase)?;
     let dice = Dice {
         faces,
         launches: format!("{:?}", launches),
         value: acc.to_string(),
+        xor_random: random.is_some(),
     };
     key.dice = Some(dice);
 
     Ok(key)
 }
 
+impl Base {
+    /// chi-square critical value at p=0.001 with `faces - 1` degrees of freedom
+    fn chi_square_critical(self) -> f64 {
+        match self {
+            Base::_2 => 10.828,
+            Base::_4 => 16.266,
+            Base::_6 => 20.515,
+            Base::_8 => 24.322,
+            Base::_12 => 31.264,
+            Base::_20 => 43.820,
+        }
+    }
+}
+
 impl From<Bits> for BigUint {
     fn from(bits: Bits) -> Self {
         let one = BigUint::from(1u32);
@@ -225,10 +354,20 @@ mod tests {
             key_name: "a".to_string(),
             launches,
             qr_version: 14,
+            qr_format: QrFormat::default(),
             encryption_key: None,
+            passphrase: None,
+            force: false,
+            xor_random: false,
         };
 
-        roll(&temp_dir_str, Network::Testnet, &opt).unwrap();
+        let result = roll(&temp_dir_str, Network::Testnet, &opt);
+        assert!(matches!(result, Err(Error::DiceEntropyChecks(_))));
+        opt.force = true;
+        let master_key = roll(&temp_dir_str, Network::Testnet, &opt).unwrap();
+        let checks = master_key.entropy_checks.unwrap();
+        assert_eq!(checks.longest_run, 29);
+        assert_eq!(checks.failures.len(), 1); // chi-square skipped, 29 launches of a d20
 
         opt.launches = vec![1u32; 28];
         opt.key_name = "b".to_string();
@@ -255,7 +394,55 @@ mod tests {
             master_key.key.dice.unwrap().value,
             "2825636378947368421052631578947368421"
         );
-        assert_eq!("xprv9s21ZrQH143K3yGb6gtghzHH4MPaEHGPN48sxoyYd4EdrQcaSVP2dxZS2vRwoKny1KRS5xMMyGunA3WkToah7ZmJ2fFtGK8vBBBiBkVFmTM", master_key.key.xprv.to_string());
+        assert_eq!("xprv9s21ZrQH143K3yGb6gtghzHH4MPaEHGPN48sxoyYd4EdrQcaSVP2dxZS2vRwoKny1KRS5xMMyGunA3WkToah7ZmJ2fFtGK8vBBBiBkVFmTM", master_key.key.xprv.unwrap().to_string());
+    }
+
+    #[test]
+    fn test_entropy_checks() {
+        let launches = [
+            3, 17, 8, 12, 1, 20, 9, 5, 14, 11, 6, 19, 2, 16, 10, 7, 13, 4, 18, 15, 9, 1, 12, 20, 6,
+            3, 17, 11, 8,
+        ];
+        let checks = entropy_checks(&launches, Base::_20);
+        assert!(checks.failures.is_empty(), "{:?}", checks.failures);
+        assert_eq!(checks.longest_run, 1);
+
+        let sequential: Vec<u32> = (0..29).map(|i| i % 20 + 1).collect();
+        let checks = entropy_checks(&sequential, Base::_20);
+        assert_eq!(checks.longest_sequence, 29);
+        assert_eq!(checks.failures.len(), 1);
+
+        // only three faces of a d6, with 49 launches every face is expected 8 times
+        let biased = [1u32, 3, 2].repeat(17)[..49].to_vec();
+        let checks = entropy_checks(&biased, Base::_6);
+        assert_eq!(checks.failures.len(), 1, "{:?}", checks.failures);
+        assert!(checks.failures[0].contains("chi-square"));
+        // the same with a d20 is expected less than 5 times, too few for the test
+        let checks = entropy_checks(&biased[..29], Base::_20);
+        assert!(checks.chi_square > Base::_20.chi_square_critical());
+        assert!(checks.failures.is_empty(), "{:?}", checks.failures);
+
+        let mut coin = vec![1u32, 2, 2, 1, 2, 1, 1, 1, 2, 2, 1, 2, 1, 2, 2, 1].repeat(8);
+        let checks = entropy_checks(&coin, Base::_2);
+        assert!(checks.failures.is_empty(), "{:?}", checks.failures);
+        coin[40..60].iter_mut().for_each(|l| *l = 2);
+        let checks = entropy_checks(&coin, Base::_2);
+        assert_eq!(checks.longest_run, 20);
+        assert!(checks.failures[0].contains("20 equal launches"));
+    }
+
+    #[test]
+    fn test_xor_random() {
+        let launches = vec![2, 3, 4, 5, 6, 7, 8, 9];
+        let plain = calculate_key(&launches, 256, Network::Bitcoin, "a", None, None).unwrap();
+        let random = [0u8; 32];
+        let zero = calculate_key(&launches, 256, Network::Bitcoin, "a", None, Some(&random));
+        let zero = zero.unwrap();
+        assert_eq!(plain.xprv, zero.xprv);
+        assert!(zero.dice.unwrap().xor_random);
+        let random = [1u8; 32];
+        let xored = calculate_key(&launches, 256, Network::Bitcoin, "a", None, Some(&random));
+        assert_ne!(plain.xprv, xored.unwrap().xprv);
     }
 
     #[test]
@@ -310,20 +497,30 @@ mod tests {
         /*
         let bytes = include_bytes!("../../test_data/dice/priv1.key");
         let expected: PrivateMasterKey = serde_json::from_slice(bytes).unwrap();
-        let calculated = calculate_key(&vec![2], 2, Network::Bitcoin, "name").unwrap();
+        let calculated = calculate_key(&[2], 2, Network::Bitcoin, "name", None).unwrap();
 
         assert_eq!(calculated, expected);
         */
 
         let bytes = include_bytes!("../../test_data/dice/priv2.key");
         let expected: PrivateMasterKeyJson = serde_json::from_slice(bytes).unwrap();
-        let calculated =
-            calculate_key(&vec![2, 3, 4, 5, 6, 7, 8, 9], 256, Network::Bitcoin, "name").unwrap();
+        let calculated = calculate_key(
+            &[2, 3, 4, 5, 6, 7, 8, 9],
+            256,
+            Network::Bitcoin,
+            "name",
+            None,
+            None,
+        )
+        .unwrap();
         assert_eq!(
             calculated.fingerprint.to_string(),
             expected.fingerprint.to_string()
         );
-        assert_eq!(calculated.xprv.to_string(), expected.xprv.to_string());
+        assert_eq!(
+            calculated.xprv.unwrap().to_string(),
+            expected.xprv.unwrap().to_string()
+        );
         assert_eq!(calculated.xpub.to_string(), expected.xpub.to_string());
         assert_eq!(calculated, expected);
     }
@@ -1,10 +1,17 @@
 use crate::offline::descriptor::extract_xpubs;
+use crate::qr::QrFormat;
 use crate::*;
 use bitcoin::Network;
+use std::path::PathBuf;
 
 /// Import a json wallet, used in firma-offline to import existing wallet json descriptor
 //TODO android-only at the moment, add support also from command line
-pub fn import_wallet(datadir: &str, network: Network, wallet: &WalletJson) -> Result<()> {
+pub fn import_wallet(
+    datadir: &str,
+    network: Network,
+    wallet: &WalletJson,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<PathBuf> {
     extract_xpubs(&wallet.descriptor)?
         .iter()
         .map(|xpub| check_compatibility(network, xpub.network))
@@ -13,11 +20,20 @@ pub fn import_wallet(datadir: &str, network: Network, wallet: &WalletJson) -> Re
         firma_datadir: datadir.to_string(),
         network,
         wallet_name: wallet.name.clone(),
+        encryption_key: encryption_key.cloned(),
     };
-    context.save_wallet(&wallet)?;
-    let qr_bytes = serde_json::to_vec(&wallet)?;
+    let wallet_file = context.save_wallet(wallet)?;
+    if !context.qr_allowed() {
+        return Ok(wallet_file);
+    }
+    let qr_bytes = common::compact_wallet::wallet_to_compact(wallet)?;
 
     let wallet_qr_path = context.path_for_wallet_qr()?;
-    common::qr::save_qrs(qr_bytes, wallet_qr_path, 14)?;
-    Ok(())
+    common::qr::save_qrs(qr_bytes, wallet_qr_path, 14, QrFormat::default())?;
+
+    let cbor = common::ur::output_to_cbor(wallet)?;
+    let wallet_ur_path = context.path_for_wallet_ur()?;
+    let ur_type = common::ur::UrType::CryptoOutput;
+    common::ur::save_ur_qrs(ur_type, &cbor, wallet_ur_path, 14, QrFormat::default())?;
+    Ok(wallet_file)
 }
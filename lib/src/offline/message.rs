@@ -0,0 +1,572 @@
+use crate::common::proprietary::set_psbt_metadata;
+use crate::offline::sign::{read_key, save_psbt};
+use crate::offline::sign_wallet::{sign_message_with_key, verify_message_with_address};
+use crate::qr::QrFormat;
+use crate::*;
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Message, Secp256k1, Verification};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+use bitcoin::util::psbt;
+use bitcoin::{
+    Address, Network, OutPoint, PublicKey, Script, SigHashType, Transaction, TxIn, TxOut,
+};
+use miniscript::descriptor::DescriptorPublicKey;
+use miniscript::{Descriptor, DescriptorPublicKeyCtx, Miniscript, Segwitv0, Terminal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// Sign a message with a key in the legacy format, or create the BIP322 PSBT proving control of a
+/// wallet address, to be signed by the cosigners with the `sign` command
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct SignMessageOptions {
+    /// Message to sign
+    #[structopt(long)]
+    pub message: String,
+
+    /// File containing the master key, the message is signed with the legacy format
+    #[structopt(short, long, parse(from_os_str))]
+    pub key: Option<PathBuf>,
+
+    /// Derivation path from the master key of the signing key, by default the master key itself
+    #[structopt(long)]
+    #[serde(default)]
+    pub path: Option<DerivationPath>,
+
+    /// Name of the wallet owning the address, a BIP322 PSBT is created
+    #[structopt(long)]
+    pub wallet_name: Option<String>,
+
+    /// Index of the wallet address
+    #[structopt(long, default_value = "0")]
+    #[serde(default)]
+    pub index: u32,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
+    /// Optional encryption key for reading the key file encrypted
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+
+    /// Optional datadir encryption key, for the wallet and the PSBT files
+    #[structopt(skip)]
+    #[serde(default)]
+    pub datadir_encryption_key: Option<StringEncoding>,
+
+    /// BIP39 passphrase, required if the key has been created with one
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub passphrase: Option<String>,
+}
+
+/// Verify a message signature made with the legacy format or with BIP322, or complete and verify
+/// the BIP322 signature from a PSBT signed by the cosigners
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct VerifyMessageOptions {
+    /// Message signed
+    #[structopt(long)]
+    pub message: String,
+
+    /// Address of the signer, p2pkh for legacy signatures or p2wsh multisig for BIP322 ones
+    #[structopt(long)]
+    pub address: Option<Address>,
+
+    /// Signature as base64
+    #[structopt(long)]
+    pub signature: Option<String>,
+
+    /// BIP322 PSBT created by `sign-message` and signed by the cosigners
+    #[structopt(long, parse(from_os_str))]
+    pub psbt_file: Option<PathBuf>,
+
+    /// Optional datadir encryption key
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+}
+
+pub fn sign_message(
+    datadir: &str,
+    network: Network,
+    opt: &SignMessageOptions,
+) -> Result<MessageSignatureOutput> {
+    match (&opt.key, &opt.wallet_name) {
+        (Some(key), None) => sign_legacy(network, key, opt),
+        (None, Some(wallet_name)) => create_bip322_psbt(datadir, network, wallet_name, opt),
+        _ => Err(Error::SignMessageTarget),
+    }
+}
+
+fn sign_legacy(
+    network: Network,
+    key: &PathBuf,
+    opt: &SignMessageOptions,
+) -> Result<MessageSignatureOutput> {
+    let secp = Secp256k1::signing_only();
+    let key = read_key(key, opt.encryption_key.as_ref())?;
+    let path = opt.path.clone().unwrap_or_else(|| vec![].into());
+    let xprv = key
+        .signing_xprv(opt.passphrase.as_deref())?
+        .derive_priv(&secp, &path)?;
+    let xpub = ExtendedPubKey::from_private(&secp, &xprv);
+    let signature = sign_message_with_key(&xprv.private_key, &opt.message, &secp)?;
+
+    Ok(MessageSignatureOutput {
+        address: Address::p2pkh(&xpub.public_key, network),
+        message: opt.message.clone(),
+        signature: Some(signature),
+        path: Some(path),
+        psbt_file: None,
+        verified: None,
+    })
+}
+
+fn create_bip322_psbt(
+    datadir: &str,
+    network: Network,
+    wallet_name: &str,
+    opt: &SignMessageOptions,
+) -> Result<MessageSignatureOutput> {
+    let wallet_path = PathBuilder::new(
+        datadir,
+        network,
+        Kind::Wallet,
+        Some(wallet_name.to_string()),
+    );
+    let wallet = read_wallet(
+        &wallet_path.file("descriptor.json")?,
+        opt.datadir_encryption_key.as_ref(),
+    )?;
+    let end = wallet
+        .descriptor
+        .find('#')
+        .unwrap_or(wallet.descriptor.len());
+    let descriptor: Descriptor<DescriptorPublicKey> = wallet.descriptor[..end].parse()?;
+    let child = ChildNumber::from_normal_idx(opt.index)?;
+    let descriptor = descriptor.derive(child);
+    let secp = Secp256k1::verification_only();
+    let context = DescriptorPublicKeyCtx::new(&secp, child);
+    let address = descriptor
+        .address(network, context)
+        .ok_or(Error::AddressFromDescriptorFails)?;
+    let witness_script = descriptor.witness_script(context);
+
+    let to_spend = to_spend(&opt.message, &address.script_pubkey());
+    let mut psbt = PSBT::from_unsigned_tx(to_sign(&to_spend))?;
+    let input = &mut psbt.inputs[0];
+    input.witness_utxo = Some(to_spend.output[0].clone());
+    input.non_witness_utxo = Some(to_spend);
+    input.witness_script = Some(witness_script);
+    input.sighash_type = Some(SigHashType::All);
+    input.hd_keypaths = key_origins(&descriptor, &secp)?;
+
+    let metadata = PsbtMetadata {
+        creator: Some(format!("firma {}", env!("CARGO_PKG_VERSION"))),
+        wallet_name: Some(wallet.name),
+        memo: Some(opt.message.clone()),
+        ..Default::default()
+    };
+    set_psbt_metadata(&mut psbt, &metadata)?;
+
+    let mut psbts_dir = PathBuilder::new(datadir, network, Kind::PSBT, None).type_path()?;
+    let (psbt_file, _, _) = save_psbt(
+        &mut psbt,
+        &mut psbts_dir,
+        opt.qr_version,
+        opt.qr_format,
+        opt.datadir_encryption_key.as_ref(),
+    )?;
+
+    Ok(MessageSignatureOutput {
+        address,
+        message: opt.message.clone(),
+        signature: None,
+        path: None,
+        psbt_file: Some(psbt_file),
+        verified: None,
+    })
+}
+
+pub fn verify_message(
+    network: Network,
+    opt: &VerifyMessageOptions,
+) -> Result<MessageSignatureOutput> {
+    let secp = Secp256k1::verification_only();
+    let (address, signature, psbt_file) = match (&opt.address, &opt.signature, &opt.psbt_file) {
+        (Some(address), Some(signature), None) => (address.clone(), signature.clone(), None),
+        (None, None, Some(psbt_file)) => {
+            let psbt = read_psbt(psbt_file, opt.encryption_key.as_ref())?;
+            let (address, signature) = finalize_bip322_psbt(&psbt, &opt.message, network)?;
+            (address, signature, Some(psbt_file.clone()))
+        }
+        _ => return Err(Error::VerifyMessageSource),
+    };
+
+    let script_pubkey = address.script_pubkey();
+    let verified = if script_pubkey.is_p2pkh() {
+        verify_message_with_address(&address, &signature, &opt.message, &secp)?
+    } else if script_pubkey.is_v0_p2wsh() {
+        verify_bip322(&address, &signature, &opt.message, &secp)?
+    } else {
+        return Err(Error::MessageUnsupportedAddress(address.to_string()));
+    };
+
+    Ok(MessageSignatureOutput {
+        address,
+        message: opt.message.clone(),
+        signature: Some(signature),
+        path: None,
+        psbt_file,
+        verified: Some(verified),
+    })
+}
+
+/// BIP340 tagged hash of the message as specified in BIP322
+pub fn message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(BIP322_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// The virtual transaction committing to the message and spent by `to_sign`
+pub fn to_spend(message: &str, script_pubkey: &Script) -> Transaction {
+    let script_sig = Builder::new()
+        .push_int(0)
+        .push_slice(&message_hash(message)[..])
+        .into_script();
+    Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: 0,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The virtual transaction whose witness is the BIP322 signature
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: 0,
+            witness: vec![],
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+fn key_origins<C: Verification>(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    secp: &Secp256k1<C>,
+) -> Result<BTreeMap<PublicKey, (Fingerprint, DerivationPath)>> {
+    let mut result = BTreeMap::new();
+    if let Descriptor::Wsh(miniscript) = descriptor {
+        for key in miniscript.get_leaf_pk() {
+            if let DescriptorPublicKey::XPub(xpub) = key {
+                let public_key = xpub
+                    .xkey
+                    .derive_pub(secp, &xpub.derivation_path)?
+                    .public_key;
+                let origin = match xpub.origin {
+                    Some((fingerprint, path)) => (fingerprint, path.extend(&xpub.derivation_path)),
+                    None => (xpub.xkey.fingerprint(), xpub.derivation_path),
+                };
+                result.insert(public_key, origin);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Threshold and keys of a `multi` witness script
+fn parse_multi(witness_script: &Script) -> Result<(usize, Vec<PublicKey>)> {
+    let miniscript = Miniscript::<PublicKey, Segwitv0>::parse(witness_script)?;
+    match miniscript.node {
+        Terminal::Multi(k, keys) => Ok((k, keys)),
+        _ => Err(Error::MessageUnsupportedAddress(witness_script.to_string())),
+    }
+}
+
+/// Check the PSBT is the BIP322 proof of `message` and build the signature from its partial
+/// signatures
+fn finalize_bip322_psbt(psbt: &PSBT, message: &str, network: Network) -> Result<(Address, String)> {
+    let input = psbt.inputs.first().ok_or(Error::Bip322WrongPsbt)?;
+    let witness_utxo = input.witness_utxo.as_ref().ok_or(Error::Bip322WrongPsbt)?;
+    let to_spend = to_spend(message, &witness_utxo.script_pubkey);
+    if psbt.global.unsigned_tx != to_sign(&to_spend) {
+        return Err(Error::Bip322WrongPsbt);
+    }
+    let address =
+        Address::from_script(&witness_utxo.script_pubkey, network).ok_or(Error::Bip322WrongPsbt)?;
+    let witness_script = input
+        .witness_script
+        .as_ref()
+        .ok_or(Error::Bip322WrongPsbt)?;
+    if witness_script.to_v0_p2wsh() != witness_utxo.script_pubkey {
+        return Err(Error::Bip322WrongPsbt);
+    }
+
+    let witness = multisig_witness(input, witness_script)?;
+    Ok((address, base64::encode(serialize(&witness))))
+}
+
+/// Build the witness of a p2wsh multisig input from its partial signatures
+pub fn multisig_witness(input: &psbt::Input, witness_script: &Script) -> Result<Vec<Vec<u8>>> {
+    let (required, keys) = parse_multi(witness_script)?;
+    // the first element is the dummy consumed by OP_CHECKMULTISIG
+    let mut witness = vec![vec![]];
+    for key in keys.iter() {
+        if witness.len() > required {
+            break;
+        }
+        if let Some(signature) = input.partial_sigs.get(key) {
+            witness.push(signature.clone());
+        }
+    }
+    if witness.len() <= required {
+        return Err(Error::PSBTMissingSignatures(witness.len() - 1, required));
+    }
+    witness.push(witness_script.to_bytes());
+    Ok(witness)
+}
+
+/// Verify the `witness` satisfies the p2wsh multisig `script_pubkey` with SIGHASH_ALL signatures
+/// of the input `input_index` of `tx`
+pub fn verify_multisig_witness<C: Verification>(
+    tx: &Transaction,
+    input_index: usize,
+    value: u64,
+    script_pubkey: &Script,
+    witness: &[Vec<u8>],
+    secp: &Secp256k1<C>,
+) -> Result<bool> {
+    let (witness_script, stack) = witness.split_last().ok_or(Error::InvalidMessageSignature)?;
+    let witness_script = Script::from(witness_script.clone());
+    if &witness_script.to_v0_p2wsh() != script_pubkey {
+        return Ok(false);
+    }
+    let (required, keys) = parse_multi(&witness_script)?;
+    if stack.len() != required + 1 || !stack[0].is_empty() {
+        return Ok(false);
+    }
+
+    let hash =
+        SigHashCache::new(tx).signature_hash(input_index, &witness_script, value, SigHashType::All);
+    let msg = Message::from_slice(&hash[..])?;
+    let mut keys = keys.iter();
+    for signature in stack[1..].iter() {
+        let (sighash, der) = signature
+            .split_last()
+            .ok_or(Error::InvalidMessageSignature)?;
+        if *sighash != SigHashType::All.as_u32() as u8 {
+            return Ok(false);
+        }
+        let signature = match secp256k1::Signature::from_der(der) {
+            Ok(signature) => signature,
+            Err(_) => return Ok(false),
+        };
+        // signatures must be in the same order of the keys
+        if !keys.any(|key| secp.verify(&msg, &signature, &key.key).is_ok()) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Verify a BIP322 simple signature of a p2wsh multisig address
+fn verify_bip322<C: Verification>(
+    address: &Address,
+    signature: &str,
+    message: &str,
+    secp: &Secp256k1<C>,
+) -> Result<bool> {
+    let witness: Vec<Vec<u8>> = deserialize(&base64::decode(signature)?)?;
+    let script_pubkey = address.script_pubkey();
+    let to_sign = to_sign(&to_spend(message, &script_pubkey));
+    verify_multisig_witness(&to_sign, 0, 0, &script_pubkey, &witness, secp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::import_wallet::import_wallet;
+    use crate::offline::message::*;
+    use crate::offline::random::{create_key, RandomOptions};
+    use crate::offline::sign::SignOptions;
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_bip322_vectors() {
+        // test vectors from BIP322
+        assert_eq!(
+            message_hash("").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+        let to_spend = to_spend("", &address.script_pubkey());
+        assert_eq!(
+            to_spend.txid().to_string(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            to_sign(&to_spend).txid().to_string(),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+    }
+
+    #[test]
+    fn test_sign_verify_message() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let network = Network::Testnet;
+        let key_encryption = [None, Some(StringEncoding::new_base64(&[1u8; 32]))];
+        let mut keys = vec![];
+        for (name, encryption_key) in ["a", "b"].iter().zip(key_encryption.iter()) {
+            let mut random_opt = RandomOptions::new(name.to_string());
+            random_opt.encryption_key = encryption_key.clone();
+            keys.push(create_key(datadir, network, &random_opt).unwrap());
+        }
+
+        let mut opt = SignMessageOptions {
+            message: "ciao".to_string(),
+            key: Some(keys[0].private_file.clone()),
+            path: Some(DerivationPath::from_str("m/0/1").unwrap()),
+            wallet_name: None,
+            index: 0,
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+            datadir_encryption_key: None,
+            passphrase: None,
+        };
+        let legacy = sign_message(datadir, network, &opt).unwrap();
+        let mut verify_opt = VerifyMessageOptions {
+            message: "ciao".to_string(),
+            address: Some(legacy.address.clone()),
+            signature: legacy.signature.clone(),
+            psbt_file: None,
+            encryption_key: None,
+        };
+        assert_eq!(
+            verify_message(network, &verify_opt).unwrap().verified,
+            Some(true)
+        );
+        verify_opt.message = "hello".to_string();
+        assert_eq!(
+            verify_message(network, &verify_opt).unwrap().verified,
+            Some(false)
+        );
+
+        let descriptor = format!(
+            "wsh(multi(2,{}/0/*,{}/0/*))",
+            keys[0].key.xpub, keys[1].key.xpub
+        );
+        let wallet = WalletJson {
+            name: "w".to_string(),
+            descriptor,
+            fingerprints: keys.iter().map(|k| k.key.fingerprint).collect(),
+            required_sig: 2,
+            created_at_height: 0,
+        };
+        let wallet_file = import_wallet(datadir, network, &wallet, None).unwrap();
+
+        opt.key = None;
+        opt.wallet_name = Some("w".to_string());
+        opt.index = 3;
+        let created = sign_message(datadir, network, &opt).unwrap();
+        let psbt_file = created.psbt_file.unwrap();
+        assert!(created.signature.is_none());
+
+        let mut verify_opt = VerifyMessageOptions {
+            message: "ciao".to_string(),
+            address: None,
+            signature: None,
+            psbt_file: Some(psbt_file.clone()),
+            encryption_key: None,
+        };
+        assert!(matches!(
+            verify_message(network, &verify_opt),
+            Err(Error::PSBTMissingSignatures(0, 2))
+        ));
+
+        for (key, encryption_key) in keys.iter().zip(key_encryption.iter()) {
+            let sign_opt = SignOptions {
+                key: key.private_file.clone(),
+                total_derivations: 10,
+                wallet_descriptor_file: wallet_file.clone(),
+                qr_version: 14,
+                qr_format: QrFormat::default(),
+                psbt_file: psbt_file.clone(),
+                binary_out: None,
+                allow_any_derivations: false,
+                encryption_key: encryption_key.clone(),
+                datadir_encryption_key: None,
+                passphrase: None,
+            };
+            crate::offline::sign::start(&sign_opt, network).unwrap();
+        }
+        // the key file encryption doesn't encrypt the signed PSBT
+        assert!(read_psbt(&psbt_file, None).is_ok());
+        assert!(psbt_file.with_file_name("qr").exists());
+        assert!(psbt_file.with_file_name("ur").exists());
+        let verified = verify_message(network, &verify_opt).unwrap();
+        assert_eq!(verified.address, created.address);
+        assert_eq!(verified.verified, Some(true));
+
+        verify_opt.psbt_file = None;
+        verify_opt.address = Some(verified.address.clone());
+        verify_opt.signature = verified.signature.clone();
+        assert_eq!(
+            verify_message(network, &verify_opt).unwrap().verified,
+            Some(true)
+        );
+        verify_opt.message = "hello".to_string();
+        assert_eq!(
+            verify_message(network, &verify_opt).unwrap().verified,
+            Some(false)
+        );
+
+        verify_opt.psbt_file = Some(psbt_file);
+        assert!(matches!(
+            verify_message(network, &verify_opt),
+            Err(Error::VerifyMessageSource)
+        ));
+    }
+}
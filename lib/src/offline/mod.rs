@@ -1,10 +1,18 @@
+pub mod backup_sheet;
+pub mod bip85;
 pub mod decrypt;
 pub mod derive_key;
 pub mod descriptor;
 pub mod dice;
 pub mod import_wallet;
+pub mod message;
 pub mod print;
 pub mod random;
+pub mod reencrypt;
+pub mod reserves;
 pub mod restore;
+pub mod scan;
 pub mod sign;
 pub mod sign_wallet;
+pub mod split;
+pub mod verify_backup;
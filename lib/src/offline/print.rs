@@ -1,5 +1,7 @@
+use crate::common::proprietary::get_psbt_metadata;
 use crate::list::ListOptions;
 use crate::offline::descriptor::{derive_address, DeriveAddressOpts};
+use crate::offline::reserves::is_commitment_input;
 use crate::*;
 use bitcoin::consensus::serialize;
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, Fingerprint};
@@ -17,23 +19,28 @@ type HDKeypaths = BTreeMap<key::PublicKey, (Fingerprint, DerivationPath)>;
 #[derive(StructOpt, Debug, Serialize, Deserialize)]
 #[structopt(name = "firma")]
 pub struct PrintOptions {
-    /// PSBT json file
+    /// PSBT file, as json, BIP174 binary, base64 or hex
     #[structopt(long)]
     pub psbt_file: Option<PathBuf>,
 
-    /// PSBT as base64 string
+    /// PSBT as base64 or hex string
     #[structopt(long)]
     pub psbt_base64: Option<String>,
 
     /// Return wallets only if wallet signature file is present and signature verifies
     #[structopt(long)]
     pub verify_wallets_signatures: bool,
+
+    /// Optional datadir encryption key
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
 }
 
 pub fn start(datadir: &str, network: Network, opt: &PrintOptions) -> Result<PsbtPrettyPrint> {
     let psbt = match (&opt.psbt_file, &opt.psbt_base64) {
-        (Some(path), None) => read_psbt(path)?,
-        (None, Some(base64)) => psbt_from_base64(base64)?.1,
+        (Some(path), None) => read_psbt(path, opt.encryption_key.as_ref())?,
+        (None, Some(base64)) => psbt_from_str(base64)?,
         (None, None) => return Err("`psbt_file` or `psbt_base64` must be set".into()),
         (Some(_), Some(_)) => {
             return Err("`psbt_file` and `psbt_base64` cannot be both specified".into())
@@ -43,7 +50,7 @@ pub fn start(datadir: &str, network: Network, opt: &PrintOptions) -> Result<Psbt
     let opt = ListOptions {
         kind,
         verify_wallets_signatures: opt.verify_wallets_signatures,
-        encryption_keys: vec![],
+        encryption_keys: opt.encryption_key.iter().cloned().collect(),
     };
     let result = common::list::list(datadir, network, &opt)?;
     let wallets: Vec<WalletJson> = result.wallets.iter().map(|w| w.wallet.clone()).collect();
@@ -56,7 +63,10 @@ pub fn pretty_print(
     network: Network,
     wallets: &[WalletJson],
 ) -> Result<PsbtPrettyPrint> {
-    let mut result = PsbtPrettyPrint::default();
+    let mut result = PsbtPrettyPrint {
+        metadata: get_psbt_metadata(psbt)?,
+        ..Default::default()
+    };
     let mut previous_outputs: Vec<TxOut> = vec![];
     let mut output_values: Vec<u64> = vec![];
     let tx = &psbt.global.unsigned_tx;
@@ -80,15 +90,21 @@ pub fn pretty_print(
     let mut balances = HashMap::new();
 
     for (i, input) in tx.input.iter().enumerate() {
-        let addr = Address::from_script(&previous_outputs[i].script_pubkey, network)
-            .ok_or(Error::NonDefaultScript)?;
+        if is_commitment_input(psbt, i) {
+            result
+                .info
+                .push("Proof of reserves, the transaction is not valid".to_string());
+        }
+        let addr = Address::from_script(&previous_outputs[i].script_pubkey, network);
         let keypaths = &psbt.inputs[i].hd_keypaths;
         let signatures: HashSet<Fingerprint> = psbt.inputs[i]
             .partial_sigs
             .iter()
             .filter_map(|(k, _)| keypaths.get(k).map(|v| v.0))
             .collect();
-        let wallet_if_any = wallet_with_path(keypaths, &wallets, &addr);
+        let wallet_if_any = addr
+            .as_ref()
+            .and_then(|addr| wallet_with_path(keypaths, wallets, addr));
         if let Some((wallet, _)) = &wallet_if_any {
             *balances.entry(wallet.clone()).or_insert(0i64) -= previous_outputs[i].value as i64
         }
@@ -104,15 +120,19 @@ pub fn pretty_print(
     }
 
     for (i, output) in tx.output.iter().enumerate() {
-        let addr =
-            Address::from_script(&output.script_pubkey, network).ok_or(Error::NonDefaultScript)?;
+        // outputs without address, like the OP_RETURN of BIP322 proofs, are shown as asm
+        let addr = Address::from_script(&output.script_pubkey, network);
         let keypaths = &psbt.outputs[i].hd_keypaths;
-        let wallet_if_any = wallet_with_path(keypaths, &wallets, &addr);
+        let wallet_if_any = addr
+            .as_ref()
+            .and_then(|addr| wallet_with_path(keypaths, wallets, addr));
         if let Some((wallet, _)) = &wallet_if_any {
             *balances.entry(wallet.clone()).or_insert(0i64) += output.value as i64
         }
         let txout = json::TxOut {
-            address: addr.to_string(),
+            address: addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|| output.script_pubkey.asm()),
             common: TxCommonInOut {
                 value: Amount::from_sat(output.value).to_string(),
                 wallet_with_path: wallet_if_any.map(|(w, p)| format!("[{}]{}", w, p)),
@@ -193,6 +213,10 @@ pub fn pretty_print(
 }
 
 fn biggest_dividing_pow(num: u64) -> u8 {
+    if num == 0 {
+        // zero value outputs, like the OP_RETURN of BIP322 proofs, don't have a precision
+        return 0;
+    }
     let mut start = 10u64;
     let mut count = 0u8;
     loop {
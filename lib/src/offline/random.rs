@@ -1,3 +1,4 @@
+use crate::qr::QrFormat;
 use crate::*;
 use bitcoin::Network;
 use common::mnemonic::Mnemonic;
@@ -18,10 +19,20 @@ pub struct RandomOptions {
     #[serde(default)]
     pub qr_version: i16,
 
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
     /// Optional encryption key for saving the key file encrypted
     /// in CLI it is populated from standard input
     #[structopt(skip)]
     pub encryption_key: Option<StringEncoding>,
+
+    /// Optional BIP39 passphrase, it is not saved in the key file
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub passphrase: Option<String>,
 }
 
 impl RandomOptions {
@@ -29,7 +40,9 @@ impl RandomOptions {
         RandomOptions {
             key_name,
             qr_version: 20,
+            qr_format: QrFormat::default(),
             encryption_key: None,
+            passphrase: None,
         }
     }
 }
@@ -37,13 +50,15 @@ impl RandomOptions {
 pub fn create_key(datadir: &str, network: Network, opt: &RandomOptions) -> Result<MasterKeyOutput> {
     let sec = rand::thread_rng().gen::<[u8; 32]>();
     let mnemonic = Mnemonic::new(&sec)?;
-    let master_key = PrivateMasterKeyJson::new(network, &mnemonic, &opt.key_name)?;
+    let master_key =
+        PrivateMasterKeyJson::new(network, &mnemonic, &opt.key_name, opt.passphrase.as_deref())?;
     let output = save_keys(
         datadir,
         network,
         &opt.key_name,
         master_key,
         opt.qr_version,
+        opt.qr_format,
         opt.encryption_key.as_ref(),
     )?;
 
@@ -0,0 +1,193 @@
+use crate::offline::decrypt::MaybeEncrypted;
+use crate::offline::sign::read_key;
+use crate::*;
+use bitcoin::Network;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use structopt::StructOpt;
+
+/// Change, add or remove the encryption of private key files.
+/// Only the key file encryption key is rotated, wallets and PSBTs are encrypted with the separate
+/// datadir key and are not touched
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct ReencryptOptions {
+    /// Name of the key to re-encrypt
+    #[structopt(short, long)]
+    pub key_name: Option<String>,
+
+    /// Re-encrypt every key in the datadir
+    #[structopt(long)]
+    pub all: bool,
+
+    /// Current encryption key, None if the files are plain
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+
+    /// New encryption key, None to save the files plain
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub new_encryption_key: Option<StringEncoding>,
+}
+
+pub fn reencrypt(
+    datadir: &str,
+    network: Network,
+    opt: &ReencryptOptions,
+) -> Result<ReencryptOutput> {
+    let paths = match (&opt.key_name, opt.all) {
+        (Some(name), false) => {
+            let path_builder = PathBuilder::new(datadir, network, Kind::Key, Some(name.clone()));
+            vec![path_builder.file("PRIVATE.json")?]
+        }
+        (None, true) => {
+            let keys_path = PathBuilder::new(datadir, network, Kind::Key, None).type_path()?;
+            let mut paths = vec![];
+            for entry in fs::read_dir(keys_path)? {
+                let path = entry?.path().join("PRIVATE.json");
+                if path.exists() {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+            paths
+        }
+        _ => return Err(Error::ReencryptTarget),
+    };
+
+    // read every key before touching any file, so a wrong key doesn't leave a partial rotation
+    let keys = paths
+        .iter()
+        .map(|path| read_key(path, opt.encryption_key.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (path, key) in paths.iter().zip(keys) {
+        rewrite(path, key, opt.new_encryption_key.as_ref())?;
+    }
+
+    Ok(ReencryptOutput { files: paths })
+}
+
+fn rewrite(
+    path: &Path,
+    key: PrivateMasterKeyJson,
+    new_encryption_key: Option<&StringEncoding>,
+) -> Result<()> {
+    let original = fs::read(path)?;
+    let backup = path.with_extension("json.bak");
+    fs::write(&backup, &original)?;
+    if fs::read(&backup)? != original {
+        return Err(Error::ReencryptVerification(backup));
+    }
+
+    let mut maybe_encrypted = MaybeEncrypted::plain(key.clone());
+    if let Some(new_encryption_key) = new_encryption_key {
+        maybe_encrypted = maybe_encrypted.encrypt_with(new_encryption_key)?;
+    }
+    let temp = path.with_extension("json.tmp");
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(serde_json::to_string_pretty(&maybe_encrypted)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp, path)?;
+
+    match read_key(&path.to_path_buf(), new_encryption_key) {
+        Ok(read) if read == key => {
+            fs::remove_file(&backup)?;
+            info!("Re-encrypted {:?}", path);
+            Ok(())
+        }
+        _ => {
+            warn!("{:?} doesn't read back, restoring backup", path);
+            fs::rename(&backup, path)?;
+            Err(Error::ReencryptVerification(path.to_path_buf()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::random::{create_key, RandomOptions};
+    use crate::offline::reencrypt::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_reencrypt() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let old_key = StringEncoding::new_base64(&[1u8; 32]);
+        let new_key = StringEncoding::new_base64(&[2u8; 32]);
+
+        let mut opt = RandomOptions::new("a".to_string());
+        opt.encryption_key = Some(old_key.clone());
+        let a = create_key(datadir, Network::Testnet, &opt).unwrap();
+        opt.key_name = "b".to_string();
+        let b = create_key(datadir, Network::Testnet, &opt).unwrap();
+
+        let mut opt = ReencryptOptions {
+            key_name: Some("a".to_string()),
+            all: false,
+            encryption_key: Some(new_key.clone()),
+            new_encryption_key: None,
+        };
+        assert!(reencrypt(datadir, Network::Testnet, &opt).is_err());
+        assert_eq!(read_key(&a.private_file, Some(&old_key)).unwrap(), a.key);
+
+        opt.encryption_key = Some(old_key.clone());
+        let result = reencrypt(datadir, Network::Testnet, &opt).unwrap();
+        assert_eq!(result.files, vec![a.private_file.clone()]);
+        assert_eq!(read_key(&a.private_file, None).unwrap(), a.key);
+        assert!(read_key(&a.private_file, Some(&old_key)).is_err());
+        assert!(!a.private_file.with_extension("json.bak").exists());
+
+        opt.encryption_key = None;
+        opt.new_encryption_key = Some(old_key.clone());
+        reencrypt(datadir, Network::Testnet, &opt).unwrap();
+
+        opt.key_name = None;
+        opt.all = true;
+        opt.encryption_key = Some(old_key.clone());
+        opt.new_encryption_key = Some(new_key.clone());
+        let result = reencrypt(datadir, Network::Testnet, &opt).unwrap();
+        assert_eq!(result.files.len(), 2);
+        assert_eq!(read_key(&a.private_file, Some(&new_key)).unwrap(), a.key);
+        assert_eq!(read_key(&b.private_file, Some(&new_key)).unwrap(), b.key);
+
+        opt.key_name = Some("a".to_string());
+        assert!(reencrypt(datadir, Network::Testnet, &opt).is_err());
+    }
+
+    #[test]
+    fn test_reencrypt_keeps_datadir_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let key_file_key = StringEncoding::new_base64(&[1u8; 32]);
+        let datadir_key = StringEncoding::new_base64(&[3u8; 32]);
+
+        let mut opt = RandomOptions::new("a".to_string());
+        opt.encryption_key = Some(key_file_key.clone());
+        let a = create_key(datadir, Network::Testnet, &opt).unwrap();
+        let wallet_file =
+            PathBuilder::new(datadir, Network::Testnet, Kind::Wallet, Some("w".into()))
+                .file("descriptor.json")
+                .unwrap();
+        let wallet = serde_json::json!({"name": "w"});
+        save_maybe_encrypted(&wallet, &wallet_file, Some(&datadir_key)).unwrap();
+        let wallet_content = fs::read(&wallet_file).unwrap();
+
+        let opt = ReencryptOptions {
+            key_name: None,
+            all: true,
+            encryption_key: Some(key_file_key),
+            new_encryption_key: Some(StringEncoding::new_base64(&[2u8; 32])),
+        };
+        let result = reencrypt(datadir, Network::Testnet, &opt).unwrap();
+        assert_eq!(result.files, vec![a.private_file]);
+        assert_eq!(fs::read(&wallet_file).unwrap(), wallet_content);
+        let read: serde_json::Value =
+            read_maybe_encrypted(&wallet_file, Some(&datadir_key)).unwrap();
+        assert_eq!(read, wallet);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::common::proprietary::get_psbt_metadata;
+use crate::offline::message::{multisig_witness, verify_multisig_witness};
+use crate::*;
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+const MESSAGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+/// Verify a signed proof of reserves and report the total of the coins still unspent
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct VerifyReservesOptions {
+    /// Challenge message of the proof
+    #[structopt(long)]
+    pub message: String,
+
+    /// Proof of reserves PSBT signed by the cosigners
+    #[structopt(long, parse(from_os_str))]
+    pub psbt_file: PathBuf,
+
+    /// Json file with the UTXO set snapshot, a list of `{"outpoint","value","script_pubkey"}`,
+    /// if missing the bitcoin node is used
+    #[structopt(long, parse(from_os_str))]
+    #[serde(default)]
+    pub utxos_file: Option<PathBuf>,
+
+    /// Optional datadir encryption key
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+}
+
+/// Outpoint spent by the commitment input, it doesn't exist so the proof can't be broadcasted
+pub fn commitment_outpoint(message: &str) -> OutPoint {
+    let hash = sha256d::Hash::hash(format!("{}{}", MESSAGE_PREFIX, message).as_bytes());
+    OutPoint::new(Txid::from_hash(hash), 0)
+}
+
+/// The fake previous output of the commitment input
+pub fn commitment_txout() -> TxOut {
+    TxOut {
+        value: 0,
+        script_pubkey: Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .into_script(),
+    }
+}
+
+/// Input `index` is the commitment input of a proof of reserves, which has nothing to sign.
+/// The input fields are controlled by the PSBT author, so the commitment must also match the
+/// memo in the firma metadata and the transaction must have a single OP_RETURN output
+pub fn is_commitment_input(psbt: &PSBT, index: usize) -> bool {
+    let tx = &psbt.global.unsigned_tx;
+    let memo = match get_psbt_metadata(psbt) {
+        Ok(PsbtMetadata {
+            memo: Some(memo), ..
+        }) => memo,
+        _ => return false,
+    };
+    let input = &psbt.inputs[index];
+    index == 0
+        && tx.input[0].previous_output == commitment_outpoint(&memo)
+        && tx.output.len() == 1
+        && tx.output[0].script_pubkey.is_op_return()
+        && input.non_witness_utxo.is_none()
+        && input.witness_utxo == Some(commitment_txout())
+}
+
+/// Unsigned transaction spending the commitment input and `coins` to a single unspendable output
+pub fn reserves_tx(message: &str, coins: &[OutPoint], total: u64) -> Transaction {
+    let mut outpoints = vec![commitment_outpoint(message)];
+    outpoints.extend(coins);
+    Transaction {
+        version: 1,
+        lock_time: 0,
+        input: outpoints
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: total,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+/// Check the proof of `message` and every signature, `lookup` returns the unspent output for
+/// the outpoint if it's in the UTXO set
+pub fn verify_reserves<F>(psbt: &PSBT, message: &str, mut lookup: F) -> Result<ReservesOutput>
+where
+    F: FnMut(&OutPoint) -> Result<Option<TxOut>>,
+{
+    let tx = &psbt.global.unsigned_tx;
+    let first = tx.input.first().ok_or(Error::ReservesWrongPsbt)?;
+    if first.previous_output != commitment_outpoint(message) || tx.output.len() != 1 {
+        return Err(Error::ReservesWrongPsbt);
+    }
+    let secp = Secp256k1::verification_only();
+    let mut coins = vec![];
+    let mut total = 0u64;
+    let mut proven = 0u64;
+    for (i, (txin, input)) in tx.input.iter().zip(psbt.inputs.iter()).enumerate().skip(1) {
+        let prevout = input
+            .witness_utxo
+            .as_ref()
+            .ok_or(Error::MissingWitnessUtxo)?;
+        let signed = match input.witness_script.as_ref() {
+            Some(witness_script) => match multisig_witness(input, witness_script) {
+                Ok(witness) => verify_multisig_witness(
+                    tx,
+                    i,
+                    prevout.value,
+                    &prevout.script_pubkey,
+                    &witness,
+                    &secp,
+                )?,
+                Err(Error::PSBTMissingSignatures(_, _)) => false,
+                Err(e) => return Err(e),
+            },
+            None => false,
+        };
+        let unspent = lookup(&txin.previous_output)?.as_ref() == Some(prevout);
+        total += prevout.value;
+        if signed && unspent {
+            proven += prevout.value;
+        }
+        coins.push(ReservesCoin {
+            outpoint: txin.previous_output,
+            amount: prevout.value,
+            signed,
+            unspent,
+        });
+    }
+    if tx.output[0].value != total {
+        return Err(Error::ReservesWrongPsbt);
+    }
+
+    Ok(ReservesOutput {
+        message: message.to_string(),
+        coins,
+        proven,
+    })
+}
+
+/// Verify the proof of reserves against the UTXO set snapshot in `utxos_file`
+pub fn verify_reserves_snapshot(
+    opt: &VerifyReservesOptions,
+    utxos_file: &Path,
+) -> Result<ReservesOutput> {
+    let psbt = read_psbt(&opt.psbt_file, opt.encryption_key.as_ref())?;
+    let utxos: Vec<Utxo> = serde_json::from_slice(&fs::read(utxos_file)?)?;
+    let utxos: HashMap<OutPoint, TxOut> = utxos
+        .into_iter()
+        .map(|u| {
+            let txout = TxOut {
+                value: u.value,
+                script_pubkey: u.script_pubkey,
+            };
+            (u.outpoint, txout)
+        })
+        .collect();
+    verify_reserves(&psbt, &opt.message, |outpoint| {
+        Ok(utxos.get(outpoint).cloned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::proprietary::set_psbt_metadata;
+    use crate::offline::import_wallet::import_wallet;
+    use crate::offline::random::{create_key, RandomOptions};
+    use crate::offline::reserves::*;
+    use crate::offline::sign::{save_psbt, SignOptions};
+    use crate::qr::QrFormat;
+    use bitcoin::util::bip32::{ChildNumber, DerivationPath};
+    use bitcoin::Network;
+    use miniscript::descriptor::DescriptorPublicKey;
+    use miniscript::{Descriptor, DescriptorPublicKeyCtx};
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_reserves() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let network = Network::Testnet;
+        let keys: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|n| create_key(datadir, network, &RandomOptions::new(n.to_string())).unwrap())
+            .collect();
+        let descriptor = format!(
+            "wsh(multi(2,{}/0/*,{}/0/*))",
+            keys[0].key.xpub, keys[1].key.xpub
+        );
+        let wallet = WalletJson {
+            name: "w".to_string(),
+            descriptor: descriptor.clone(),
+            fingerprints: keys.iter().map(|k| k.key.fingerprint).collect(),
+            required_sig: 2,
+            created_at_height: 0,
+        };
+        let wallet_file = import_wallet(datadir, network, &wallet, None).unwrap();
+
+        // two coins of the wallet at index 0 and 1, received with a fake tx
+        let descriptor: Descriptor<DescriptorPublicKey> = descriptor.parse().unwrap();
+        let secp = Secp256k1::verification_only();
+        let mut funding = reserves_tx("funding", &[], 0);
+        let mut witness_scripts = vec![];
+        funding.output.clear();
+        for (index, value) in [(0u32, 10_000u64), (1, 20_000)].iter() {
+            let context =
+                DescriptorPublicKeyCtx::new(&secp, ChildNumber::from_normal_idx(*index).unwrap());
+            funding.output.push(TxOut {
+                value: *value,
+                script_pubkey: descriptor.script_pubkey(context),
+            });
+            witness_scripts.push(descriptor.witness_script(context));
+        }
+        let coins: Vec<_> = (0..2).map(|i| OutPoint::new(funding.txid(), i)).collect();
+
+        let message = "audit 2021";
+        let tx = reserves_tx(message, &coins, 30_000);
+        let mut psbt = PSBT::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(commitment_txout());
+        for (i, input) in psbt.inputs.iter_mut().skip(1).enumerate() {
+            input.witness_utxo = Some(funding.output[i].clone());
+            input.non_witness_utxo = Some(funding.clone());
+            input.witness_script = Some(witness_scripts[i].clone());
+            let mut hd_keypaths = BTreeMap::new();
+            for key in keys.iter() {
+                let path: DerivationPath = vec![
+                    ChildNumber::from_normal_idx(0).unwrap(),
+                    ChildNumber::from_normal_idx(i as u32).unwrap(),
+                ]
+                .into();
+                let public_key = key.key.xpub.derive_pub(&secp, &path).unwrap().public_key;
+                hd_keypaths.insert(public_key, (key.key.fingerprint, path));
+            }
+            input.hd_keypaths = hd_keypaths;
+        }
+        let metadata = PsbtMetadata {
+            memo: Some(message.to_string()),
+            ..Default::default()
+        };
+        set_psbt_metadata(&mut psbt, &metadata).unwrap();
+        assert!(is_commitment_input(&psbt, 0));
+        assert!(!is_commitment_input(&psbt, 1));
+
+        // a real input labeled as commitment is not treated as such
+        let mut crafted = psbt.clone();
+        crafted.inputs[1].non_witness_utxo = None;
+        crafted.inputs[1].witness_utxo = Some(commitment_txout());
+        assert!(!is_commitment_input(&crafted, 1));
+        crafted.inputs.swap(0, 1);
+        crafted.global.unsigned_tx.input.swap(0, 1);
+        assert!(!is_commitment_input(&crafted, 0));
+        let mut crafted = psbt.clone();
+        set_psbt_metadata(
+            &mut crafted,
+            &PsbtMetadata {
+                memo: Some("other".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(!is_commitment_input(&crafted, 0));
+        let mut crafted = psbt.clone();
+        let output = crafted.global.unsigned_tx.output[0].clone();
+        crafted.global.unsigned_tx.output.push(output);
+        crafted.outputs.push(Default::default());
+        assert!(!is_commitment_input(&crafted, 0));
+        let mut crafted = psbt.clone();
+        crafted.global.unknown.clear();
+        assert!(!is_commitment_input(&crafted, 0));
+
+        // without the memo the signer checks the prevout of the first input too
+        let crafted_file = temp_dir.path().join("crafted.psbt");
+        save_psbt_binary(&crafted, &crafted_file).unwrap();
+        let sign_opt = SignOptions {
+            key: keys[0].private_file.clone(),
+            total_derivations: 10,
+            wallet_descriptor_file: wallet_file.clone(),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            psbt_file: crafted_file,
+            binary_out: None,
+            allow_any_derivations: false,
+            encryption_key: None,
+            datadir_encryption_key: None,
+            passphrase: None,
+        };
+        assert!(matches!(
+            crate::offline::sign::start(&sign_opt, network),
+            Err(Error::MissingPrevoutTx)
+        ));
+
+        let mut psbts_dir = PathBuilder::new(datadir, network, Kind::PSBT, None)
+            .type_path()
+            .unwrap();
+        let (psbt_file, _, _) =
+            save_psbt(&mut psbt, &mut psbts_dir, 14, QrFormat::default(), None).unwrap();
+
+        let utxos = funding.output.clone();
+        let lookup = |outpoint: &OutPoint| Ok(utxos.get(outpoint.vout as usize).cloned());
+        let result = verify_reserves(&psbt, message, lookup).unwrap();
+        assert_eq!(result.proven, 0);
+        assert!(result.coins.iter().all(|c| !c.signed && c.unspent));
+
+        for key in keys.iter() {
+            let sign_opt = SignOptions {
+                key: key.private_file.clone(),
+                total_derivations: 10,
+                wallet_descriptor_file: wallet_file.clone(),
+                qr_version: 14,
+                qr_format: QrFormat::default(),
+                psbt_file: psbt_file.clone(),
+                binary_out: None,
+                allow_any_derivations: false,
+                encryption_key: None,
+                datadir_encryption_key: None,
+                passphrase: None,
+            };
+            crate::offline::sign::start(&sign_opt, network).unwrap();
+        }
+        let psbt = read_psbt(&psbt_file, None).unwrap();
+        let result = verify_reserves(&psbt, message, lookup).unwrap();
+        assert_eq!(result.proven, 30_000);
+        assert!(result.coins.iter().all(|c| c.signed && c.unspent));
+
+        // the second coin has been spent
+        let lookup =
+            |outpoint: &OutPoint| Ok(utxos.first().filter(|_| outpoint.vout == 0).cloned());
+        let result = verify_reserves(&psbt, message, lookup).unwrap();
+        assert_eq!(result.proven, 10_000);
+
+        assert!(matches!(
+            verify_reserves(&psbt, "other", lookup),
+            Err(Error::ReservesWrongPsbt)
+        ));
+
+        let snapshot: Vec<_> = utxos
+            .iter()
+            .zip(coins.iter())
+            .map(|(txout, outpoint)| Utxo {
+                outpoint: *outpoint,
+                value: txout.value,
+                script_pubkey: txout.script_pubkey.clone(),
+            })
+            .collect();
+        let utxos_file = temp_dir.path().join("utxos.json");
+        fs::write(&utxos_file, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+        let opt = VerifyReservesOptions {
+            message: message.to_string(),
+            psbt_file,
+            utxos_file: Some(utxos_file.clone()),
+            encryption_key: None,
+        };
+        let result = verify_reserves_snapshot(&opt, &utxos_file).unwrap();
+        assert_eq!(result.proven, 30_000);
+    }
+}
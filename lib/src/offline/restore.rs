@@ -1,5 +1,7 @@
 use crate::mnemonic::Mnemonic;
-use crate::{check_compatibility, Result, StringEncoding};
+use crate::offline::split::combine;
+use crate::qr::QrFormat;
+use crate::{check_compatibility, Error, Result, StringEncoding};
 use crate::{save_keys, MasterKeyOutput, PrivateMasterKeyJson};
 use bitcoin::util::bip32::ExtendedPrivKey;
 use bitcoin::Network;
@@ -24,12 +26,24 @@ pub struct RestoreOptions {
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
 
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
     /// Optional encryption key for saving the key file encrypted
     /// in CLI it is populated from standard input
     #[structopt(skip)]
     pub encryption_key: Option<StringEncoding>,
 
-    /// value of the secret component, could be xprv or seed in hex or bech32
+    /// Optional BIP39 passphrase, it is not saved in the key file.
+    /// With `slip39` nature it is the SLIP-39 passphrase of the shares
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub passphrase: Option<String>,
+
+    /// value of the secret component, could be xprv or seed in hex or bech32,
+    /// SLIP-39 shares are separated by comma
     pub value: String,
 }
 
@@ -37,6 +51,7 @@ pub struct RestoreOptions {
 pub enum Nature {
     Xprv,
     Mnemonic,
+    Slip39,
 }
 
 impl FromStr for Nature {
@@ -46,9 +61,10 @@ impl FromStr for Nature {
         match s {
             "xprv" => Ok(Nature::Xprv),
             "mnemonic" => Ok(Nature::Mnemonic),
+            "slip39" => Ok(Nature::Slip39),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                format!("({}) valid values are: xprv, mnemonic", s),
+                format!("({}) valid values are: xprv, mnemonic, slip39", s),
             )),
         }
     }
@@ -57,13 +73,23 @@ impl FromStr for Nature {
 pub fn start(datadir: &str, network: Network, opt: &RestoreOptions) -> Result<MasterKeyOutput> {
     let master_key = match opt.nature {
         Nature::Xprv => {
+            if opt.passphrase.as_deref().is_some_and(|p| !p.is_empty()) {
+                return Err(Error::PassphraseWithXprv);
+            }
             let key = ExtendedPrivKey::from_str(&opt.value)?;
             check_compatibility(key.network, network)?;
             PrivateMasterKeyJson::from_xprv(key, &opt.key_name)
         }
         Nature::Mnemonic => {
             let mnemonic = Mnemonic::from_str(&opt.value)?;
-            PrivateMasterKeyJson::new(network, &mnemonic, &opt.key_name)?
+            PrivateMasterKeyJson::new(network, &mnemonic, &opt.key_name, opt.passphrase.as_deref())?
+        }
+        Nature::Slip39 => {
+            // the passphrase is the SLIP-39 one, the master secret is the BIP32 seed
+            let shares: Vec<String> = opt.value.split(',').map(|s| s.to_string()).collect();
+            let seed = combine(&shares, opt.passphrase.as_deref().unwrap_or(""))?;
+            let xprv = ExtendedPrivKey::new_master(network, &seed)?;
+            PrivateMasterKeyJson::from_xprv(xprv, &opt.key_name)
         }
     };
 
@@ -73,6 +99,7 @@ pub fn start(datadir: &str, network: Network, opt: &RestoreOptions) -> Result<Ma
         &opt.key_name,
         master_key,
         opt.qr_version,
+        opt.qr_format,
         opt.encryption_key.as_ref(),
     )?;
     Ok(output)
@@ -82,6 +109,9 @@ pub fn start(datadir: &str, network: Network, opt: &RestoreOptions) -> Result<Ma
 mod tests {
     use crate::offline::random::RandomOptions;
     use crate::offline::restore::{Nature, RestoreOptions};
+    use crate::qr::QrFormat;
+    use crate::Error;
+    use bitcoin::secp256k1::Secp256k1;
     use bitcoin::Network;
     use tempfile::TempDir;
 
@@ -101,9 +131,11 @@ mod tests {
         let restore_opts = RestoreOptions {
             key_name,
             nature: Nature::Xprv,
-            value: key_orig.key.xprv.to_string(),
+            value: key_orig.key.xprv.unwrap().to_string(),
             qr_version: 14,
+            qr_format: QrFormat::default(),
             encryption_key: None,
+            passphrase: None,
         };
         let key_restored =
             crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts).unwrap();
@@ -117,7 +149,9 @@ mod tests {
             nature: Nature::Mnemonic,
             value: key_orig.key.mnemonic.as_ref().unwrap().to_string(),
             qr_version: 14,
+            qr_format: QrFormat::default(),
             encryption_key: None,
+            passphrase: None,
         };
         let key_restored =
             crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts).unwrap();
@@ -125,7 +159,45 @@ mod tests {
         assert_eq!(key_orig.key.xpub, key_restored.key.xpub);
         assert_eq!(&key_orig.key.mnemonic, &key_restored.key.mnemonic);
 
-        // TODO add restore mnemonic
+        let (key_name, name_counter) = (format!("{}", name_counter), name_counter + 1);
+        let restore_opts = RestoreOptions {
+            key_name,
+            nature: Nature::Mnemonic,
+            value: key_orig.key.mnemonic.as_ref().unwrap().to_string(),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+            passphrase: Some("TREZOR".to_string()),
+        };
+        let key_restored =
+            crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts).unwrap();
+        let key = key_restored.key;
+        assert!(key.passphrase);
+        assert!(key.xprv.is_none());
+        assert_ne!(key_orig.key.xpub, key.xpub);
+        let xprv = key.signing_xprv(Some("TREZOR")).unwrap();
+        assert_eq!(
+            xprv.fingerprint(&Secp256k1::signing_only()),
+            key.fingerprint
+        );
+        assert!(key.signing_xprv(None).is_err());
+        assert!(key.signing_xprv(Some("WRONG")).is_err());
+        let private = std::fs::read_to_string(key_restored.private_file).unwrap();
+        assert!(!private.contains("TREZOR"));
+        assert!(!private.contains("xprv"));
+
+        let (key_name, name_counter) = (format!("{}", name_counter), name_counter + 1);
+        let restore_opts = RestoreOptions {
+            key_name,
+            nature: Nature::Xprv,
+            value: key_orig.key.xprv.unwrap().to_string(),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+            passphrase: Some("TREZOR".to_string()),
+        };
+        let result = crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts);
+        assert!(matches!(result, Err(Error::PassphraseWithXprv)));
 
         let (key_name, name_counter) = (format!("{}", name_counter), name_counter + 1);
         let restore_opts = RestoreOptions {
@@ -133,7 +205,9 @@ mod tests {
             nature: Nature::Xprv,
             value: "X".to_string(),
             qr_version: 14,
+            qr_format: QrFormat::default(),
             encryption_key: None,
+            passphrase: None,
         };
         let result = crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts);
         assert!(result.is_err());
@@ -144,7 +218,9 @@ mod tests {
             nature: Nature::Xprv,
             value: key_orig.key.xpub.to_string(),
             qr_version: 14,
+            qr_format: QrFormat::default(),
             encryption_key: None,
+            passphrase: None,
         };
         let result = crate::offline::restore::start(&temp_dir_str, Network::Testnet, &restore_opts);
         assert!(result.is_err());
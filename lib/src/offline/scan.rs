@@ -0,0 +1,289 @@
+use crate::common::compact_wallet::{decode_wallet, wallet_from_compact};
+use crate::offline::import_wallet::import_wallet;
+use crate::offline::sign::save_psbt_options;
+use crate::qr::QrFormat;
+use crate::*;
+use bitcoin::consensus::deserialize;
+use bitcoin::util::bip32::ExtendedPubKey;
+use bitcoin::{Address, Network};
+use log::{debug, info};
+use rxing::{RXingResult, RXingResultMetadataType, RXingResultMetadataValue};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct ScanOptions {
+    /// Image files (BMP or PNG) containing the QR codes, multipart QR could be spread on many files
+    #[structopt(parse(from_os_str), required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// QR code max version to use (max size) for the QR codes of the imported payload
+    #[structopt(short, long, default_value = "14")]
+    pub qr_version: i16,
+
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
+    /// Optional datadir encryption key for saving the imported payload
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+}
+
+enum QrContent {
+    Single(Vec<u8>),
+    Structured {
+        index: usize,
+        total: usize,
+        bytes: Vec<u8>,
+    },
+    Ur(String),
+}
+
+/// Decode the QR codes in `opt.files`, merge the parts and import the resulting payload
+pub fn scan(datadir: &str, network: Network, opt: &ScanOptions) -> Result<ScanOutput> {
+    let mut contents = vec![];
+    for file in opt.files.iter() {
+        contents.extend(read_qr_codes(file)?);
+    }
+    let payload = merge_contents(contents, network)?;
+    let mut output = identify(&payload)?;
+    info!("scanned {:?}", output.kind);
+
+    let datadir = expand_tilde(datadir)?;
+    let datadir = datadir.to_str().ok_or(Error::MissingDatadir)?;
+    match output.kind {
+        ScanPayload::Psbt => {
+            let opts = SavePSBTOptions {
+                psbt: StringEncoding::new_base64(&payload),
+                qr_version: opt.qr_version,
+                qr_format: opt.qr_format,
+                encryption_key: opt.encryption_key.clone(),
+            };
+            output.file = Some(save_psbt_options(datadir, network, &opts)?);
+        }
+        ScanPayload::Wallet => {
+            let wallet = decode_wallet(&payload)?;
+            output.file = Some(import_wallet(
+                datadir,
+                network,
+                &wallet,
+                opt.encryption_key.as_ref(),
+            )?);
+        }
+        ScanPayload::Xpub => {
+            let xpub = ExtendedPubKey::from_str(&output.content)?;
+            check_compatibility(network, xpub.network)?;
+        }
+        ScanPayload::Address => {
+            let address = Address::from_str(&output.content)?;
+            check_compatibility(network, address.network)?;
+        }
+    }
+
+    Ok(output)
+}
+
+fn read_qr_codes(file: &PathBuf) -> Result<Vec<QrContent>> {
+    let file_str = file.to_str().ok_or(Error::QrNotFound(file.clone()))?;
+    let results = rxing::helpers::detect_multiple_in_file(file_str)
+        .map_err(|_| Error::QrNotFound(file.clone()))?;
+    info!("found {} qr in {:?}", results.len(), file);
+    Ok(results.iter().map(to_content).collect())
+}
+
+fn to_content(result: &RXingResult) -> QrContent {
+    let bytes = result.getRawBytes().to_vec();
+    let metadata = result.getRXingResultMetadata();
+    // rxing reports the symbol index and the symbol count of a structured append QR
+    let index = metadata.get(&RXingResultMetadataType::STRUCTURED_APPEND_SEQUENCE);
+    let total = metadata.get(&RXingResultMetadataType::STRUCTURED_APPEND_PARITY);
+    match (index, total) {
+        (
+            Some(RXingResultMetadataValue::StructuredAppendSequence(index)),
+            Some(RXingResultMetadataValue::StructuredAppendParity(total)),
+        ) if *index >= 0 && *total > 0 => QrContent::Structured {
+            index: *index as usize,
+            total: *total as usize,
+            bytes,
+        },
+        _ => match std::str::from_utf8(&bytes) {
+            Ok(s) if s.to_lowercase().starts_with("ur:") => QrContent::Ur(s.to_string()),
+            _ => QrContent::Single(bytes),
+        },
+    }
+}
+
+fn merge_contents(contents: Vec<QrContent>, network: Network) -> Result<Vec<u8>> {
+    let mut singles = vec![];
+    let mut structured = BTreeMap::new();
+    let mut total_structured = None;
+    let mut urs = vec![];
+    for content in contents {
+        match content {
+            QrContent::Single(bytes) => singles.push(bytes),
+            QrContent::Structured {
+                index,
+                total,
+                bytes,
+            } => {
+                if *total_structured.get_or_insert(total) != total {
+                    return Err(Error::QrMultiplePayloads);
+                }
+                structured.insert(index, bytes);
+            }
+            QrContent::Ur(s) => urs.push(s),
+        }
+    }
+    debug!(
+        "singles:{} structured:{} urs:{}",
+        singles.len(),
+        structured.len(),
+        urs.len()
+    );
+
+    match (singles.len(), structured.len(), urs.len()) {
+        (1, 0, 0) => Ok(singles.pop().unwrap()), // checked len 1
+        (0, n, 0) if n > 0 => {
+            if total_structured != Some(n) || structured.keys().copied().ne(0..n) {
+                return Err(Error::QrMissingParts);
+            }
+            Ok(structured.into_values().flatten().collect())
+        }
+        (0, 0, n) if n > 0 => Ok(hex::decode(ur::merge_urs_payload(&urs, network)?.hex)?),
+        _ => Err(Error::QrMultiplePayloads),
+    }
+}
+
+/// identify the payload between binary or base64 PSBT, compact or json wallet, xpub and address
+pub fn identify(payload: &[u8]) -> Result<ScanOutput> {
+    let (kind, content) = if let Ok(psbt) = deserialize::<PSBT>(payload) {
+        (ScanPayload::Psbt, psbt_to_base64(&psbt).1)
+    } else if let Ok(wallet) = wallet_from_compact(payload) {
+        (ScanPayload::Wallet, wallet.descriptor)
+    } else {
+        let s = std::str::from_utf8(payload)
+            .map_err(|_| Error::QrUnknownPayload)?
+            .trim();
+        if let Ok((_, psbt)) = psbt_from_base64(s) {
+            (ScanPayload::Psbt, psbt_to_base64(&psbt).1)
+        } else if let Ok(wallet) = serde_json::from_str::<WalletJson>(s) {
+            (ScanPayload::Wallet, wallet.descriptor)
+        } else if let Ok(xpub) = ExtendedPubKey::from_str(s) {
+            (ScanPayload::Xpub, xpub.to_string())
+        } else {
+            // bech32 address are uppercased in QR, strip also the BIP21 scheme and parameters
+            let address = match s.get(..8) {
+                Some(scheme) if scheme.eq_ignore_ascii_case("bitcoin:") => &s[8..],
+                _ => s,
+            };
+            let address = address.split('?').next().unwrap_or(address);
+            match Address::from_str(address).or_else(|_| Address::from_str(&address.to_lowercase()))
+            {
+                Ok(address) => (ScanPayload::Address, address.to_string()),
+                Err(_) => return Err(Error::QrUnknownPayload),
+            }
+        }
+    };
+    Ok(ScanOutput {
+        kind,
+        content,
+        file: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::scan::*;
+    use tempfile::TempDir;
+
+    fn extract_psbt_bytes() -> Vec<u8> {
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.json");
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        psbt_from_base64(&psbt_json.psbt).unwrap().0
+    }
+
+    fn scan_dir(datadir: &str, qr_dir: PathBuf, prefix: &str) -> Result<ScanOutput> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&qr_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| {
+                let name = p.file_name().unwrap().to_str().unwrap();
+                name.starts_with(prefix) && name.ends_with(".bmp")
+            })
+            .collect();
+        files.reverse(); // order doesn't matter
+        let opt = ScanOptions {
+            files,
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+        };
+        scan(datadir, Network::Testnet, &opt)
+    }
+
+    #[test]
+    fn test_scan_psbt() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let psbt_bytes = extract_psbt_bytes();
+
+        let mut qr_dir = temp_dir.path().to_path_buf();
+        qr_dir.push("qr");
+        qr::save_qrs(psbt_bytes.clone(), qr_dir.clone(), 14, QrFormat::Bmp).unwrap();
+        let result = scan_dir(datadir, qr_dir.clone(), "qr-").unwrap();
+        assert_eq!(result.kind, ScanPayload::Psbt);
+        assert_eq!(result.content, base64::encode(&psbt_bytes));
+        assert!(result.file.unwrap().exists());
+
+        // importing again the same PSBT doesn't change anything
+        assert!(scan_dir(datadir, qr_dir.clone(), "qr-").is_err());
+
+        let mut files = std::fs::read_dir(&qr_dir).unwrap();
+        let one_file = files.next().unwrap().unwrap().path();
+        let opt = ScanOptions {
+            files: vec![one_file],
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+        };
+        assert!(scan(datadir, Network::Testnet, &opt).is_err());
+
+        let mut ur_dir = temp_dir.path().to_path_buf();
+        ur_dir.push("ur");
+        let cbor = ur::psbt_to_cbor(&psbt_bytes).unwrap();
+        ur::save_ur_qrs(
+            ur::UrType::CryptoPsbt,
+            &cbor,
+            ur_dir.clone(),
+            14,
+            QrFormat::Bmp,
+        )
+        .unwrap();
+        let result = scan_dir(datadir, ur_dir, "ur-").unwrap_err();
+        assert_eq!(result.to_string(), "PSBT did not change after merge");
+    }
+
+    #[test]
+    fn test_identify() {
+        let xpub = "tpubD6NzVbkrYhZ4YfG9CySHqKHFbaLcD7hSDyqRUtCmMKNim5fkiJtTnFeqKsRHMHSK5ddFrhqRr3Ghv1JtuWkBzikuBqKu1xCpjQ9YxoPGgqU";
+        let result = identify(xpub.as_bytes()).unwrap();
+        assert_eq!(result.kind, ScanPayload::Xpub);
+
+        let address = "TB1Q5NRREGEP899VNVAA5VDPXCWG8794JQY38NU304KL4D7WM4E92YEQZ4JFMK";
+        let result = identify(address.as_bytes()).unwrap();
+        assert_eq!(result.kind, ScanPayload::Address);
+        assert_eq!(result.content, address.to_lowercase());
+
+        let uri = "bitcoin:mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn?amount=0.1";
+        let result = identify(uri.as_bytes()).unwrap();
+        assert_eq!(result.content, "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn");
+
+        assert!(identify(b"not a payload").is_err());
+    }
+}
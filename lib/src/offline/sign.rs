@@ -1,6 +1,10 @@
+use crate::common::journal::JournalRecord;
+use crate::common::proprietary::{get_psbt_name, set_psbt_name};
 use crate::offline::decrypt::{decrypt, DecryptOptions, MaybeEncrypted};
 use crate::offline::print::pretty_print;
+use crate::offline::reserves::is_commitment_input;
 use crate::qr::save_qrs;
+use crate::qr::QrFormat;
 use crate::*;
 use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::script::Builder;
@@ -9,7 +13,6 @@ use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{self, Message, Secp256k1, SignOnly};
 use bitcoin::util::bip143::SigHashCache;
 use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
-use bitcoin::util::psbt::{raw, Map};
 use bitcoin::{Network, Script, SigHashType, Txid};
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
@@ -38,17 +41,38 @@ pub struct SignOptions {
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
 
-    /// PSBT json file
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
+    /// PSBT file, as json, BIP174 binary, base64 or hex
     pub psbt_file: PathBuf,
 
+    /// Also write the resulting PSBT in BIP174 binary format at this path
+    #[structopt(long, parse(from_os_str))]
+    #[serde(default)]
+    pub binary_out: Option<PathBuf>,
+
     /// Allow any derivations (to avoid ramson attacks, by default only 2 levels are allowed, and the first level must be 0 or 1)
     #[structopt(long)]
     pub allow_any_derivations: bool,
 
-    /// Optional encryption key for saving the key file encrypted
+    /// Optional encryption key for reading the key file encrypted
     /// in CLI it is populated from standard input
     #[structopt(skip)]
     pub encryption_key: Option<StringEncoding>,
+
+    /// Optional datadir encryption key, for the PSBT and the wallet files. When present the
+    /// signed PSBT is saved encrypted and QR codes are not saved
+    #[structopt(skip)]
+    #[serde(default)]
+    pub datadir_encryption_key: Option<StringEncoding>,
+
+    /// BIP39 passphrase, required if the key has been created with one
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub passphrase: Option<String>,
 }
 
 pub struct SignResult {
@@ -67,16 +91,11 @@ struct PSBTSigner {
     allow_any_derivations: bool,
 }
 
-/// extract field name in the PSBT extra field if present
-pub fn get_psbt_name(psbt: &PSBT) -> Option<String> {
-    psbt.global.unknown.get(&get_name_key()).map(|v| {
-        std::str::from_utf8(v)
-            .expect("PSBT name not utf8")
-            .to_string()
-    }) // TODO remove expect
-}
-
-pub fn save_psbt_options(datadir: &str, network: Network, opt: &SavePSBTOptions) -> Result<()> {
+pub fn save_psbt_options(
+    datadir: &str,
+    network: Network,
+    opt: &SavePSBTOptions,
+) -> Result<PathBuf> {
     info!("save_psbt_options {:?}", opt);
     let bytes = opt
         .psbt
@@ -86,22 +105,34 @@ pub fn save_psbt_options(datadir: &str, network: Network, opt: &SavePSBTOptions)
     let mut psbts_dir: PathBuf = datadir.into();
     psbts_dir.push(format!("{}", network));
     psbts_dir.push("psbts");
-    save_psbt(&mut psbt, &mut psbts_dir, opt.qr_version)?;
-    Ok(())
+    fs::create_dir_all(&psbts_dir)?;
+    let (psbt_file, _, _) = save_psbt(
+        &mut psbt,
+        &mut psbts_dir,
+        opt.qr_version,
+        opt.qr_format,
+        opt.encryption_key.as_ref(),
+    )?;
+    Ok(psbt_file)
 }
 
 /// Search existing psbt, if one matches the txid, return that name, otherwise it gives a new unused name
-fn get_name(psbts_dir: &PathBuf, txid: &Txid) -> Result<String> {
+fn get_name(
+    psbts_dir: &PathBuf,
+    txid: &Txid,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<String> {
     for entry in std::fs::read_dir(psbts_dir)? {
         let entry = entry?;
         let mut path = entry.path();
         path.push("psbt.json");
-        if let Ok(psbt_json) = read_psbt_json(&path) {
-            if let Ok((_, psbt)) = psbt_from_base64(&psbt_json.psbt) {
-                if &psbt.global.unsigned_tx.txid() == txid {
-                    return Ok(psbt_json.name);
-                }
-            }
+        if !path.exists() {
+            continue;
+        }
+        let psbt_json = read_psbt_json(&path, encryption_key)?;
+        let (_, psbt) = psbt_from_base64(&psbt_json.psbt)?;
+        if &psbt.global.unsigned_tx.txid() == txid {
+            return Ok(psbt_json.name);
         }
     }
     let mut count = 0usize;
@@ -119,27 +150,32 @@ fn get_name(psbts_dir: &PathBuf, txid: &Txid) -> Result<String> {
 
 /// psbts_dir is general psbts dir, name is extracted from PSBT
 /// if file exists a PSBT merge will be attempted
+/// if `encryption_key` is given the psbt is saved encrypted and QR codes are not saved
+/// returns the psbt file, the structured qr files and the UR qr files
 pub fn save_psbt(
     psbt: &mut PSBT,
     psbts_dir: &mut PathBuf,
     qr_version: i16,
-) -> Result<(PathBuf, Vec<PathBuf>)> {
-    let name = get_psbt_name(psbt).unwrap_or_else(|| {
-        let new_name = get_name(&psbts_dir, &psbt.global.unsigned_tx.txid()).unwrap(); // TODO remove unwrap
-        info!("PSBT without name, giving one: {}", new_name);
-        let pair = raw::Pair {
-            key: get_name_key(),
-            value: new_name.as_bytes().to_vec(),
-        };
-        let _ = psbt.global.insert_pair(pair);
-        new_name
-    });
+    qr_format: QrFormat,
+    encryption_key: Option<&StringEncoding>,
+) -> Result<(PathBuf, Vec<PathBuf>, Vec<PathBuf>)> {
+    let name = match get_psbt_name(psbt)? {
+        Some(name) => name,
+        None => {
+            let new_name = get_name(psbts_dir, &psbt.global.unsigned_tx.txid(), encryption_key)?;
+            info!("PSBT without name, giving one: {}", new_name);
+            set_psbt_name(psbt, &new_name)?;
+            new_name
+        }
+    };
 
     psbts_dir.push(&name);
     if psbts_dir.exists() {
         let mut old_psbt = psbts_dir.clone();
         old_psbt.push("psbt.json");
-        if let Ok(mut old_psbt) = read_psbt(&old_psbt) {
+        if old_psbt.exists() {
+            // a wrong or missing key must not overwrite the existing psbt
+            let mut old_psbt = read_psbt(&old_psbt, encryption_key)?;
             info!("old psbt exist, merging together");
             let before_psbt = psbt.clone();
             psbt.merge(old_psbt.clone())?;
@@ -164,13 +200,20 @@ pub fn save_psbt(
     };
     psbts_dir.push("psbt.json");
     let psbt_file = psbts_dir.clone();
-    let contents = serde_json::to_string_pretty(&psbt_json)?;
-    fs::write(&psbts_dir, contents.as_bytes())?;
+    save_maybe_encrypted(&psbt_json, &psbt_file, encryption_key)?;
+    if encryption_key.is_some() {
+        return Ok((psbt_file, vec![], vec![]));
+    }
 
     psbts_dir.set_file_name("qr");
-    let qrs = save_qrs(psbt_bytes, psbts_dir.clone(), qr_version)?;
+    let cbor = ur::psbt_to_cbor(&psbt_bytes)?;
+    let qrs = save_qrs(psbt_bytes, psbts_dir.clone(), qr_version, qr_format)?;
+
+    psbts_dir.set_file_name("ur");
+    let ur_type = ur::UrType::CryptoPsbt;
+    let urs = ur::save_ur_qrs(ur_type, &cbor, psbts_dir.clone(), qr_version, qr_format)?;
 
-    Ok((psbt_file, qrs))
+    Ok((psbt_file, qrs, urs))
 }
 
 impl PSBTSigner {
@@ -198,7 +241,7 @@ impl PSBTSigner {
     }
 
     fn from_opt(opt: &SignOptions, network: Network) -> Result<Self> {
-        let psbt = read_psbt(&opt.psbt_file)?;
+        let psbt = read_psbt(&opt.psbt_file, opt.datadir_encryption_key.as_ref())?;
         let psbt_file = opt.psbt_file.clone();
         let psbts_dir = psbt_file.parent().unwrap().parent().unwrap().to_path_buf(); //TODO remove unwrap
 
@@ -206,7 +249,7 @@ impl PSBTSigner {
 
         let signer = PSBTSigner::new(
             &psbt,
-            &xprv_json.xprv,
+            &xprv_json.signing_xprv(opt.passphrase.as_deref())?,
             network,
             opt.total_derivations,
             psbts_dir,
@@ -221,6 +264,10 @@ impl PSBTSigner {
 
         for (i, input) in self.psbt.inputs.clone().iter().enumerate() {
             debug!("{} {:?}", i, input);
+            if is_commitment_input(&self.psbt, i) {
+                debug!("{} is the commitment input of a proof of reserves", i);
+                continue;
+            }
             let is_segwit = input.witness_utxo.is_some();
 
             match input.non_witness_utxo.as_ref() {
@@ -361,25 +408,23 @@ impl PSBTSigner {
                 continue;
             }
             if input.partial_sigs.contains_key(pubkey) {
-                return Err(
-                    "request to sign a PSBT already containing a signature from this key".into(),
-                );
+                return Err(Error::PSBTAlreadySigned(input_index));
             }
             if !self.allow_any_derivations {
                 let path_slice = child.as_ref();
                 if path_slice.len() != 2 {
-                    return Err(format!("{} only two derivation paths allowed", child).into());
+                    let reason = format!("{} only two derivation paths allowed", child);
+                    return Err(Error::DerivationNotAllowed(input_index, reason));
                 } else if !(path_slice[0] == 0.into() || path_slice[0] == 1.into()) {
-                    return Err(format!("{} first derivation must be Soft 0 or 1", child).into());
+                    let reason = format!("{} first derivation must be Soft 0 or 1", child);
+                    return Err(Error::DerivationNotAllowed(input_index, reason));
                 }
             }
             let privkey = self.xprv.derive_priv(&self.secp, &child)?;
             let derived_pubkey =
                 secp256k1::PublicKey::from_secret_key(&self.secp, &privkey.private_key.key);
             if pubkey.key != derived_pubkey {
-                return Err(
-                    "pubkey derived and expected differs even if fingerprint matches!".into(),
-                );
+                return Err(Error::DerivedPubkeyMismatch(input_index));
             }
             let (hash, sighash);
             if is_segwit {
@@ -401,8 +446,19 @@ impl PSBTSigner {
         Ok(())
     }
 
-    fn save_signed_psbt_file(&mut self, qr_version: i16) -> Result<(PathBuf, Vec<PathBuf>)> {
-        save_psbt(&mut self.psbt, &mut self.psbts_dir.clone(), qr_version)
+    fn save_signed_psbt_file(
+        &mut self,
+        qr_version: i16,
+        qr_format: QrFormat,
+        encryption_key: Option<&StringEncoding>,
+    ) -> Result<(PathBuf, Vec<PathBuf>, Vec<PathBuf>)> {
+        save_psbt(
+            &mut self.psbt,
+            &mut self.psbts_dir.clone(),
+            qr_version,
+            qr_format,
+            encryption_key,
+        )
     }
 
     fn pretty_print(&self, wallets: &[WalletJson]) -> Result<PsbtPrettyPrint> {
@@ -411,7 +467,18 @@ impl PSBTSigner {
 }
 
 pub fn start(opt: &SignOptions, network: Network) -> Result<PsbtPrettyPrint> {
-    let wallet = read_wallet(&opt.wallet_descriptor_file)?;
+    Ok(sign_psbt(opt, network)?.0)
+}
+
+/// Like `start`, also returning the journal record if signatures have been added
+pub fn sign_psbt(
+    opt: &SignOptions,
+    network: Network,
+) -> Result<(PsbtPrettyPrint, Option<JournalRecord>)> {
+    let wallet = read_wallet(
+        &opt.wallet_descriptor_file,
+        opt.datadir_encryption_key.as_ref(),
+    )?;
     let mut psbt_signer = PSBTSigner::from_opt(opt, network)?;
     debug!("{:?}", psbt_signer);
     //TODO refuse to sign if my address has first level different from 0/1 and more than one level?
@@ -422,14 +489,33 @@ pub fn start(opt: &SignOptions, network: Network) -> Result<PsbtPrettyPrint> {
         psbt_print.info.push("Added paths".to_string());
     }
     if sign_result.signed {
-        let (psbt_file, _) = psbt_signer.save_signed_psbt_file(opt.qr_version)?;
+        let (psbt_file, _, _) = psbt_signer.save_signed_psbt_file(
+            opt.qr_version,
+            opt.qr_format,
+            opt.datadir_encryption_key.as_ref(),
+        )?;
         psbt_print.psbt_file = psbt_file;
         psbt_print.info.push("Added signatures".to_string());
     } else {
         psbt_print.info.push("No signature added".to_string());
     }
+    if let Some(binary_out) = &opt.binary_out {
+        save_psbt_binary(&psbt_signer.psbt, binary_out)?;
+    }
+    let record = if sign_result.signed {
+        let fingerprint = psbt_signer.xprv.fingerprint(&psbt_signer.secp);
+        let fee = psbt_print.fee.absolute;
+        Some(JournalRecord::sign(
+            &psbt_signer.psbt,
+            network,
+            fingerprint,
+            fee,
+        ))
+    } else {
+        None
+    };
 
-    Ok(psbt_print)
+    Ok((psbt_print, record))
 }
 
 pub fn read_key(
@@ -508,6 +594,36 @@ mod tests {
         psbt_from_base64(&psbt_json.psbt).unwrap()
     }
 
+    #[test]
+    fn test_save_psbt_encrypted() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.json");
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        let mut opt = SavePSBTOptions {
+            psbt: StringEncoding::Base64(psbt_json.psbt),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: Some(StringEncoding::new_base64(&[1u8; 32])),
+        };
+        let psbt_file = save_psbt_options(datadir, Network::Testnet, &opt).unwrap();
+        let content = fs::read(&psbt_file).unwrap();
+
+        // an existing encrypted psbt is never overwritten without its key
+        let bytes = include_bytes!("../../test_data/sign/psbt_bip.signed.json");
+        let psbt_json: PsbtJson = serde_json::from_slice(bytes).unwrap();
+        opt.psbt = StringEncoding::Base64(psbt_json.psbt);
+        opt.encryption_key = Some(StringEncoding::new_base64(&[2u8; 32]));
+        assert!(save_psbt_options(datadir, Network::Testnet, &opt).is_err());
+        opt.encryption_key = None;
+        assert!(matches!(
+            save_psbt_options(datadir, Network::Testnet, &opt),
+            Err(Error::MissingEncryptionKey)
+        ));
+        assert_eq!(fs::read(&psbt_file).unwrap(), content);
+        assert!(!psbt_file.with_file_name("qr").exists());
+    }
+
     #[test]
     fn test_compression() {
         let bytes = include_bytes!("../../test_data/sign/psbt_bip.json");
@@ -554,7 +670,7 @@ mod tests {
 
         assert_eq!(
             key.xpub.to_string(),
-            ExtendedPubKey::from_private(&secp, &key.xprv).to_string()
+            ExtendedPubKey::from_private(&secp, &key.xprv.unwrap()).to_string()
         );
 
         assert_eq!(
@@ -592,14 +708,14 @@ mod tests {
         assert_eq!(outputs_len, 106);
 
         assert_eq!(
-            test_sign(&mut psbt_to_sign, &psbt_signed, &key.xprv)
+            test_sign(&mut psbt_to_sign, &psbt_signed, &key.xprv.unwrap())
                 .unwrap_err()
                 .to_string(),
             Error::MissingPrevoutTx.to_string(),
         );
         psbt_to_sign.inputs[1].non_witness_utxo = psbt_to_sign.inputs[0].non_witness_utxo.clone();
         assert_eq!(
-            test_sign(&mut psbt_to_sign, &psbt_signed, &key.xprv)
+            test_sign(&mut psbt_to_sign, &psbt_signed, &key.xprv.unwrap())
                 .unwrap_err()
                 .to_string(),
             Error::MismatchPrevoutHash.to_string(),
@@ -607,12 +723,15 @@ mod tests {
 
         let mut mut_psbt_signed = psbt_signed.clone();
         assert!(
-            test_sign(&mut mut_psbt_signed, &psbt_signed, &key.xprv).is_err(),
+            matches!(
+                test_sign(&mut mut_psbt_signed, &psbt_signed, &key.xprv.unwrap()),
+                Err(Error::PSBTAlreadySigned(0))
+            ),
             "trying to sign a psbt which is already signed with this key"
         );
 
         psbt_to_sign.inputs[1].non_witness_utxo = Some(tx2);
-        test_sign(&mut psbt_to_sign, &psbt_signed, &key.xprv).unwrap();
+        test_sign(&mut psbt_to_sign, &psbt_signed, &key.xprv.unwrap()).unwrap();
 
         assert!(perc_diff_with_core(&psbt_to_sign, 462).unwrap()); // 462 is estimated_vsize from analyzepsbt
 
@@ -625,16 +744,16 @@ mod tests {
         let key: crate::PrivateMasterKeyJson = serde_json::from_slice(bytes).unwrap();
         assert_eq!(
             key.xpub.to_string(),
-            ExtendedPubKey::from_private(&secp, &key.xprv).to_string()
+            ExtendedPubKey::from_private(&secp, &key.xprv.unwrap()).to_string()
         );
         assert!(
-            test_sign(&mut psbt_to_sign, &psbt_1, &key.xprv).is_err(),
+            test_sign(&mut psbt_to_sign, &psbt_1, &key.xprv.unwrap()).is_err(),
             "segwit input missing previous tx"
         );
         let tx_in = "020000000001019e60071916a88cf0f5b9c6f015b7f8eef3ab1ef6ca4929b7236ec74e693f36210000000023220020c3af1472a85b23206da9be4fbef18d0ce5fd965671110d722a816e892d2e5f33fdffffff02801a0600000000002200201148e93e9315e37dbed2121be5239257af35adc03ffdfc5d914b083afa44dab80e07a1010000000017a9142aaba9f43085c5a6f28b0d01a8ed4dbcc0e5ec4f87040047304402203fdaeafde5fc1d1838d4c431abf6672f4cfee996f932187b31a4e3dad04d7b9f0220247d2cee5aabceb029ee6a1809a821fd95aa3ff02627977cbac8d00ff5a4628901473044022026879e4c65462161e2805ca26d392b0aace13906ec5b4776cac99f5e2bfd49f4022072500f1e2818a6738c37b6cedb2fd0a16375df34ce145e3f8fbfef7b7bec99d401475221020ca0e815748c41087075f3840c1edd9400f4db031dbe948b1929b6a93c72386a21026471f666489f80aed63bbbdee4f09ffcd69b40900435633cef5f5a35bf00932752ae4ff21700";
         let tx_in: Transaction = deserialize(&hex::decode(tx_in).unwrap()).unwrap();
         psbt_to_sign.inputs[0].non_witness_utxo = Some(tx_in.clone());
-        test_sign(&mut psbt_to_sign, &psbt_1, &key.xprv).unwrap();
+        test_sign(&mut psbt_to_sign, &psbt_1, &key.xprv.unwrap()).unwrap();
         assert!(perc_diff_with_core(&psbt_to_sign, 192).unwrap());
 
         let bytes = include_bytes!("../../test_data/sign/psbt_testnet.2.signed.json");
@@ -644,14 +763,14 @@ mod tests {
         let key: crate::PrivateMasterKeyJson = serde_json::from_slice(bytes).unwrap();
         assert_eq!(
             key.xpub.to_string(),
-            ExtendedPubKey::from_private(&secp, &key.xprv).to_string()
+            ExtendedPubKey::from_private(&secp, &key.xprv.unwrap()).to_string()
         );
         assert!(
-            test_sign(&mut psbt_to_sign, &psbt_2, &key.xprv).is_err(),
+            test_sign(&mut psbt_to_sign, &psbt_2, &key.xprv.unwrap()).is_err(),
             "segwit input missing previous tx"
         );
         psbt_to_sign.inputs[0].non_witness_utxo = Some(tx_in);
-        test_sign(&mut psbt_to_sign, &psbt_2, &key.xprv).unwrap();
+        test_sign(&mut psbt_to_sign, &psbt_2, &key.xprv.unwrap()).unwrap();
 
         let bytes = include_bytes!("../../test_data/sign/psbt_testnet.signed.json");
         let (psbt_complete_bytes, psbt_complete) = extract_psbt(bytes);
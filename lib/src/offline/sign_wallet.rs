@@ -22,6 +22,17 @@ pub struct SignWalletOptions {
     /// It is an Option so that structopt could skip,
     #[structopt(skip)]
     pub encryption_key: Option<StringEncoding>,
+
+    /// Optional datadir encryption key, for the wallet and the signature files
+    #[structopt(skip)]
+    #[serde(default)]
+    pub datadir_encryption_key: Option<StringEncoding>,
+
+    /// BIP39 passphrase, required if the key of the wallet has been created with one
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    #[serde(default)]
+    pub passphrase: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
@@ -29,6 +40,11 @@ pub struct VerifyWalletOptions {
     /// Wallet name to be verified
     #[structopt(long)]
     pub wallet_name: String,
+
+    /// Optional datadir encryption key
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
 }
 
 pub fn verify_wallet(
@@ -46,7 +62,12 @@ pub fn verify_wallet(
     let signature_file = wallet_path.file("signature.json")?;
     let secp = Secp256k1::verification_only();
 
-    verify_wallet_internal(&wallet_file, &signature_file, &secp)
+    verify_wallet_internal(
+        &wallet_file,
+        &signature_file,
+        opt.encryption_key.as_ref(),
+        &secp,
+    )
 }
 
 pub fn sign_wallet(
@@ -64,7 +85,7 @@ pub fn sign_wallet(
     )
     .file("descriptor.json")?;
     debug!("wallet_file {:?}", wallet_file);
-    let wallet = read_wallet(&wallet_file)?; // read the json
+    let wallet = read_wallet(&wallet_file, opt.datadir_encryption_key.as_ref())?; // read the json
     let message = &wallet.descriptor;
     let xpubs: Vec<ExtendedPubKey> = extract_xpubs(&wallet.descriptor)?;
     let encryption_keys = match opt.encryption_key.as_ref() {
@@ -81,7 +102,7 @@ pub fn sign_wallet(
     };
     debug!("list_opt {:?}", list_opt);
     let available_keys = common::list::list(datadir, network, &list_opt)?;
-    let master_private_key = find_key(&available_keys, &xpubs)?; // TODO should be added a derivation?
+    let master_private_key = find_key(&available_keys, &xpubs, opt.passphrase.as_deref())?; // TODO should be added a derivation?
     let master_public_key = ExtendedPubKey::from_private(&secp, &master_private_key);
 
     let signature = sign_message_with_key(&master_private_key.private_key, message, &secp)?;
@@ -95,6 +116,7 @@ pub fn sign_wallet(
         firma_datadir: datadir.to_string(),
         network,
         wallet_name: wallet.name,
+        encryption_key: opt.datadir_encryption_key.clone(),
     };
 
     let wallet_signature = WalletSignature {
@@ -106,13 +128,14 @@ pub fn sign_wallet(
     Ok(wallet_signature)
 }
 
-fn find_key<'a>(
-    available_keys: &'a ListOutput,
+fn find_key(
+    available_keys: &ListOutput,
     xpubs: &[ExtendedPubKey],
-) -> Result<&'a ExtendedPrivKey> {
+    passphrase: Option<&str>,
+) -> Result<ExtendedPrivKey> {
     for key in available_keys.keys.iter() {
         if check_xpub_in_descriptor(&key.key.xpub, &xpubs).is_ok() {
-            return Ok(&key.key.xprv);
+            return key.key.signing_xprv(passphrase);
         }
     }
     Err("There is No private key participating in the wallet available".into())
@@ -133,10 +156,11 @@ fn check_xpub_in_descriptor(
 pub fn verify_wallet_internal(
     wallet_path: &PathBuf,
     signature_path: &PathBuf,
+    encryption_key: Option<&StringEncoding>,
     secp: &Secp256k1<VerifyOnly>,
 ) -> Result<VerifyWalletResult> {
-    let wallet = read_wallet(&wallet_path)?;
-    let signature = read_signature(&signature_path)?;
+    let wallet = read_wallet(wallet_path, encryption_key)?;
+    let signature = read_signature(signature_path, encryption_key)?;
     let xpubs = extract_xpubs(&wallet.descriptor)?;
     let message = &wallet.descriptor;
     let master_address = Address::p2pkh(&signature.xpub.public_key, signature.xpub.network);
@@ -158,7 +182,7 @@ pub fn verify_wallet_internal(
     Ok(result)
 }
 
-fn sign_message_with_key(
+pub fn sign_message_with_key(
     private_key: &PrivateKey,
     message: &str,
     secp: &Secp256k1<SignOnly>,
@@ -189,7 +213,7 @@ pub fn sign_message(private_key: &str, message: &str) -> Result<String> {
     sign_message_with_key(&private_key, message, &secp)
 }
 
-fn verify_message_with_address(
+pub fn verify_message_with_address(
     address: &Address,
     signature: &str,
     message: &str,
@@ -231,7 +255,10 @@ fn verify_message(address: &str, signature: &str, message: &str) -> Result<bool>
 // json contains signature, the address and descriptor of the address!
 #[cfg(test)]
 mod tests {
-    use crate::offline::sign_wallet::{sign_message, verify_message};
+    use crate::offline::import_wallet::import_wallet;
+    use crate::offline::random::{create_key, RandomOptions};
+    use crate::offline::sign_wallet::*;
+    use tempfile::TempDir;
 
     /*
     $ bitcoin-cli signmessagewithprivkey "KwQoPt6dL91fxRBWdt4nkCVrfo4ipeLcaD4ZCLntoTPhKGNgGqGm" ciao
@@ -254,4 +281,58 @@ mod tests {
     fn test_verify_message() {
         assert!(verify_message(ADDRESS, SIGNATURE, MESSAGE).unwrap());
     }
+
+    #[test]
+    fn test_sign_wallet_passphrase() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let other_dir = TempDir::new().unwrap();
+        let network = Network::Testnet;
+        let mut random_opt = RandomOptions::new("a".to_string());
+        random_opt.passphrase = Some("TREZOR".to_string());
+        let key = create_key(datadir, network, &random_opt).unwrap();
+        let other_datadir = other_dir.path().to_str().unwrap();
+        let other = create_key(other_datadir, network, &RandomOptions::new("b".to_string()));
+        let other = other.unwrap();
+        let wallet = WalletJson {
+            name: "w".to_string(),
+            descriptor: format!("wsh(multi(2,{}/0/*,{}/0/*))", key.key.xpub, other.key.xpub),
+            fingerprints: [key.key.fingerprint, other.key.fingerprint]
+                .iter()
+                .cloned()
+                .collect(),
+            required_sig: 2,
+            created_at_height: 0,
+        };
+        import_wallet(datadir, network, &wallet, None).unwrap();
+
+        let mut opt = SignWalletOptions {
+            wallet_name: "w".to_string(),
+            encryption_key: None,
+            datadir_encryption_key: None,
+            passphrase: None,
+        };
+        assert!(matches!(
+            sign_wallet(datadir, network, &opt),
+            Err(Error::MissingPassphrase)
+        ));
+        opt.passphrase = Some("WRONG".to_string());
+        assert!(matches!(
+            sign_wallet(datadir, network, &opt),
+            Err(Error::PassphraseMismatch)
+        ));
+        opt.passphrase = Some("TREZOR".to_string());
+        let signature = sign_wallet(datadir, network, &opt).unwrap();
+        assert_eq!(signature.xpub, key.key.xpub);
+
+        let verify_opt = VerifyWalletOptions {
+            wallet_name: "w".to_string(),
+            encryption_key: None,
+        };
+        assert!(
+            verify_wallet(datadir, network, &verify_opt)
+                .unwrap()
+                .verified
+        );
+    }
 }
@@ -0,0 +1,237 @@
+use crate::offline::sign::read_key;
+use crate::*;
+use bitcoin::Network;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Iteration exponent of the SLIP-39 passphrase encryption, it is encoded in every share but
+/// sssmc39 decrypts the recombined secret always with 0, so other values don't round trip
+const ITERATION_EXPONENT: u8 = 0;
+
+/// Split the BIP32 seed of a key in SLIP-39 shares, any SLIP-39 wallet recovers the same master
+/// key from them. The seed is computed from the mnemonic, keys without it can't be split
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct SplitOptions {
+    /// Name of the key to split
+    #[structopt(short, long)]
+    pub key_name: String,
+
+    /// Number of groups required to restore the key
+    #[structopt(long, default_value = "1")]
+    pub group_threshold: u8,
+
+    /// A group of shares in the form `{member_threshold}of{member_count}`, eg. 2of3, could be repeated
+    #[structopt(long = "group", required = true)]
+    pub groups: Vec<SlipGroup>,
+
+    /// in CLI it is populated from standard input
+    /// It is an Option so that structopt could skip,
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+
+    /// BIP39 passphrase, required if the key has been created with one, the shares contain the
+    /// seed derived with it and are not protected by a SLIP-39 passphrase
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    #[serde(default)]
+    pub passphrase: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SlipGroup {
+    pub member_threshold: u8,
+    pub member_count: u8,
+}
+
+impl FromStr for SlipGroup {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("({}) valid values are in the form 2of3", s),
+            )
+        };
+        let mut parts = s.split("of");
+        let member_threshold = parts.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+        let member_count = parts.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+        if parts.next().is_some() {
+            return Err(err());
+        }
+        Ok(SlipGroup {
+            member_threshold,
+            member_count,
+        })
+    }
+}
+
+/// Split the BIP32 seed of the key in SLIP-39 shares, every share is verified by recombining it
+/// with others
+pub fn split(datadir: &str, network: Network, opt: &SplitOptions) -> Result<SplitOutput> {
+    let key_file = PathBuilder::new(datadir, network, Kind::Key, Some(opt.key_name.to_string()))
+        .file("PRIVATE.json")?;
+    let key = read_key(&key_file, opt.encryption_key.as_ref())?;
+    let mnemonic = key.mnemonic.as_ref().ok_or(Error::MissingMnemonic)?;
+    // checks the passphrase matches the key
+    key.signing_xprv(opt.passphrase.as_deref())?;
+    let seed = mnemonic.to_seed(opt.passphrase.as_deref().filter(|p| !p.is_empty()));
+    let seed = &seed.0[..];
+
+    let groups: Vec<(u8, u8)> = opt
+        .groups
+        .iter()
+        .map(|g| (g.member_threshold, g.member_count))
+        .collect();
+    let group_shares =
+        sssmc39::generate_mnemonics(opt.group_threshold, &groups, seed, "", ITERATION_EXPONENT)?;
+    let mut shares = vec![];
+    for group_share in group_shares.iter() {
+        shares.push(group_share.mnemonic_list()?);
+    }
+    verify_shares(&shares, &opt.groups, opt.group_threshold, seed)?;
+    info!("{} groups of shares verified", shares.len());
+
+    let groups = shares
+        .into_iter()
+        .zip(opt.groups.iter())
+        .map(|(shares, group)| SlipGroupShares {
+            member_threshold: group.member_threshold,
+            shares: shares.iter().map(|words| words.join(" ")).collect(),
+        })
+        .collect();
+
+    Ok(SplitOutput {
+        key_name: opt.key_name.clone(),
+        fingerprint: key.fingerprint,
+        group_threshold: opt.group_threshold,
+        groups,
+    })
+}
+
+/// recombine every share at least once, with a threshold of members taken cyclically from its
+/// group and the first threshold of members of the other groups needed
+fn verify_shares(
+    shares: &[Vec<Vec<String>>],
+    groups: &[SlipGroup],
+    group_threshold: u8,
+    seed: &[u8],
+) -> Result<()> {
+    let first_members = |i: usize| shares[i][..groups[i].member_threshold as usize].to_vec();
+    for (i, group) in groups.iter().enumerate() {
+        let others = (0..groups.len()).filter(|j| *j != i);
+        for start in 0..group.member_count as usize {
+            let mut selected: Vec<Vec<String>> = (0..group.member_threshold as usize)
+                .map(|m| shares[i][(start + m) % group.member_count as usize].clone())
+                .collect();
+            for j in others
+                .clone()
+                .take((group_threshold as usize).saturating_sub(1))
+            {
+                selected.extend(first_members(j));
+            }
+            if sssmc39::combine_mnemonics(&selected, "")? != seed {
+                return Err(Error::Slip39Verification);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Recombine SLIP-39 shares and return the master secret, which is the BIP32 seed of the key
+pub fn combine(shares: &[String], passphrase: &str) -> Result<Vec<u8>> {
+    let shares: Vec<Vec<String>> = shares
+        .iter()
+        .map(|share| share.split_whitespace().map(|w| w.to_string()).collect())
+        .collect();
+    Ok(sssmc39::combine_mnemonics(&shares, passphrase)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::random::{create_key, RandomOptions};
+    use crate::offline::restore::{self, Nature, RestoreOptions};
+    use crate::offline::split::*;
+    use crate::qr::QrFormat;
+    use bitcoin::util::bip32::ExtendedPrivKey;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_split() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let key = create_key(
+            datadir,
+            Network::Testnet,
+            &RandomOptions::new("a".to_string()),
+        )
+        .unwrap()
+        .key;
+
+        let mut opt = SplitOptions {
+            key_name: "a".to_string(),
+            group_threshold: 2,
+            groups: vec![
+                SlipGroup::from_str("2of3").unwrap(),
+                SlipGroup::from_str("1of1").unwrap(),
+                SlipGroup::from_str("3of5").unwrap(),
+            ],
+            encryption_key: None,
+            passphrase: None,
+        };
+        let result = split(datadir, Network::Testnet, &opt).unwrap();
+        assert_eq!(result.fingerprint, key.fingerprint);
+        assert_eq!(result.groups.len(), 3);
+        assert_eq!(result.groups[2].shares.len(), 5);
+        assert_eq!(result.groups[0].shares[0].split(' ').count(), 59);
+
+        // the master secret is the BIP32 seed, as in any SLIP-39 wallet
+        let mut shares = result.groups[1].shares.clone();
+        shares.extend(result.groups[2].shares[2..].to_vec());
+        let seed = combine(&shares, "").unwrap();
+        assert_eq!(
+            seed,
+            key.mnemonic.as_ref().unwrap().to_seed(None).0.to_vec()
+        );
+        let xprv = ExtendedPrivKey::new_master(Network::Testnet, &seed).unwrap();
+        assert_eq!(Some(xprv), key.xprv);
+        assert!(combine(&shares[..3], "").is_err());
+
+        let restore_opt = RestoreOptions {
+            key_name: "restored".to_string(),
+            nature: Nature::Slip39,
+            value: shares.join(","),
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+            passphrase: None,
+        };
+        let restored = restore::start(datadir, Network::Testnet, &restore_opt).unwrap();
+        assert_eq!(restored.key.fingerprint, key.fingerprint);
+
+        // keys with a passphrase are split with the seed derived with it
+        let mut random_opt = RandomOptions::new("p".to_string());
+        random_opt.passphrase = Some("pass".to_string());
+        let key = create_key(datadir, Network::Testnet, &random_opt)
+            .unwrap()
+            .key;
+        opt.key_name = "p".to_string();
+        assert!(matches!(
+            split(datadir, Network::Testnet, &opt),
+            Err(Error::MissingPassphrase)
+        ));
+        opt.passphrase = Some("pass".to_string());
+        let result = split(datadir, Network::Testnet, &opt).unwrap();
+        let mut shares = result.groups[1].shares.clone();
+        shares.extend(result.groups[2].shares[..3].to_vec());
+        let seed = combine(&shares, "").unwrap();
+        let xprv = ExtendedPrivKey::new_master(Network::Testnet, &seed).unwrap();
+        assert_eq!(key.signing_xprv(Some("pass")).unwrap(), xprv);
+
+        assert!(SlipGroup::from_str("2of").is_err());
+        assert!(SlipGroup::from_str("2of3of4").is_err());
+    }
+}
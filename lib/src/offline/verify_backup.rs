@@ -0,0 +1,192 @@
+use crate::mnemonic::Mnemonic;
+use crate::offline::dice::{calculate_key, check_launches, Base};
+use crate::offline::sign::read_key;
+use crate::*;
+use bitcoin::Network;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+
+/// Verify the backup of a key recomputing it from the mnemonic or the dice launches, nothing is
+/// written except the time of the last successful verification
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct VerifyBackupOptions {
+    /// Name of the key to verify
+    #[structopt(short, long)]
+    pub key_name: String,
+
+    /// The mnemonic as written in the backup
+    #[structopt(short, long)]
+    pub mnemonic: Option<String>,
+
+    /// Number of faces of the dice, for keys created with dice
+    #[structopt(short, long)]
+    pub faces: Option<Base>,
+
+    /// Value of the die launch as written in the backup, to be repeated multiple times
+    #[structopt(short)]
+    #[serde(default)]
+    pub launches: Vec<u32>,
+
+    /// Optional encryption key for reading the key file
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub encryption_key: Option<StringEncoding>,
+
+    /// BIP39 passphrase, required if the key has been created with one
+    /// in CLI it is populated from standard input
+    #[structopt(skip)]
+    pub passphrase: Option<String>,
+}
+
+pub fn verify_backup(
+    datadir: &str,
+    network: Network,
+    opt: &VerifyBackupOptions,
+) -> Result<VerifyBackupOutput> {
+    let path_builder = PathBuilder::new(datadir, network, Kind::Key, Some(opt.key_name.clone()));
+    let key = read_key(
+        &path_builder.file("PRIVATE.json")?,
+        opt.encryption_key.as_ref(),
+    )?;
+    if key.passphrase && opt.passphrase.is_none() {
+        return Err(Error::MissingPassphrase);
+    }
+
+    let recomputed = match (&opt.mnemonic, opt.faces, opt.launches.is_empty()) {
+        (Some(mnemonic), None, true) => {
+            let words: Vec<String> = mnemonic
+                .split_whitespace()
+                .map(|w| w.to_lowercase())
+                .collect();
+            let mnemonic = Mnemonic::from_str(&words.join(" "))?;
+            PrivateMasterKeyJson::new(network, &mnemonic, &key.name, opt.passphrase.as_deref())?
+        }
+        (None, Some(faces), false) => {
+            if key.dice.as_ref().map(|d| d.xor_random).unwrap_or(false) {
+                return Err(Error::VerifyBackupXorRandom);
+            }
+            check_launches(&opt.launches, faces as u32)?;
+            calculate_key(
+                &opt.launches,
+                faces as u32,
+                network,
+                &key.name,
+                opt.passphrase.as_deref(),
+                None,
+            )?
+        }
+        _ => return Err(Error::VerifyBackupSource),
+    };
+
+    let verified = recomputed.fingerprint == key.fingerprint;
+    let verification_file = path_builder.file("backup_verification.json")?;
+    if verified {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| Error::Generic(e.to_string()))?
+            .as_secs();
+        let verification = BackupVerification { last_verified: now };
+        fs::write(
+            &verification_file,
+            serde_json::to_string_pretty(&verification)?,
+        )?;
+        info!("Backup of {} verified", key.name);
+    }
+    let last_verified = fs::read(&verification_file)
+        .ok()
+        .and_then(|content| serde_json::from_slice::<BackupVerification>(&content).ok())
+        .map(|v| v.last_verified);
+
+    Ok(VerifyBackupOutput {
+        key_name: key.name,
+        fingerprint: key.fingerprint,
+        verified,
+        last_verified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::offline::dice::{roll, Bits, DiceOptions};
+    use crate::offline::random::{create_key, RandomOptions};
+    use crate::offline::verify_backup::*;
+    use crate::qr::QrFormat;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_verify_backup() {
+        let temp_dir = TempDir::new().unwrap();
+        let datadir = temp_dir.path().to_str().unwrap();
+        let key = create_key(datadir, Network::Testnet, &RandomOptions::new("r".into())).unwrap();
+        let mnemonic = key.key.mnemonic.unwrap().to_string();
+
+        let mut opt = VerifyBackupOptions {
+            key_name: "r".to_string(),
+            mnemonic: Some(format!("  {} ", mnemonic.to_uppercase())),
+            faces: None,
+            launches: vec![],
+            encryption_key: None,
+            passphrase: None,
+        };
+        let result = verify_backup(datadir, Network::Testnet, &opt).unwrap();
+        assert!(result.verified);
+        assert!(result.last_verified.is_some());
+
+        let other = create_key(datadir, Network::Testnet, &RandomOptions::new("o".into())).unwrap();
+        opt.mnemonic = Some(other.key.mnemonic.unwrap().to_string());
+        let result = verify_backup(datadir, Network::Testnet, &opt).unwrap();
+        assert!(!result.verified);
+        assert!(result.last_verified.is_some());
+
+        opt.launches = vec![1];
+        assert!(verify_backup(datadir, Network::Testnet, &opt).is_err());
+
+        let mut dice_opt = DiceOptions {
+            faces: Base::_20,
+            bits: Bits::_128,
+            key_name: "d".to_string(),
+            launches: vec![2u32; 29],
+            qr_version: 14,
+            qr_format: QrFormat::default(),
+            encryption_key: None,
+            passphrase: None,
+            force: true,
+            xor_random: false,
+        };
+        roll(datadir, Network::Testnet, &dice_opt).unwrap();
+        let mut opt = VerifyBackupOptions {
+            key_name: "d".to_string(),
+            mnemonic: None,
+            faces: Some(Base::_20),
+            launches: vec![2u32; 29],
+            encryption_key: None,
+            passphrase: None,
+        };
+        let result = verify_backup(datadir, Network::Testnet, &opt).unwrap();
+        assert!(result.verified);
+        assert!(result.last_verified.is_some());
+
+        // launches must be faces of the dice
+        opt.launches[0] = 0;
+        assert!(matches!(
+            verify_backup(datadir, Network::Testnet, &opt),
+            Err(Error::DiceValueErr(0, 20))
+        ));
+        opt.launches[0] = 21;
+        assert!(matches!(
+            verify_backup(datadir, Network::Testnet, &opt),
+            Err(Error::DiceValueErr(21, 20))
+        ));
+        opt.launches[0] = 2;
+
+        dice_opt.key_name = "x".to_string();
+        dice_opt.xor_random = true;
+        roll(datadir, Network::Testnet, &dice_opt).unwrap();
+        opt.key_name = "x".to_string();
+        assert!(verify_backup(datadir, Network::Testnet, &opt).is_err());
+    }
+}
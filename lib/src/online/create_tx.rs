@@ -1,37 +1,66 @@
+use crate::common::bip21::PaymentUri;
+use crate::common::proprietary::set_psbt_metadata;
 use crate::offline::sign::save_psbt;
 use crate::online::get_address::GetAddressOptions;
 use crate::online::Wallet;
+use crate::qr::QrFormat;
 use crate::qr::QrMode;
 use crate::*;
-use bitcoin::{Address, Amount, OutPoint};
+use bitcoin::{Address, Amount, Network, OutPoint};
 use bitcoincore_rpc::bitcoincore_rpc_json::{
     CreateRawTransactionInput, GetTransactionResultDetailCategory, WalletCreateFundedPsbtOptions,
 };
 use bitcoincore_rpc::RpcApi;
 use log::{debug, info};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
 pub struct CreateTxOptions {
     /// Address and amount in satoshi of the recipient specified as address:amount,
     /// it is possible to use units for amount but is mandatory to enclose quotes eg "address:amount BTC"
     /// at least 1 is required
     #[structopt(long, long = "recipient")]
+    #[serde(default)]
     pub recipients: Vec<AddressAmount>,
 
+    /// BIP21 payment URI of the recipient with the amount eg "bitcoin:address?amount=0.001",
+    /// could be used together with --recipient
+    #[structopt(long = "uri")]
+    #[serde(default)]
+    pub uris: Vec<PaymentUri>,
+
     /// Coin to spend, specified as txid:vout see list-coins, if not specified the node will choose coins
     #[structopt(long, long = "coin")]
+    #[serde(default)]
     pub coins: Vec<OutPoint>,
 
     /// Name of the PSBT
     #[structopt(short, long)]
     pub psbt_name: String,
 
+    /// Free-form note stored in the PSBT metadata
+    #[structopt(long)]
+    pub memo: Option<String>,
+
     /// QR code max version to use (max size)
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
+
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+
+    /// Also write the PSBT in BIP174 binary format at this path
+    #[structopt(long, parse(from_os_str))]
+    pub binary_out: Option<PathBuf>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -42,18 +71,35 @@ pub struct AddressAmount {
 
 impl CreateTxOptions {
     fn validate(&self) -> Result<()> {
-        if self.recipients.is_empty() {
-            return Err("At least one recipient is mandatory (--recipient)".into());
+        if self.recipients.is_empty() && self.uris.is_empty() {
+            return Err("At least one recipient is mandatory (--recipient or --uri)".into());
         }
 
         Ok(())
     }
 
-    fn recipients_as_outputs(&self) -> HashMap<String, Amount> {
-        self.recipients
-            .iter()
-            .map(|r| (r.address.to_string(), r.amount))
-            .collect()
+    /// recipients given as `address:amount` and as payment URIs, all on `network` and with
+    /// different addresses, since the outputs are keyed by address
+    fn all_recipients(&self, network: Network) -> Result<Vec<AddressAmount>> {
+        let mut recipients = vec![];
+        for r in self.recipients.iter() {
+            recipients.push(AddressAmount {
+                address: r.address.clone(),
+                amount: r.amount,
+            });
+        }
+        for uri in self.uris.iter() {
+            recipients.push(uri.try_into()?);
+        }
+        let mut addresses = HashSet::new();
+        for r in recipients.iter() {
+            check_compatibility(r.address.network, network)
+                .map_err(|_| Error::AddressWrongNetwork(r.address.clone(), network))?;
+            if !addresses.insert(r.address.to_string()) {
+                return Err(Error::RecipientDuplicated(r.address.clone()));
+            }
+        }
+        Ok(recipients)
     }
 
     fn coins_as_inputs(&self) -> Vec<CreateRawTransactionInput> {
@@ -69,6 +115,40 @@ impl CreateTxOptions {
     }
 }
 
+impl TryFrom<&PaymentUri> for AddressAmount {
+    type Error = Error;
+
+    fn try_from(uri: &PaymentUri) -> Result<Self> {
+        let amount = uri
+            .amount
+            .ok_or_else(|| Error::PaymentUriMissingAmount(uri.to_string()))?;
+        Ok(AddressAmount {
+            address: uri.address.clone(),
+            amount,
+        })
+    }
+}
+
+impl fmt::Display for AddressAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.address, self.amount.as_sat())
+    }
+}
+
+/// Serialized as the command line argument `address:amount`
+impl Serialize for AddressAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for AddressAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        AddressAmount::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 impl FromStr for AddressAmount {
     type Err = std::io::Error;
 
@@ -92,7 +172,11 @@ impl FromStr for AddressAmount {
 impl Wallet {
     pub fn create_tx(&self, opt: &CreateTxOptions) -> Result<CreateTxOutput> {
         opt.validate()?;
-        let outputs = opt.recipients_as_outputs();
+        let recipients = opt.all_recipients(self.context.network)?;
+        let outputs: HashMap<String, Amount> = recipients
+            .iter()
+            .map(|r| (r.address.to_string(), r.amount))
+            .collect();
         debug!("{:?}", outputs);
         let inputs = opt.coins_as_inputs();
         debug!("{:?}", inputs);
@@ -102,6 +186,7 @@ impl Wallet {
         let get_addr_opts = GetAddressOptions {
             index: None,
             qr_mode: QrMode::None,
+            ..Default::default()
         };
         options.change_address = Some(self.get_address(&get_addr_opts)?.address);
         let result = self.client.wallet_create_funded_psbt(
@@ -128,9 +213,31 @@ impl Wallet {
         };
 
         let mut psbt = psbt_from_rpc(&funded_psbt, &opt.psbt_name)?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| Error::Generic(e.to_string()))?
+            .as_secs();
+        let metadata = PsbtMetadata {
+            name: None,
+            creator: Some(format!("firma {}", env!("CARGO_PKG_VERSION"))),
+            created_at: Some(created_at),
+            wallet_name: Some(self.context.wallet_name.clone()),
+            memo: opt.memo.clone(),
+        };
+        set_psbt_metadata(&mut psbt, &metadata)?;
 
         let mut psbts_dir = self.context.psbts_dir()?;
-        let (psbt_file, qr_files) = save_psbt(&mut psbt, &mut psbts_dir, opt.qr_version)?;
+        let (psbt_file, qr_files, ur_files) = save_psbt(
+            &mut psbt,
+            &mut psbts_dir,
+            opt.qr_version,
+            opt.qr_format,
+            self.context.encryption_key.as_ref(),
+        )?;
+
+        if let Some(binary_out) = &opt.binary_out {
+            save_psbt_binary(&psbt, binary_out)?;
+        }
 
         // detect address reuse
         let transactions = self
@@ -138,7 +245,7 @@ impl Wallet {
             .list_transactions(None, Some(1000), None, Some(true))
             .unwrap();
         let mut address_reused = HashSet::new();
-        for recipient in opt.recipients.iter() {
+        for recipient in recipients.iter() {
             for tx in transactions.iter() {
                 if tx.detail.address.as_ref() == Some(&recipient.address)
                     && tx.detail.category == GetTransactionResultDetailCategory::Send
@@ -149,12 +256,57 @@ impl Wallet {
         }
 
         let create_tx = CreateTxOutput {
-            funded_psbt: (&psbt).into(),
+            funded_psbt: (&psbt).try_into()?,
             psbt_file,
             address_reused,
             qr_files,
+            ur_files,
         };
 
         Ok(create_tx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::online::create_tx::CreateTxOptions;
+    use crate::Error;
+    use bitcoin::Network;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_all_recipients() {
+        let address = "tb1q5nrregep899vnvaa5vdpxcwg8794jqy38nu304kl4d7wm4e92yeqz4jfmk";
+        let other = "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn";
+        let recipient = format!("{}:1000", address);
+        let uri = format!("bitcoin:{}?amount=0.00002", other);
+        let args = [
+            "create-tx",
+            "--psbt-name",
+            "a",
+            "--recipient",
+            &recipient,
+            "--uri",
+            &uri,
+        ];
+        let opt = CreateTxOptions::from_iter_safe(&args).unwrap();
+        let recipients = opt.all_recipients(Network::Testnet).unwrap();
+        assert_eq!(recipients.len(), 2);
+        assert_eq!(recipients[1].amount.as_sat(), 2000);
+
+        // the same address in a recipient and in an uri would be collapsed in a single output
+        let uri = format!("bitcoin:{}?amount=0.00002", address.to_uppercase());
+        let args = [
+            "create-tx",
+            "--psbt-name",
+            "a",
+            "--recipient",
+            &recipient,
+            "--uri",
+            &uri,
+        ];
+        let opt = CreateTxOptions::from_iter_safe(&args).unwrap();
+        let result = opt.all_recipients(Network::Testnet);
+        assert!(matches!(result, Err(Error::RecipientDuplicated(_))));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::online::{read_xpubs_files, Wallet};
+use crate::qr::QrFormat;
 use crate::*;
 use bitcoin::util::bip32::ExtendedPubKey;
 use bitcoin::Network;
@@ -8,10 +9,11 @@ use bitcoincore_rpc::bitcoincore_rpc_json::{
 use bitcoincore_rpc::RpcApi;
 use log::debug;
 use log::info;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
 pub struct CreateWalletOptions {
     /// number of signatures required
     #[structopt(short)]
@@ -19,10 +21,12 @@ pub struct CreateWalletOptions {
 
     /// Extended Public Keys (xpub) that are composing the wallet, given as String (xprv...)
     #[structopt(long = "xpub")]
+    #[serde(default)]
     pub xpubs: Vec<ExtendedPubKey>,
 
     /// Extended Public Keys (xpub) that are composing the wallet, given as a json file
     #[structopt(long = "xpub-file")]
+    #[serde(default)]
     pub xpub_files: Vec<PathBuf>,
 
     #[structopt(flatten)]
@@ -31,6 +35,11 @@ pub struct CreateWalletOptions {
     /// QR code max version to use (max size)
     #[structopt(long, default_value = "14")]
     pub qr_version: i16,
+
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
 }
 
 impl CreateWalletOptions {
@@ -122,14 +131,28 @@ impl Wallet {
         self.context.save_index(&indexes)?;
         self.context.save_daemon_opts(&daemon_opts)?;
 
-        let wallet_for_qr = wallet.clone();
-        let qr_bytes = serde_json::to_vec(&wallet_for_qr)?;
-
-        let wallet_qr_path = self.context.path_for_wallet_qr()?;
-        let qr_files = common::qr::save_qrs(qr_bytes, wallet_qr_path, opt.qr_version)?;
+        let (qr_files, ur_files) = if self.context.qr_allowed() {
+            let qr_bytes = common::compact_wallet::wallet_to_compact(&wallet)?;
+
+            let wallet_qr_path = self.context.path_for_wallet_qr()?;
+            let qr_files =
+                common::qr::save_qrs(qr_bytes, wallet_qr_path, opt.qr_version, opt.qr_format)?;
+
+            let ur_files = common::ur::save_ur_qrs(
+                common::ur::UrType::CryptoOutput,
+                &common::ur::output_to_cbor(&wallet)?,
+                self.context.path_for_wallet_ur()?,
+                opt.qr_version,
+                opt.qr_format,
+            )?;
+            (qr_files, ur_files)
+        } else {
+            (vec![], vec![])
+        };
 
         let create_wallet = CreateWalletOutput {
             qr_files,
+            ur_files,
             wallet_file,
             wallet,
             signature: None,
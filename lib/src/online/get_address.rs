@@ -1,16 +1,17 @@
+use crate::common::bip21::PaymentUri;
 use crate::offline::descriptor::DeriveAddressOpts;
 use crate::qr::QrMode;
 use crate::*;
-use bitcoin::util::address::Payload;
-use bitcoin::Address;
+use bitcoin::{Address, Amount};
 use bitcoincore_rpc::RpcApi;
 use log::info;
 use qr_code::QrCode;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::Write;
 use structopt::StructOpt;
 
-#[derive(StructOpt, Debug, Default)]
+#[derive(StructOpt, Debug, Default, Serialize, Deserialize)]
 pub struct GetAddressOptions {
     /// Explicitly specify address derivation index (by default taken from .firma and incremented)
     #[structopt(long)]
@@ -19,7 +20,20 @@ pub struct GetAddressOptions {
     /// Show the qr in text mode inside the returned json, note that new line are encoded,
     /// to properly see the qr_code you can pipe the json in jq eg. ` | jq -r .qr_text`
     #[structopt(long, default_value = "none")]
+    #[serde(default)]
     pub qr_mode: QrMode,
+
+    /// Amount in satoshi requested in the BIP21 payment URI
+    #[structopt(long)]
+    pub amount: Option<u64>,
+
+    /// Label of the recipient in the BIP21 payment URI
+    #[structopt(long)]
+    pub label: Option<String>,
+
+    /// Message describing the payment in the BIP21 payment URI
+    #[structopt(long)]
+    pub message: Option<String>,
 }
 
 impl Wallet {
@@ -51,15 +65,23 @@ impl Wallet {
         indexes.main += 1;
         self.context.save_index(&indexes)?;
 
+        let uri = PaymentUri {
+            address: derive_address.address.clone(),
+            amount: opts.amount.map(Amount::from_sat),
+            label: opts.label.clone(),
+            message: opts.message.clone(),
+        };
+        derive_address.uri = Some(uri.to_string());
+
         match opts.qr_mode {
             QrMode::Text { inverted } => {
-                let qr = addr_to_qr(&derive_address.address)?;
+                let qr = uri_to_qr(&uri)?;
                 let (mut output_file, name) = addr_to_file(&derive_address.address, "txt")?;
                 derive_address.qr_file = Some(name);
                 output_file.write_all(qr.to_string(inverted, 3).as_bytes())?;
             }
             QrMode::Image => {
-                let qr = addr_to_qr(&derive_address.address)?;
+                let qr = uri_to_qr(&uri)?;
                 let (output_file, name) = addr_to_file(&derive_address.address, "bmp")?;
                 derive_address.qr_file = Some(name);
                 qr.to_bmp()
@@ -79,11 +101,6 @@ fn addr_to_file(address: &Address, ext: &str) -> Result<(File, String)> {
     Ok((File::create(&name)?, name))
 }
 
-fn addr_to_qr(address: &Address) -> Result<QrCode> {
-    let address_string = address.to_string();
-    let qr_string = match &address.payload {
-        Payload::WitnessProgram { .. } => address_string.to_uppercase(),
-        _ => address_string,
-    };
-    Ok(qr_code::QrCode::new(qr_string.as_bytes())?)
+fn uri_to_qr(uri: &PaymentUri) -> Result<QrCode> {
+    Ok(qr_code::QrCode::new(uri.qr_string().as_bytes())?)
 }
@@ -1,30 +1,62 @@
 use crate::*;
 use bitcoin::util::bip32::ExtendedPubKey;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoin::Network;
+use bitcoincore_rpc::{Auth, RpcApi};
 use log::{debug, info};
+use rpc::NodeClient;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub mod balance;
 pub mod create_tx;
 pub mod create_wallet;
 pub mod get_address;
 pub mod list_coins;
+pub mod proof_of_reserves;
 pub mod rescan;
+pub mod rpc;
 pub mod send_tx;
+pub mod serve;
 
 pub struct Wallet {
-    pub client: Client,
+    pub client: NodeClient,
     context: Context,
 }
 
 impl Wallet {
-    pub fn new(url: String, auth: Auth, context: Context) -> Result<Self> {
+    pub fn new(url: &str, auth: Auth, timeout: Option<Duration>, context: Context) -> Result<Self> {
         Ok(Wallet {
-            client: Client::new(url, auth)?,
+            client: NodeClient::new(url, auth, timeout)?,
             context,
         })
     }
+
+    /// Wallet of `context` on the node specified by `daemon_opts`
+    pub fn open(daemon_opts: &DaemonOpts, context: Context) -> Result<Self> {
+        let url_with_wallet = format!("{}/wallet/{}", daemon_opts.url, context.wallet_name);
+        let timeout = daemon_opts.timeout.map(Duration::from_secs);
+        Wallet::new(&url_with_wallet, daemon_opts.auth()?, timeout, context)
+    }
+
+    /// Check the node is on the network of the context, returns the node height
+    pub fn check_network(&self) -> Result<u64> {
+        let result = self.client.get_blockchain_info()?;
+        let node_network = match result.chain.as_ref() {
+            "main" => Network::Bitcoin,
+            "test" => Network::Testnet,
+            "regtest" => Network::Regtest,
+            _ => return Err("Unrecognized network".into()),
+        };
+        if node_network != self.context.network {
+            return Err(format!(
+                "network of the bitcoin node {} does not match used one {}",
+                node_network, self.context.network
+            )
+            .into());
+        }
+        Ok(result.blocks)
+    }
 }
 
 fn read_xpubs_files(paths: &[PathBuf]) -> Result<Vec<ExtendedPubKey>> {
@@ -0,0 +1,124 @@
+use crate::common::proprietary::set_psbt_metadata;
+use crate::offline::reserves::{
+    commitment_txout, reserves_tx, verify_reserves, VerifyReservesOptions,
+};
+use crate::offline::sign::save_psbt;
+use crate::online::Wallet;
+use crate::qr::QrFormat;
+use crate::*;
+use bitcoin::{OutPoint, Script, TxOut};
+use bitcoincore_rpc::RpcApi;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+
+/// Create an unspendable PSBT proving the wallet controls its coins, to be signed by the cosigners
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
+pub struct ProofOfReservesOptions {
+    /// Challenge message committed in the proof, usually given by the auditor
+    #[structopt(long)]
+    pub message: String,
+
+    /// Coin to include, specified as txid:vout see list-coins, if not specified every confirmed
+    /// coin of the wallet is included
+    #[structopt(long = "coin")]
+    #[serde(default)]
+    pub coins: Vec<OutPoint>,
+
+    /// Name of the PSBT
+    #[structopt(short, long)]
+    pub psbt_name: String,
+
+    /// QR code max version to use (max size)
+    #[structopt(long, default_value = "14")]
+    pub qr_version: i16,
+
+    /// QR code image format (bmp, png, svg)
+    #[structopt(long, default_value = "bmp")]
+    #[serde(default)]
+    pub qr_format: QrFormat,
+}
+
+impl Wallet {
+    pub fn proof_of_reserves(&self, opt: &ProofOfReservesOptions) -> Result<CreateTxOutput> {
+        let unspent = self.client.list_unspent(Some(1), None, None, None, None)?;
+        let mut coins = vec![];
+        let mut total = 0u64;
+        for utxo in unspent.iter() {
+            let outpoint = OutPoint::new(utxo.txid, utxo.vout);
+            if opt.coins.is_empty() || opt.coins.contains(&outpoint) {
+                coins.push(outpoint);
+                total += utxo.amount.as_sat();
+            }
+        }
+        if let Some(missing) = opt.coins.iter().find(|c| !coins.contains(c)) {
+            return Err(format!("{} is not a confirmed coin of the wallet", missing).into());
+        }
+        if coins.is_empty() {
+            return Err("The wallet has no confirmed coins".into());
+        }
+
+        let mut psbt = PSBT::from_unsigned_tx(reserves_tx(&opt.message, &coins, total))?;
+        psbt.inputs[0].witness_utxo = Some(commitment_txout());
+
+        // the node fills the wallet inputs with previous txs, scripts and key paths
+        let args = [
+            psbt_to_base64(&psbt).1.into(),
+            false.into(),
+            "ALL".into(),
+            true.into(),
+        ];
+        let processed: Value = self.client.call("walletprocesspsbt", &args)?;
+        info!("walletprocesspsbt {:#?}", processed);
+        let processed = processed["psbt"].as_str().ok_or(Error::MissingHex)?;
+        let mut psbt = psbt_from_base64(processed)?.1;
+
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| Error::Generic(e.to_string()))?
+            .as_secs();
+        let metadata = PsbtMetadata {
+            name: Some(opt.psbt_name.clone()),
+            creator: Some(format!("firma {}", env!("CARGO_PKG_VERSION"))),
+            created_at: Some(created_at),
+            wallet_name: Some(self.context.wallet_name.clone()),
+            memo: Some(opt.message.clone()),
+        };
+        set_psbt_metadata(&mut psbt, &metadata)?;
+
+        let mut psbts_dir = self.context.psbts_dir()?;
+        let (psbt_file, qr_files, ur_files) = save_psbt(
+            &mut psbt,
+            &mut psbts_dir,
+            opt.qr_version,
+            opt.qr_format,
+            self.context.encryption_key.as_ref(),
+        )?;
+
+        Ok(CreateTxOutput {
+            funded_psbt: (&psbt).try_into()?,
+            psbt_file,
+            address_reused: HashSet::new(),
+            qr_files,
+            ur_files,
+        })
+    }
+
+    /// Verify the proof of reserves against the UTXO set of the node
+    pub fn verify_reserves(&self, opt: &VerifyReservesOptions) -> Result<ReservesOutput> {
+        let psbt = read_psbt(&opt.psbt_file, self.context.encryption_key.as_ref())?;
+        verify_reserves(&psbt, &opt.message, |outpoint| {
+            let result = self
+                .client
+                .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?;
+            Ok(result.map(|r| TxOut {
+                value: r.value.as_sat(),
+                script_pubkey: Script::from(r.script_pub_key.hex),
+            }))
+        })
+    }
+}
@@ -1,8 +1,9 @@
 use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use structopt::StructOpt;
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Serialize, Deserialize)]
 pub struct RescanOptions {
     /// Specify the block height from which doing a blockchain rescan (use 0 to start from the beginning)
     #[structopt(long)]
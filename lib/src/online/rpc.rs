@@ -0,0 +1,185 @@
+use crate::*;
+use bitcoincore_rpc::jsonrpc;
+use bitcoincore_rpc::{Auth, RpcApi};
+use log::debug;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// JSON-RPC client of the bitcoin node over plain HTTP, unlike `bitcoincore_rpc::Client` the
+/// calls could time out
+pub struct NodeClient {
+    host: String,
+    path: String,
+    authorization: Option<String>,
+    timeout: Option<Duration>,
+    nonce: AtomicUsize,
+}
+
+impl NodeClient {
+    pub fn new(url: &str, auth: Auth, timeout: Option<Duration>) -> Result<Self> {
+        let without_scheme = url
+            .strip_prefix("http://")
+            .ok_or_else(|| Error::Generic(format!("node url {} must start with http://", url)))?;
+        let (host, path) = match without_scheme.find('/') {
+            Some(i) => without_scheme.split_at(i),
+            None => (without_scheme, "/"),
+        };
+        let credentials = match auth {
+            Auth::None => None,
+            Auth::UserPass(user, password) => Some(format!("{}:{}", user, password)),
+            Auth::CookieFile(path) => Some(fs::read_to_string(path)?.trim().to_string()),
+        };
+        Ok(NodeClient {
+            host: host.to_string(),
+            path: path.to_string(),
+            authorization: credentials.map(|c| format!("Basic {}", base64::encode(c))),
+            timeout,
+            nonce: AtomicUsize::new(0),
+        })
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "node host not resolved");
+        for addr in self.host.to_socket_addrs()? {
+            let result = match self.timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// POST `body` returning the HTTP status and the response body
+    fn post(&self, body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        if let Some(authorization) = self.authorization.as_ref() {
+            request.push_str(&format!("Authorization: {}\r\n", authorization));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid node http response");
+        let end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or_else(invalid)?;
+        let head = std::str::from_utf8(&response[..end]).map_err(|_| invalid())?;
+        let status = head
+            .split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(invalid)?;
+        let mut body = response[end + 4..].to_vec();
+        let content_length = head.lines().find_map(|line| {
+            let (name, value) = line.split_at(line.find(':')?);
+            if name.eq_ignore_ascii_case("content-length") {
+                value[1..].trim().parse::<usize>().ok()
+            } else {
+                None
+            }
+        });
+        if let Some(len) = content_length {
+            body.truncate(len);
+        }
+        Ok((status, body))
+    }
+}
+
+impl RpcApi for NodeClient {
+    fn call<T: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        cmd: &str,
+        args: &[Value],
+    ) -> bitcoincore_rpc::Result<T> {
+        let id = self.nonce.fetch_add(1, Ordering::Relaxed);
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": cmd, "params": args});
+        debug!("node request {}", cmd);
+        let (status, body) = self.post(&serde_json::to_vec(&request)?)?;
+        let response: jsonrpc::Response = serde_json::from_slice(&body)
+            .map_err(|_| io::Error::other(format!("node replied with http status {}", status)))?;
+        Ok(response.into_result()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::online::rpc::NodeClient;
+    use bitcoincore_rpc::{Auth, RpcApi};
+    use serde_json::Value;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_node_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/wallet/w", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = vec![];
+            for body in &[r#"{"result":42,"error":null,"id":0}"#, ""] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with('}') {
+                    let len = stream.read(&mut buffer).unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buffer[..len]));
+                }
+                requests.push(request);
+                let status = if body.is_empty() {
+                    "401 Unauthorized"
+                } else {
+                    "200 OK"
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            // the last connection never gets a response
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(2));
+            drop(stream);
+            requests
+        });
+
+        let auth = Auth::UserPass("user".to_string(), "pass".to_string());
+        let timeout = Some(Duration::from_millis(500));
+        let client = NodeClient::new(&url, auth, timeout).unwrap();
+        let result: u64 = client.call("getblockcount", &[]).unwrap();
+        assert_eq!(result, 42);
+        let error = client.call::<Value>("getblockcount", &[]).unwrap_err();
+        assert!(error.to_string().contains("401"), "{}", error);
+        assert!(client.call::<Value>("getblockcount", &[]).is_err());
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].starts_with("POST /wallet/w HTTP/1.1"));
+        assert!(requests[0].contains("Authorization: Basic dXNlcjpwYXNz"));
+        assert!(requests[0].contains(r#""method":"getblockcount""#));
+
+        assert!(NodeClient::new("https://host", Auth::None, None).is_err());
+    }
+}
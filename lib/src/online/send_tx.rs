@@ -3,20 +3,24 @@ use bitcoin::consensus::deserialize;
 use bitcoin::Transaction;
 use bitcoincore_rpc::RpcApi;
 use log::{debug, info};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
-#[derive(structopt::StructOpt, Debug)]
+#[derive(structopt::StructOpt, Debug, Serialize, Deserialize)]
 pub struct SendTxOptions {
-    /// filename containing the PSBTs
+    /// filename containing the PSBTs, as json, BIP174 binary, base64 or hex
     #[structopt(long = "psbt-file")]
+    #[serde(default)]
     pub psbts_file: Vec<PathBuf>,
 
-    /// the PSBTs content as base64
+    /// the PSBTs content as base64 or hex
     #[structopt(long = "psbt")]
+    #[serde(default)]
     pub psbts: Vec<String>,
 
     /// broadcast transaction through the node, by default it is not broadcasted
     #[structopt(long)]
+    #[serde(default)]
     pub broadcast: bool,
 }
 
@@ -34,10 +38,12 @@ impl Wallet {
         opt.validate()?;
         let mut psbts = vec![];
         for psbt_file in opt.psbts_file.iter() {
-            let json = read_psbt_json(psbt_file)?;
-            psbts.push(json.psbt);
+            let psbt = read_psbt(psbt_file, self.context.encryption_key.as_ref())?;
+            psbts.push(psbt_to_base64(&psbt).1);
+        }
+        for psbt in opt.psbts.iter() {
+            psbts.push(psbt_to_base64(&psbt_from_str(psbt)?).1);
         }
-        psbts.extend(opt.psbts.clone());
 
         let combined = self.client.combine_psbt(&psbts)?;
         debug!("combined {:?}", combined);
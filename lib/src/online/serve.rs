@@ -0,0 +1,462 @@
+use crate::common::config::{Config, NetworkConfig};
+use crate::common::exchange::check_name;
+use crate::offline::reserves::VerifyReservesOptions;
+use crate::online::create_tx::CreateTxOptions;
+use crate::online::create_wallet::CreateWalletOptions;
+use crate::online::get_address::GetAddressOptions;
+use crate::online::proof_of_reserves::ProofOfReservesOptions;
+use crate::online::rescan::RescanOptions;
+use crate::online::send_tx::SendTxOptions;
+use crate::online::Wallet;
+use crate::*;
+use log::{info, warn};
+use rand::{thread_rng, Rng};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+pub const COOKIE_FILE: &str = "serve.cookie";
+const COOKIE_USER: &str = "__cookie__";
+const MAX_BODY_LEN: usize = 1_000_000;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serve the online commands as JSON-RPC over HTTP, the credentials for basic auth are written in
+/// the `serve.cookie` file in the network directory
+#[derive(StructOpt, Debug)]
+pub struct ServeOptions {
+    /// Address to listen to, for example 127.0.0.1:18999
+    #[structopt(long)]
+    pub listen: SocketAddr,
+
+    /// Bitcoin node rpc password for wallets using an rpc user
+    /// in CLI it is populated from standard input or environment
+    #[structopt(skip)]
+    pub rpc_password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+    /// The same error as returned by the CLI, with the firma error code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl From<Error> for RpcError {
+    fn from(e: Error) -> Self {
+        let code = match e {
+            Error::UnknownMethod(_) => -32601,
+            Error::Serde(_) => -32602,
+            _ => -32000,
+        };
+        RpcError {
+            code,
+            message: e.to_string(),
+            data: Some(e.to_json()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WalletParam {
+    wallet_name: String,
+}
+
+pub struct Server {
+    /// Context shared by every wallet, `wallet_name` is set per request
+    context: Context,
+    /// Defaults of the network from the datadir config
+    config: NetworkConfig,
+    /// Bitcoin node rpc password, used if not saved with the wallet
+    rpc_password: Option<String>,
+    authorization: String,
+    /// Opened wallets, the lock serializes the calls on the same wallet and so the writes of
+    /// its `indexes.json`
+    wallets: Mutex<HashMap<String, Arc<Mutex<Wallet>>>>,
+}
+
+pub fn serve(context: Context, opt: &ServeOptions) -> Result<()> {
+    let (mut server, cookie_file) = Server::new(context)?;
+    server.rpc_password = opt.rpc_password.clone();
+    let listener = TcpListener::bind(opt.listen)?;
+    info!("Listening on {}, cookie in {:?}", opt.listen, cookie_file);
+    Arc::new(server).run(listener);
+    Ok(())
+}
+
+impl Server {
+    /// Create the server writing a new random cookie file, returned with the server
+    pub fn new(context: Context) -> Result<(Self, PathBuf)> {
+        let mut cookie_file =
+            PathBuilder::new(&context.firma_datadir, context.network, Kind::Wallet, None)
+                .type_path()?;
+        cookie_file.pop();
+        cookie_file.push(COOKIE_FILE);
+        let password: [u8; 32] = thread_rng().gen();
+        let cookie = format!("{}:{}", COOKIE_USER, hex::encode(password));
+        write_owner_only(&cookie_file, cookie.as_bytes())?;
+
+        let config = Config::load(&context.firma_datadir)?
+            .for_network(context.network)
+            .clone();
+        let server = Server {
+            context,
+            config,
+            rpc_password: None,
+            authorization: format!("Basic {}", base64::encode(&cookie)),
+            wallets: Mutex::new(HashMap::new()),
+        };
+        Ok((server, cookie_file))
+    }
+
+    /// Accept connections handling each one in its own thread, never returns
+    pub fn run(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = self.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = server.handle_connection(stream) {
+                            warn!("connection error {:?}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("accept error {:?}", e),
+            }
+        }
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        let mut content_length = 0usize;
+        let mut authorized = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_lowercase();
+            let value = parts.next().unwrap_or("").trim();
+            match name.as_str() {
+                "content-length" => content_length = value.parse().unwrap_or(MAX_BODY_LEN + 1),
+                "authorization" => authorized = constant_time_eq(value, &self.authorization),
+                _ => (),
+            }
+        }
+
+        let (status, body) = if !request_line.starts_with("POST ") {
+            ("405 Method Not Allowed", vec![])
+        } else if !authorized {
+            ("401 Unauthorized", vec![])
+        } else if content_length > MAX_BODY_LEN {
+            ("413 Payload Too Large", vec![])
+        } else {
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body)?;
+            ("200 OK", serde_json::to_vec(&self.handle_body(&body))?)
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status,
+            body.len()
+        )?;
+        if status.starts_with("401") {
+            write!(stream, "WWW-Authenticate: Basic realm=\"firma\"\r\n")?;
+        }
+        write!(stream, "\r\n")?;
+        stream.write_all(&body)?;
+        Ok(())
+    }
+
+    /// JSON-RPC 2.0 response to the request in `body`
+    fn handle_body(&self, body: &[u8]) -> Value {
+        let request: RpcRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = RpcError {
+                    code: -32700,
+                    message: e.to_string(),
+                    data: None,
+                };
+                return json!({"jsonrpc": "2.0", "id": Value::Null, "error": error});
+            }
+        };
+        info!("method:{} params:{:?}", request.method, request.params);
+        match self.call(&request.method, request.params) {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": request.id, "result": result}),
+            Err(e) => json!({"jsonrpc": "2.0", "id": request.id, "error": RpcError::from(e)}),
+        }
+    }
+
+    fn call(&self, method: &str, mut params: Value) -> Result<Value> {
+        self.config.apply_to_args(&mut params, |_| true);
+        if let (Value::Object(map), Some(name)) = (&mut params, &self.config.wallet_name) {
+            map.entry("wallet_name")
+                .or_insert_with(|| name.clone().into());
+        }
+        let datadir = &self.context.firma_datadir;
+        let network = self.context.network;
+        let mut value = match method {
+            "export" => {
+                let mut opt: common::exchange::ExportOptions = parse(params)?;
+                opt.encryption_key = self.context.encryption_key.clone();
+                return Ok(serde_json::to_value(common::exchange::export_journaled(
+                    datadir, network, &opt,
+                )?)?);
+            }
+            "import" => {
+                let mut opt: common::exchange::ImportOptions = parse(params)?;
+                opt.encryption_key = self.context.encryption_key.clone();
+                return Ok(serde_json::to_value(common::exchange::import(
+                    datadir, network, &opt,
+                )?)?);
+            }
+            "create_wallet" => return self.create_wallet(params),
+            "rescan" | "get_address" | "create_tx" | "send_tx" | "balance" | "list_coins"
+            | "proof_of_reserves" | "verify_reserves" => params,
+            _ => return Err(Error::UnknownMethod(method.to_string())),
+        };
+
+        let name = wallet_name(&value)?;
+        let wallet = self.wallet(&name)?;
+        let wallet = wallet
+            .lock()
+            .map_err(|_| Error::Generic("poisoned lock".into()))?;
+        if let Value::Object(map) = &mut value {
+            map.remove("wallet_name");
+        }
+        let result = match method {
+            "rescan" => wallet.rescan(&parse::<RescanOptions>(value)?)?,
+            "get_address" => {
+                serde_json::to_value(wallet.get_address(&parse::<GetAddressOptions>(value)?)?)?
+            }
+            "create_tx" => {
+                serde_json::to_value(wallet.create_tx(&parse::<CreateTxOptions>(value)?)?)?
+            }
+            "send_tx" => serde_json::to_value(wallet.send_tx(&parse::<SendTxOptions>(value)?)?)?,
+            "balance" => serde_json::to_value(wallet.balance()?)?,
+            "proof_of_reserves" => {
+                let opt: ProofOfReservesOptions = parse(value)?;
+                serde_json::to_value(wallet.proof_of_reserves(&opt)?)?
+            }
+            "verify_reserves" => {
+                let mut opt: VerifyReservesOptions = parse(value)?;
+                opt.encryption_key = self.context.encryption_key.clone();
+                serde_json::to_value(wallet.verify_reserves(&opt)?)?
+            }
+            _ => serde_json::to_value(wallet.list_coins()?)?,
+        };
+        Ok(result)
+    }
+
+    fn with_rpc_password(&self, mut daemon_opts: DaemonOpts) -> DaemonOpts {
+        if daemon_opts.rpc_password.is_none() {
+            daemon_opts.rpc_password = self.rpc_password.clone();
+        }
+        daemon_opts
+    }
+
+    fn context_for(&self, wallet_name: &str) -> Context {
+        Context {
+            wallet_name: wallet_name.to_string(),
+            ..self.context.clone()
+        }
+    }
+
+    fn wallets(&self) -> Result<MutexGuard<'_, HashMap<String, Arc<Mutex<Wallet>>>>> {
+        self.wallets
+            .lock()
+            .map_err(|_| Error::Generic("poisoned lock".into()))
+    }
+
+    /// The cached wallet `name`, opened and checked against the node if not yet cached. The
+    /// wallets aren't locked while talking to the node, so a slow node doesn't block the others
+    fn wallet(&self, name: &str) -> Result<Arc<Mutex<Wallet>>> {
+        if let Some(wallet) = self.wallets()?.get(name) {
+            return Ok(wallet.clone());
+        }
+        let context = self.context_for(name);
+        let daemon_opts = match self.config.daemon_opts() {
+            Some(daemon_opts) => daemon_opts,
+            None => context.load_wallet_index_daemon()?.2,
+        };
+        let daemon_opts = self.with_rpc_password(daemon_opts);
+        let wallet = Wallet::open(&daemon_opts, context)?;
+        wallet.load_if_unloaded(name)?;
+        wallet.check_network()?;
+        // if opened concurrently by another request, the first one inserted is kept
+        let wallet = self
+            .wallets()?
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(wallet)))
+            .clone();
+        Ok(wallet)
+    }
+
+    fn create_wallet(&self, params: Value) -> Result<Value> {
+        let name = wallet_name(&params)?;
+        let mut opt: CreateWalletOptions = parse(params)?;
+        opt.daemon_opts = self.with_rpc_password(opt.daemon_opts);
+        let wallet = Wallet::open(&opt.daemon_opts, self.context_for(&name))?;
+        let height = wallet.check_network()?;
+        let result = wallet.create(&opt.daemon_opts, &opt, height)?;
+        self.wallets()?.insert(name, Arc::new(Mutex::new(wallet)));
+        Ok(serde_json::to_value(result)?)
+    }
+}
+
+/// The `wallet_name` in `params`, which becomes a directory in the datadir
+fn wallet_name(params: &Value) -> Result<String> {
+    let name = parse::<WalletParam>(params.clone())?.wallet_name;
+    check_name(&name)?;
+    Ok(name)
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T> {
+    Ok(serde_json::from_value(params)?)
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Write `content` in a new file readable only by the owner, a previous file is removed because
+/// the mode applies only at creation
+fn write_owner_only(path: &std::path::Path, content: &[u8]) -> Result<()> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::journal;
+    use crate::online::serve::*;
+    use bitcoin::Network;
+    use tempfile::TempDir;
+
+    fn post(addr: SocketAddr, authorization: Option<&str>, body: &str) -> (String, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n",
+            body.len()
+        )
+        .unwrap();
+        if let Some(authorization) = authorization {
+            write!(stream, "Authorization: {}\r\n", authorization).unwrap();
+        }
+        write!(stream, "\r\n{}", body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let mut parts = response.splitn(2, "\r\n\r\n");
+        let head = parts.next().unwrap().to_string();
+        let body = parts.next().unwrap_or("");
+        (head, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn test_serve() {
+        let temp_dir = TempDir::new().unwrap();
+        let context = Context {
+            network: Network::Testnet,
+            wallet_name: "".to_string(),
+            firma_datadir: temp_dir.path().to_str().unwrap().to_string(),
+            encryption_key: None,
+        };
+        let (server, cookie_file) = Server::new(context).unwrap();
+        let cookie = fs::read_to_string(&cookie_file).unwrap();
+        assert!(cookie.starts_with("__cookie__:"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&cookie_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let authorization = format!("Basic {}", base64::encode(&cookie));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || Arc::new(server).run(listener));
+
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"balance","params":{"wallet_name":"w"}}"#;
+        let (head, _) = post(addr, None, body);
+        assert!(head.starts_with("HTTP/1.1 401"));
+        let (head, _) = post(addr, Some("Basic d3Jvbmc="), body);
+        assert!(head.starts_with("HTTP/1.1 401"));
+
+        let (head, value) = post(addr, Some(&authorization), body);
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert_eq!(value["id"], 1);
+        assert_eq!(value["error"]["code"], -32000); // wallet doesn't exist
+
+        // wallet names must not escape the datadir
+        let body = json!({"jsonrpc": "2.0", "id": 3, "method": "create_wallet",
+            "params": {"wallet_name": "../../x"}});
+        let (_, value) = post(addr, Some(&authorization), &body.to_string());
+        assert_eq!(
+            value["error"]["data"]["code"], "exchange_invalid_name",
+            "{}",
+            value
+        );
+
+        let body = r#"{"jsonrpc":"2.0","id":"a","method":"unknown"}"#;
+        let (_, value) = post(addr, Some(&authorization), body);
+        assert_eq!(value["id"], "a");
+        assert_eq!(value["error"]["code"], -32601);
+
+        let (_, value) = post(addr, Some(&authorization), "{");
+        assert_eq!(value["error"]["code"], -32700);
+
+        let to = temp_dir.path().join("exchange");
+        let body = json!({"jsonrpc": "2.0", "id": 2, "method": "export", "params": {"to": to}});
+        let (_, value) = post(addr, Some(&authorization), &body.to_string());
+        assert_eq!(value["result"]["items"], json!([]), "{}", value);
+        let datadir = temp_dir.path().to_str().unwrap();
+        let show = journal::show(datadir, Network::Testnet, &Default::default(), None).unwrap();
+        assert_eq!(show.entries[0].record.event, journal::JournalEvent::Export);
+    }
+}
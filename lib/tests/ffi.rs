@@ -0,0 +1,147 @@
+//! Load the firma cdylib with `dlopen` and use it only through the C ABI, like other apps do
+#![cfg(target_os = "linux")]
+
+use firma::bitcoin::consensus::deserialize;
+use serde_json::{json, Value};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::PathBuf;
+use std::process::Command;
+
+const RTLD_NOW: c_int = 2;
+
+const TX_IN: &str = "020000000001019e60071916a88cf0f5b9c6f015b7f8eef3ab1ef6ca4929b7236ec74e693f36210000000023220020c3af1472a85b23206da9be4fbef18d0ce5fd965671110d722a816e892d2e5f33fdffffff02801a0600000000002200201148e93e9315e37dbed2121be5239257af35adc03ffdfc5d914b083afa44dab80e07a1010000000017a9142aaba9f43085c5a6f28b0d01a8ed4dbcc0e5ec4f87040047304402203fdaeafde5fc1d1838d4c431abf6672f4cfee996f932187b31a4e3dad04d7b9f0220247d2cee5aabceb029ee6a1809a821fd95aa3ff02627977cbac8d00ff5a4628901473044022026879e4c65462161e2805ca26d392b0aace13906ec5b4776cac99f5e2bfd49f4022072500f1e2818a6738c37b6cedb2fd0a16375df34ce145e3f8fbfef7b7bec99d401475221020ca0e815748c41087075f3840c1edd9400f4db031dbe948b1929b6a93c72386a21026471f666489f80aed63bbbdee4f09ffcd69b40900435633cef5f5a35bf00932752ae4ff21700";
+
+#[link(name = "dl")]
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+type FirmaCall = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type FirmaFree = unsafe extern "C" fn(*mut c_char);
+
+struct Firma {
+    call: FirmaCall,
+    free: FirmaFree,
+    datadir: String,
+}
+
+impl Firma {
+    fn load(datadir: &str) -> Self {
+        // tests executables are in target/<profile>/deps, the cdylib in target/<profile>
+        let mut path: PathBuf = std::env::current_exe().unwrap();
+        path.pop();
+        path.pop();
+        // `cargo test` doesn't build the cdylib, build it in the same target dir and profile
+        let profile = path.file_name().unwrap().to_os_string();
+        let target_dir = path.parent().unwrap();
+        let mut build = Command::new(env!("CARGO"));
+        build.args(["build", "-p", "firma", "--lib", "--target-dir"]);
+        build.arg(target_dir);
+        if profile == "release" {
+            build.arg("--release");
+        }
+        assert!(
+            build.status().unwrap().success(),
+            "building libfirma.so failed"
+        );
+        path.push("libfirma.so");
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let handle = dlopen(path.as_ptr(), RTLD_NOW);
+            assert!(!handle.is_null(), "{:?}", CStr::from_ptr(dlerror()));
+            let call = dlsym(handle, b"firma_call\0".as_ptr() as *const c_char);
+            let free = dlsym(handle, b"firma_free\0".as_ptr() as *const c_char);
+            assert!(!call.is_null() && !free.is_null());
+            Firma {
+                call: std::mem::transmute::<*mut c_void, FirmaCall>(call),
+                free: std::mem::transmute::<*mut c_void, FirmaFree>(free),
+                datadir: datadir.to_string(),
+            }
+        }
+    }
+
+    fn call(&self, method: &str, args: Value) -> Value {
+        let request = json!({
+            "datadir": self.datadir,
+            "network": "testnet",
+            "method": method,
+            "args": args,
+        });
+        let request = CString::new(request.to_string()).unwrap();
+        unsafe {
+            let result = (self.call)(request.as_ptr());
+            let value = serde_json::from_slice(CStr::from_ptr(result).to_bytes()).unwrap();
+            (self.free)(result);
+            value
+        }
+    }
+}
+
+#[test]
+fn test_c_abi() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let firma = Firma::load(temp_dir.path().to_str().unwrap());
+
+    let methods = firma.call("methods", Value::Null);
+    assert_eq!(methods["version"], 1);
+
+    let random = firma.call("random", json!({"key_name": "random"}));
+    assert!(random["key"]["xpub"].is_string(), "{}", random);
+
+    let key: Value =
+        serde_json::from_slice(include_bytes!("../test_data/sign/psbt_testnet.1.key")).unwrap();
+    let restored = firma.call(
+        "restore",
+        json!({"key_name": "k", "nature": "Xprv", "value": key["xprv"], "qr_version": 14}),
+    );
+    let key_file = restored["private_file"].clone();
+    assert!(key_file.is_string(), "{}", restored);
+
+    let wallet = json!({
+        "name": "w",
+        "descriptor": format!("wsh(multi(1,{}/0/*))", restored["key"]["xpub"].as_str().unwrap()),
+        "fingerprints": [restored["key"]["fingerprint"]],
+        "required_sig": 1,
+        "created_at_height": 0,
+    });
+    let wallet_file = firma.call("import_wallet", wallet);
+    assert!(wallet_file.is_string(), "{}", wallet_file);
+
+    // the signer requires the previous tx also for segwit inputs
+    let psbt: Value =
+        serde_json::from_slice(include_bytes!("../test_data/sign/psbt_testnet.json")).unwrap();
+    let (_, mut psbt) = firma::psbt_from_base64(psbt["psbt"].as_str().unwrap()).unwrap();
+    psbt.inputs[0].non_witness_utxo = Some(deserialize(&hex::decode(TX_IN).unwrap()).unwrap());
+    let psbt = firma::psbt_to_base64(&psbt).1;
+    let psbt_file = firma.call(
+        "save_psbt",
+        json!({"psbt": {"t": "base64", "c": psbt}, "qr_version": 14}),
+    );
+    assert!(psbt_file.is_string(), "{}", psbt_file);
+
+    let signed = firma.call(
+        "sign",
+        json!({
+            "key": key_file,
+            "total_derivations": 10,
+            "wallet_descriptor_file": wallet_file,
+            "qr_version": 14,
+            "psbt_file": psbt_file,
+            "allow_any_derivations": true,
+        }),
+    );
+    let info = signed["info"].as_array().unwrap();
+    assert!(info.contains(&json!("Added signatures")), "{}", signed);
+
+    let printed = firma.call(
+        "print",
+        json!({"psbt_file": psbt_file, "verify_wallets_signatures": false}),
+    );
+    assert_eq!(printed["fee"], signed["fee"], "{}", printed);
+
+    let error = firma.call("unknown", Value::Null);
+    assert!(error["error"].is_string(), "{}", error);
+}